@@ -0,0 +1,646 @@
+use crate::bus::Bus;
+use crate::cpu::CpuVariant;
+use crate::opcodes::{self, Instruction, Mode, Opcode};
+use std::collections::HashMap;
+use std::fmt;
+
+//two pass 6502 (and 65c02) assembler for tests and debugger patches.
+//
+//  label:  lda #<table     ; comments after a semicolon
+//          sta $00,x
+//          bne label
+//  value = $40 + 2         ; constants
+//          .org $c000
+//          .byte 1, 2, "text"
+//          .word label, *+3
+//
+//numbers are decimal, $hex, 0xhex, %binary or 'c'. expressions take labels, * (the
+//current address), + - * / & | ^ and the unary - ~ < (low byte) > (high byte).
+//parentheses are only used for the indirect modes, a: forces absolute addressing
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize, //1 based
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub struct Program {
+    pub segments: Vec<(u16, Vec<u8>)>, //one per .org, in source order
+    pub labels: HashMap<String, u16>,
+}
+
+impl Program {
+    //all segments back to back, handy when there is a single one
+    pub fn bytes(&self) -> Vec<u8> {
+        self.segments.iter().flat_map(|(_, bytes)| bytes.iter().copied()).collect()
+    }
+
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    pub fn write_to<B: Bus>(&self, bus: &mut B) {
+        for (origin, bytes) in &self.segments {
+            for (i, value) in bytes.iter().enumerate() {
+                bus.write(origin.wrapping_add(i as u16), *value);
+            }
+        }
+    }
+}
+
+//asm!(origin, "line", "line", ...) assembles or panics, for tests
+#[macro_export]
+macro_rules! asm {
+    ($origin:expr, $($line:expr),+ $(,)?) => {
+        match $crate::asm::assemble(&[$($line),+].join("\n"), $origin) {
+            Ok(program) => program,
+            Err(e) => panic!("asm!: {}", e),
+        }
+    };
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operand: String },
+    Org(String),
+    Byte(Vec<String>),
+    Word(Vec<String>),
+    Constant { name: String, value: String },
+}
+
+struct SourceLine {
+    number: usize,
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+fn error<T>(line: usize, message: String) -> Result<T, AsmError> {
+    Err(AsmError { line, message })
+}
+
+pub fn assemble(source: &str, origin: u16) -> Result<Program, AsmError> {
+    assemble_for(source, origin, CpuVariant::Ricoh2A03)
+}
+
+//same, with the instruction set of a cpu variant (the 65c02 ones need Cmos65C02)
+pub fn assemble_for(source: &str, origin: u16, variant: CpuVariant) -> Result<Program, AsmError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| parse_line(i + 1, text))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    //the sizing pass finds the labels. an operand that isn't known yet is assumed
+    //to be absolute, the choice is remembered so the encode pass emits the same sizes
+    let mut asm = Assembler {
+        table: opcodes::table(variant),
+        labels: HashMap::new(),
+        wide: HashMap::new(),
+    };
+    let addresses = asm.size(&lines, origin)?;
+
+    //constants that use a label from further down
+    for (line, &pc) in lines.iter().zip(&addresses) {
+        if let Some(Statement::Constant { name, value }) = &line.statement {
+            if !asm.labels.contains_key(name) {
+                let value = eval(value, pc, &asm.labels, line.number, true)?.unwrap_or(0);
+                asm.labels.insert(name.clone(), value as u16);
+            }
+        }
+    }
+
+    let mut segments = Vec::new();
+    let mut current: Option<(u16, Vec<u8>)> = None;
+    for (line, &pc) in lines.iter().zip(&addresses) {
+        if let Some(Statement::Org(_)) = &line.statement {
+            segments.extend(current.take());
+            continue;
+        }
+        let bytes = asm.encode(line, pc, true)?;
+        if !bytes.is_empty() {
+            current.get_or_insert_with(|| (pc, Vec::new())).1.extend(&bytes);
+        }
+    }
+    segments.extend(current);
+    Ok(Program {
+        segments,
+        labels: asm.labels,
+    })
+}
+
+struct Assembler {
+    table: &'static [Opcode; 256],
+    labels: HashMap<String, u16>,
+    wide: HashMap<usize, bool>, //line number -> absolute operand, set by the sizing pass
+}
+
+//what select makes of an instruction line
+struct Selected {
+    instruction: Instruction,
+    mode: Mode,
+    value: Option<i64>,  //None while a label is still unknown in the sizing pass
+    target: Option<i64>, //branch target of bbr/bbs, whose value is the zero page address
+}
+
+fn parse_line(number: usize, text: &str) -> Result<SourceLine, AsmError> {
+    let mut text = strip_comment(text).trim();
+    let mut label = None;
+    //a label is a name followed by a colon, outside of any string
+    if let Some(colon) = text.find(':') {
+        let name = text[..colon].trim();
+        if is_name(name) && !name.eq_ignore_ascii_case("a") {
+            label = Some(name.to_ascii_lowercase());
+            text = text[colon + 1..].trim();
+        }
+    }
+    if text.is_empty() {
+        return Ok(SourceLine {
+            number,
+            label,
+            statement: None,
+        });
+    }
+
+    if let Some((name, value)) = text.split_once('=').filter(|(name, _)| is_name(name.trim())) {
+        let name = name.trim();
+        return Ok(SourceLine {
+            number,
+            label,
+            statement: Some(Statement::Constant {
+                name: name.to_ascii_lowercase(),
+                value: value.trim().to_string(),
+            }),
+        });
+    }
+
+    let (head, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    };
+    let statement = match head.to_ascii_lowercase().as_str() {
+        ".org" => Statement::Org(rest.to_string()),
+        ".byte" | ".db" => Statement::Byte(split_list(rest)),
+        ".word" | ".dw" => Statement::Word(split_list(rest)),
+        d if d.starts_with('.') => return error(number, format!("unknown directive {}", head)),
+        m => Statement::Instruction {
+            mnemonic: m.to_string(),
+            operand: rest.replace(char::is_whitespace, ""),
+        },
+    };
+    Ok(SourceLine {
+        number,
+        label,
+        statement: Some(statement),
+    })
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => (),
+        }
+    }
+    text
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '@' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@')
+}
+
+//comma separated .byte/.word arguments, commas inside strings don't count
+fn split_list(text: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => items.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    items.push(current.trim().to_string());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+impl Assembler {
+    //defines the labels and picks the operand widths, returns the address of every line
+    fn size(&mut self, lines: &[SourceLine], origin: u16) -> Result<Vec<u16>, AsmError> {
+        let mut pc = origin;
+        let mut addresses = Vec::with_capacity(lines.len());
+        for line in lines {
+            let n = line.number;
+            if let Some(label) = &line.label {
+                if self.labels.insert(label.clone(), pc).is_some() {
+                    return error(n, format!("label {} defined twice", label));
+                }
+            }
+            addresses.push(pc);
+            match &line.statement {
+                Some(Statement::Org(expr)) => match eval(expr, pc, &self.labels, n, false)? {
+                    Some(value) => pc = value as u16,
+                    None => return error(n, ".org needs a value known at that point".to_string()),
+                },
+                Some(Statement::Constant { name, value }) => {
+                    if self.labels.contains_key(name) {
+                        return error(n, format!("{} defined twice", name));
+                    }
+                    if let Some(value) = eval(value, pc, &self.labels, n, false)? {
+                        self.labels.insert(name.clone(), value as u16);
+                    }
+                }
+                Some(Statement::Instruction { mnemonic, operand }) => {
+                    let mode = self.select(mnemonic, operand, pc, n, false)?.mode;
+                    self.wide.insert(n, matches!(mode, Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY));
+                    pc = pc.wrapping_add(mode.size() as u16);
+                }
+                Some(_) => pc = pc.wrapping_add(self.encode(line, pc, false)?.len() as u16),
+                None => (),
+            }
+        }
+        Ok(addresses)
+    }
+
+    //ca65 spellings of a few undocumented opcodes
+    fn instruction_named(&self, name: &str) -> Option<Instruction> {
+        let name = match name {
+            "jam" => "kil",
+            "ane" => "xaa",
+            "isb" => "isc",
+            "sbx" => "axs",
+            "asr" => "alr",
+            n => n,
+        };
+        self.table
+            .iter()
+            .map(|op| op.instruction)
+            .find(|i| i.name().eq_ignore_ascii_case(name))
+    }
+
+    //opcode for an instruction in a mode, official encodings first
+    fn find_opcode(&self, instruction: Instruction, mode: Mode) -> Option<u8> {
+        let matching = |official: bool| {
+            (0..=255u8).find(|op| {
+                let info = self.table[*op as usize];
+                info.instruction == instruction && info.mode == mode && info.official == official
+            })
+        };
+        matching(true).or_else(|| matching(false))
+    }
+
+    //picks the addressing mode for an operand. the width chosen by the sizing
+    //pass is kept once there is one
+    fn select(&self, mnemonic: &str, operand: &str, pc: u16, n: usize, strict: bool) -> Result<Selected, AsmError> {
+        let instruction = match self.instruction_named(mnemonic) {
+            //ca65 writes lxa as lax #imm
+            Some(Instruction::Lax) if operand.starts_with('#') => Instruction::Lxa,
+            Some(i) => i,
+            None => return error(n, format!("unknown instruction {}", mnemonic)),
+        };
+        let has = |mode| self.find_opcode(instruction, mode).is_some();
+        let lower = operand.to_ascii_lowercase();
+        let value = |expr: &str| eval(expr, pc, &self.labels, n, strict);
+        let mut target = None;
+
+        let (value, mode) = if operand.is_empty() {
+            (None, if has(Mode::Implied) { Mode::Implied } else { Mode::Accumulator })
+        } else if lower == "a" && has(Mode::Accumulator) {
+            (None, Mode::Accumulator)
+        } else if let Some(expr) = operand.strip_prefix('#') {
+            (value(expr)?, Mode::Immediate)
+        } else if lower.starts_with('(') && lower.ends_with(",x)") {
+            let mode = if has(Mode::AbsoluteIndirectX) {
+                Mode::AbsoluteIndirectX
+            } else {
+                Mode::IndirectX
+            };
+            (value(&operand[1..operand.len() - 3])?, mode)
+        } else if lower.starts_with('(') && lower.ends_with("),y") {
+            (value(&operand[1..operand.len() - 3])?, Mode::IndirectY)
+        } else if lower.starts_with('(') && lower.ends_with(')') {
+            let mode = if has(Mode::Indirect) {
+                Mode::Indirect
+            } else {
+                Mode::ZeroPageIndirect
+            };
+            (value(&operand[1..operand.len() - 1])?, mode)
+        } else if has(Mode::Relative) {
+            (value(operand)?, Mode::Relative)
+        } else if has(Mode::ZeroPageRelative) {
+            let (zero, branch) = match operand.split_once(',') {
+                Some(split) => split,
+                None => return error(n, format!("{} needs a zero page address and a target", mnemonic)),
+            };
+            target = value(branch)?;
+            (value(zero)?, Mode::ZeroPageRelative)
+        } else {
+            let (expr, index) = if lower.ends_with(",x") || lower.ends_with(",y") {
+                (&operand[..operand.len() - 2], lower.chars().last())
+            } else {
+                (operand, None)
+            };
+            let (expr, forced) = match expr.strip_prefix("a:").or_else(|| expr.strip_prefix("A:")) {
+                Some(expr) => (expr, true),
+                None => (expr, false),
+            };
+            let v = value(expr)?;
+            let (zero, absolute) = match index {
+                Some('x') => (Mode::ZeroPageX, Mode::AbsoluteX),
+                Some(_) => (Mode::ZeroPageY, Mode::AbsoluteY),
+                None => (Mode::ZeroPage, Mode::Absolute),
+            };
+            let is_wide = match self.wide.get(&n) {
+                Some(&wide) => wide,
+                None => forced || v.is_none_or(|v| !(0..0x100).contains(&v)),
+            };
+            let mode = if (is_wide || !has(zero)) && has(absolute) {
+                absolute
+            } else {
+                zero
+            };
+            (v, mode)
+        };
+
+        if !has(mode) {
+            return error(n, format!("{} can't be used with that addressing mode", mnemonic));
+        }
+        Ok(Selected {
+            instruction,
+            mode,
+            value,
+            target,
+        })
+    }
+
+    fn encode(&self, line: &SourceLine, pc: u16, strict: bool) -> Result<Vec<u8>, AsmError> {
+        let n = line.number;
+        let mut bytes = Vec::new();
+        match &line.statement {
+            Some(Statement::Instruction { mnemonic, operand }) => {
+                let selected = self.select(mnemonic, operand, pc, n, strict)?;
+                let mode = selected.mode;
+                match self.find_opcode(selected.instruction, mode) {
+                    Some(opcode) => bytes.push(opcode),
+                    None => return error(n, format!("{} can't be used with that addressing mode", mnemonic)),
+                }
+                let value = selected.value.unwrap_or(0);
+                match mode {
+                    Mode::Relative => bytes.push(branch_offset(value, pc.wrapping_add(2), n)?),
+                    Mode::ZeroPageRelative => {
+                        bytes.push(byte(value, n)?);
+                        let target = selected.target.unwrap_or(0);
+                        bytes.push(branch_offset(target, pc.wrapping_add(3), n)?);
+                    }
+                    _ => match mode.size() {
+                        1 => (),
+                        2 => bytes.push(byte(value, n)?),
+                        _ => bytes.extend(&word(value, n)?.to_le_bytes()),
+                    },
+                }
+            }
+            Some(Statement::Byte(items)) => {
+                for item in items {
+                    if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+                        bytes.extend(item[1..item.len() - 1].bytes());
+                    } else {
+                        let value = eval(item, pc, &self.labels, n, strict)?.unwrap_or(0);
+                        bytes.push(byte(value, n)?);
+                    }
+                }
+            }
+            Some(Statement::Word(items)) => {
+                for item in items {
+                    let value = eval(item, pc, &self.labels, n, strict)?.unwrap_or(0);
+                    bytes.extend(&word(value, n)?.to_le_bytes());
+                }
+            }
+            _ => (),
+        }
+        Ok(bytes)
+    }
+}
+
+//offset byte of a branch to target, next is the address after the instruction
+fn branch_offset(target: i64, next: u16, n: usize) -> Result<u8, AsmError> {
+    let offset = target - next as i64;
+    if !(-128..=127).contains(&offset) {
+        return error(n, format!("branch out of range ({} bytes)", offset));
+    }
+    Ok(offset as u8)
+}
+
+fn byte(value: i64, n: usize) -> Result<u8, AsmError> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => error(n, format!("value {} doesn't fit in a byte", value)),
+    }
+}
+
+fn word(value: i64, n: usize) -> Result<u16, AsmError> {
+    match value {
+        -32768..=65535 => Ok(value as u16),
+        _ => error(n, format!("value {} doesn't fit in a word", value)),
+    }
+}
+
+//None when a label isn't defined yet and strict is off
+fn eval(
+    expr: &str,
+    pc: u16,
+    labels: &HashMap<String, u16>,
+    n: usize,
+    strict: bool,
+) -> Result<Option<i64>, AsmError> {
+    let mut parser = Expr {
+        chars: expr.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+        pc,
+        labels,
+        strict,
+    };
+    let value = parser.bitwise().map_err(|message| AsmError { line: n, message })?;
+    if parser.pos != parser.chars.len() {
+        return error(n, format!("can't parse expression {}", expr));
+    }
+    Ok(value)
+}
+
+//precedence climbing: bitwise < additive < multiplicative < unary
+struct Expr<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    pc: u16,
+    labels: &'a HashMap<String, u16>,
+    strict: bool,
+}
+
+impl Expr<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[char],
+        next: fn(&mut Self) -> Result<Option<i64>, String>,
+    ) -> Result<Option<i64>, String> {
+        let mut left = next(self)?;
+        while let Some(op) = self.peek().filter(|c| ops.contains(c)) {
+            self.pos += 1;
+            let right = next(self)?;
+            left = match (left, right) {
+                (Some(l), Some(r)) => Some(match op {
+                    '&' => l & r,
+                    '|' => l | r,
+                    '^' => l ^ r,
+                    '+' => l + r,
+                    '-' => l - r,
+                    '*' => l * r,
+                    _ if r == 0 => return Err("division by zero".to_string()),
+                    _ => l / r,
+                }),
+                _ => None,
+            };
+        }
+        Ok(left)
+    }
+
+    fn bitwise(&mut self) -> Result<Option<i64>, String> {
+        self.binary(&['&', '|', '^'], Self::additive)
+    }
+
+    fn additive(&mut self) -> Result<Option<i64>, String> {
+        self.binary(&['+', '-'], Self::multiplicative)
+    }
+
+    fn multiplicative(&mut self) -> Result<Option<i64>, String> {
+        self.binary(&['*', '/'], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Option<i64>, String> {
+        let op = match self.peek() {
+            Some(c @ ('<' | '>' | '-' | '~')) => c,
+            _ => return self.atom(),
+        };
+        self.pos += 1;
+        let value = self.unary()?;
+        Ok(value.map(|v| match op {
+            '<' => v & 0xff,
+            '>' => (v >> 8) & 0xff,
+            '-' => -v,
+            _ => !v,
+        }))
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn atom(&mut self) -> Result<Option<i64>, String> {
+        let radix_number = |this: &mut Self, radix: u32| {
+            let digits = this.take_while(|c| c.is_digit(radix));
+            i64::from_str_radix(&digits, radix).map_err(|_| "bad number".to_string())
+        };
+        let value = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                self.pc as i64
+            }
+            Some('$') => {
+                self.pos += 1;
+                radix_number(self, 16)?
+            }
+            Some('%') => {
+                self.pos += 1;
+                radix_number(self, 2)?
+            }
+            Some('\'') => {
+                let c = self.chars.get(self.pos + 1).copied();
+                if c.is_none() || self.chars.get(self.pos + 2) != Some(&'\'') {
+                    return Err("bad character constant".to_string());
+                }
+                self.pos += 3;
+                c.unwrap() as i64
+            }
+            Some('0') if matches!(self.chars.get(self.pos + 1), Some('x') | Some('X')) => {
+                self.pos += 2;
+                radix_number(self, 16)?
+            }
+            Some(c) if c.is_ascii_digit() => radix_number(self, 10)?,
+            Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '@' => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@');
+                match self.labels.get(&name.to_ascii_lowercase()) {
+                    Some(value) => *value as i64,
+                    None if self.strict => return Err(format!("undefined label {}", name)),
+                    None => return Ok(None),
+                }
+            }
+            _ => return Err("expected a value".to_string()),
+        };
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_page_and_absolute_operands() {
+        let program = asm!(0xc000, "lda $10", "lda $1234,x", "lda a:$10", "jmp ($fffc)");
+        assert_eq!(program.bytes(), [0xa5, 0x10, 0xbd, 0x34, 0x12, 0xad, 0x10, 0x00, 0x6c, 0xfc, 0xff]);
+    }
+
+    #[test]
+    fn forward_labels_stay_absolute() {
+        let program = asm!(0x0000, "lda later", "later: rts");
+        assert_eq!(program.bytes(), [0xad, 0x03, 0x00, 0x60]);
+        assert_eq!(program.label("LATER"), Some(0x0003));
+    }
+
+    #[test]
+    fn constants_can_use_labels_defined_later() {
+        let program = asm!(0xc000, "lda #size", "size = end - start", "start: .byte 1, 2, 3", "end:");
+        assert_eq!(program.bytes(), [0xa9, 0x03, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn org_starts_a_segment() {
+        let program = asm!(0x8000, "nop", ".org $fffc", ".word $8000");
+        assert_eq!(program.segments, [(0x8000, vec![0xea]), (0xfffc, vec![0x00, 0x80])]);
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let far = assemble("bne far\n.org $c100\nfar: nop", 0xc000).err().unwrap();
+        assert_eq!(far.line, 1);
+        assert!(far.message.contains("out of range"));
+        let twice = assemble("x: nop\nx: nop", 0).err().unwrap();
+        assert_eq!(twice.line, 2);
+        let mode = assemble("stx $1234,x", 0).err().unwrap();
+        assert!(mode.message.contains("addressing mode"));
+    }
+}
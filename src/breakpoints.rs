@@ -0,0 +1,641 @@
+use crate::cpu::CpuState;
+use crate::nes_bus;
+use crate::utils::*;
+use std::fmt;
+
+//execute breakpoints and read/write watchpoints, checked by the cpu itself.
+//when one fires next_instruction returns CpuError::Break with the reason
+//
+//conditions are small expressions evaluated when the breakpoint is reached:
+//  a == $40 && [$0300] > 3 && frame > 100
+//names: a x y sp p pc, the flags n v d i z c (0 or 1), frame (estimated from
+//cycles, there's no ppu to count them), cycles, and for watchpoints addr and
+//value (the byte read or written). [expr] reads memory.
+//numbers are decimal, $hex or 0xhex. operators, loosest first:
+//  ||  &&  |  ^  &  == !=  < <= > >=  + -  * / %  and the unary ! - ~
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Execute,   //pc reaches the address, before the instruction runs
+    Read,      //any cpu read in the range, dummy reads included
+    Write,     //any cpu write in the range
+    ReadWrite, //either of the above
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Trigger::Execute => "exec",
+            Trigger::Read => "read",
+            Trigger::Write => "write",
+            Trigger::ReadWrite => "access",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//what the breakpoint conditions can look at
+pub struct Context<'a> {
+    pub regs: &'a CpuState,
+    pub pc: u16, //address of the instruction, regs.pc may be past it already
+    pub cycles: u64,
+    pub peek: &'a dyn Fn(u16) -> Option<u8>,
+    pub access: Option<(u16, u8)>, //address and value of the access a watchpoint saw
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Var {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+    Flag(u8),
+    Frame, //see nes_bus::frame
+    Cycles,
+    Addr,
+    Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Var(Var),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Invert(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, ctx: &Context) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Var(var) => match var {
+                Var::A => ctx.regs.a() as i64,
+                Var::X => ctx.regs.x() as i64,
+                Var::Y => ctx.regs.y() as i64,
+                Var::Sp => ctx.regs.sp() as i64,
+                Var::P => ctx.regs.p() as i64,
+                Var::Pc => ctx.pc as i64,
+                Var::Flag(bit) => ctx.regs.flag(*bit) as i64,
+                Var::Frame => nes_bus::frame(ctx.cycles) as i64,
+                Var::Cycles => ctx.cycles as i64,
+                Var::Addr => ctx.access.map_or(0, |(addr, _)| addr as i64),
+                Var::Value => ctx.access.map_or(0, |(_, value)| value as i64),
+            },
+            Expr::Memory(addr) => (ctx.peek)(addr.eval(ctx) as u16).unwrap_or(0) as i64,
+            Expr::Not(e) => (e.eval(ctx) == 0) as i64,
+            Expr::Neg(e) => e.eval(ctx).wrapping_neg(),
+            Expr::Invert(e) => !e.eval(ctx),
+            Expr::Binary(Op::Or, l, r) => (l.eval(ctx) != 0 || r.eval(ctx) != 0) as i64,
+            Expr::Binary(Op::And, l, r) => (l.eval(ctx) != 0 && r.eval(ctx) != 0) as i64,
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(ctx), r.eval(ctx));
+                match op {
+                    Op::BitOr => l | r,
+                    Op::BitXor => l ^ r,
+                    Op::BitAnd => l & r,
+                    Op::Eq => (l == r) as i64,
+                    Op::Ne => (l != r) as i64,
+                    Op::Lt => (l < r) as i64,
+                    Op::Le => (l <= r) as i64,
+                    Op::Gt => (l > r) as i64,
+                    Op::Ge => (l >= r) as i64,
+                    Op::Add => l.wrapping_add(r),
+                    Op::Sub => l.wrapping_sub(r),
+                    Op::Mul => l.wrapping_mul(r),
+                    Op::Div => l.checked_div(r).unwrap_or(0),
+                    Op::Rem => l.checked_rem(r).unwrap_or(0),
+                    Op::Or | Op::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+//longest first so <= isn't read as <
+const SYMBOLS: [&str; 22] = [
+    "||", "&&", "==", "!=", "<=", ">=", "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")",
+    "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected '{}'", rest.chars().next().unwrap_or(' ')));
+            }
+            let word = &rest[..end];
+            tokens.push(number(word).map_or_else(|| Token::Name(word.to_ascii_lowercase()), Token::Number));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn number(word: &str) -> Option<i64> {
+    if let Some(hex) = word.strip_prefix('$').or_else(|| word.strip_prefix("0x")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+        word.parse().ok()
+    } else {
+        None
+    }
+}
+
+//binary operators by precedence level, loosest first
+const LEVELS: [&[(&str, Op)]; 9] = [
+    &[("||", Op::Or)],
+    &[("&&", Op::And)],
+    &[("|", Op::BitOr)],
+    &[("^", Op::BitXor)],
+    &[("&", Op::BitAnd)],
+    &[("==", Op::Eq), ("!=", Op::Ne)],
+    &[("<", Op::Lt), ("<=", Op::Le), (">", Op::Gt), (">=", Op::Ge)],
+    &[("+", Op::Add), ("-", Op::Sub)],
+    &[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Symbol(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.peek_symbol() != Some(symbol) {
+            return Err(format!("expected '{}'", symbol));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self
+            .peek_symbol()
+            .and_then(|s| LEVELS[level].iter().find(|(name, _)| *name == s))
+            .map(|(_, op)| *op)
+        {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => token.clone(),
+            None => return Err("expression ends too early".to_string()),
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Name(name) => variable(&name).map(Expr::Var),
+            Token::Symbol("!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Symbol("-") => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Token::Symbol("~") => Ok(Expr::Invert(Box::new(self.unary()?))),
+            Token::Symbol("(") => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Symbol("[") => {
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Token::Symbol(s) => Err(format!("unexpected '{}'", s)),
+        }
+    }
+}
+
+fn variable(name: &str) -> Result<Var, String> {
+    Ok(match name {
+        "a" => Var::A,
+        "x" => Var::X,
+        "y" => Var::Y,
+        "sp" | "s" => Var::Sp,
+        "p" => Var::P,
+        "pc" => Var::Pc,
+        "n" => Var::Flag(NEGATIVE),
+        "v" => Var::Flag(OVERFLOW),
+        "d" => Var::Flag(DECIMAL),
+        "i" => Var::Flag(INTERRUPT),
+        "z" => Var::Flag(ZERO),
+        "c" => Var::Flag(CARRY),
+        "frame" => Var::Frame,
+        "cycles" | "cycle" => Var::Cycles,
+        "addr" => Var::Addr,
+        "value" => Var::Value,
+        _ => return Err(format!("unknown name '{}'", name)),
+    })
+}
+
+//a parsed condition, shown as it was written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let expr = parser.binary(0)?;
+        if parser.pos != parser.tokens.len() {
+            return Err("junk after the expression".to_string());
+        }
+        Ok(Condition {
+            source: text.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn evaluate(&self, ctx: &Context) -> i64 {
+        self.expr.eval(ctx)
+    }
+
+    pub fn holds(&self, ctx: &Context) -> bool {
+        self.evaluate(ctx) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+pub struct Breakpoint {
+    pub id: usize,
+    pub trigger: Trigger,
+    pub start: u16,
+    pub end: u16, //inclusive, same as start for a single address
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+    pub one_shot: bool, //deleted the first time it fires
+    pub ignore: u64,    //hits to let through before it fires
+    pub hits: u64,      //times it was reached with the condition true
+}
+
+impl Breakpoint {
+    fn covers(&self, addr: u16) -> bool {
+        self.enabled && (self.start..=self.end).contains(&addr)
+    }
+
+    //counts the hit if the condition holds, true if it should stop the cpu
+    fn hit(&mut self, ctx: &Context) -> bool {
+        if let Some(condition) = &self.condition {
+            if !condition.holds(ctx) {
+                return false;
+            }
+        }
+        self.hits += 1;
+        self.hits > self.ignore
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>3}  {:<6} {:04X}", self.id, self.trigger.to_string(), self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04X}", self.end)?;
+        }
+        write!(f, "  hits {}", self.hits)?;
+        if self.ignore > 0 {
+            write!(f, ", ignore {}", self.ignore)?;
+        }
+        if self.one_shot {
+            write!(f, ", once")?;
+        }
+        if !self.enabled {
+            write!(f, ", disabled")?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, "  if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+//why the cpu stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Break {
+    pub id: usize,
+    pub trigger: Trigger, //Execute, Read or Write, never ReadWrite
+    pub pc: u16,          //instruction that was about to run or made the access
+    pub addr: u16,
+    pub value: Option<u8>, //byte read or written for watchpoints
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.trigger, self.value) {
+            (Trigger::Execute, _) | (_, None) => write!(f, "breakpoint {} at {:04X}", self.id, self.pc),
+            (Trigger::Write, Some(value)) => write!(
+                f,
+                "watchpoint {}: {:04X} wrote {:02X} to {:04X}",
+                self.id, self.pc, value, self.addr
+            ),
+            (_, Some(value)) => write!(
+                f,
+                "watchpoint {}: {:04X} read {:02X} from {:04X}",
+                self.id, self.pc, value, self.addr
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: usize,
+    resume: Option<u16>, //pc where execution continues after a stop, not checked once
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints::default()
+    }
+
+    //returns the id used by get, remove and Break
+    pub fn add(&mut self, trigger: Trigger, start: u16, end: u16) -> usize {
+        self.next_id += 1;
+        self.list.push(Breakpoint {
+            id: self.next_id,
+            trigger,
+            start: start.min(end),
+            end: start.max(end),
+            condition: None,
+            enabled: true,
+            one_shot: false,
+            ignore: 0,
+            hits: 0,
+        });
+        self.next_id
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.list.iter().find(|b| b.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.list.iter_mut().find(|b| b.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|b| b.id != id);
+        self.list.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    //lets the instruction at pc run without stopping at its own breakpoint,
+    //call it before continuing from a stop. the cpu does it for the breaks it reports
+    pub fn resume_at(&mut self, pc: u16) {
+        self.resume = Some(pc);
+    }
+
+    pub(crate) fn watching(&self) -> bool {
+        self.list.iter().any(|b| b.enabled && b.trigger != Trigger::Execute)
+    }
+
+    //the first breakpoint that fires for this trigger and address. all matching
+    //ones count the hit, one shot breakpoints are deleted when they fire
+    fn check(&mut self, trigger: Trigger, addr: u16, ctx: &Context) -> Option<Break> {
+        let mut fired = None;
+        for b in self.list.iter_mut() {
+            let matches = match b.trigger {
+                Trigger::ReadWrite => trigger != Trigger::Execute,
+                t => t == trigger,
+            };
+            if matches && b.covers(addr) && b.hit(ctx) && fired.is_none() {
+                fired = Some((b.id, b.one_shot));
+            }
+        }
+        let (id, one_shot) = fired?;
+        if one_shot {
+            self.remove(id);
+        }
+        Some(Break {
+            id,
+            trigger,
+            pc: ctx.pc,
+            addr,
+            value: ctx.access.map(|(_, value)| value),
+        })
+    }
+
+    pub(crate) fn check_execute(&mut self, ctx: &Context) -> Option<Break> {
+        if self.resume.take() == Some(ctx.pc) {
+            return None;
+        }
+        let hit = self.check(Trigger::Execute, ctx.pc, ctx)?;
+        self.resume = Some(ctx.pc);
+        Some(hit)
+    }
+
+    pub(crate) fn check_access(&mut self, write: bool, ctx: &Context) -> Option<Break> {
+        let (addr, _) = ctx.access?;
+        let trigger = if write { Trigger::Write } else { Trigger::Read };
+        self.check(trigger, addr, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn regs() -> CpuState {
+        let mut regs = *Cpu::default().state();
+        regs.set_a(0x40);
+        regs.set_x(0x02);
+        regs.set_y(0xff);
+        regs.set_sp(0xfd);
+        regs.set_p(0x24 | 0x01);
+        regs
+    }
+
+    //[$0300] is 5, everything else reads as its low byte
+    fn peek(addr: u16) -> Option<u8> {
+        Some(if addr == 0x0300 { 5 } else { addr as u8 })
+    }
+
+    fn eval(text: &str, cycles: u64) -> i64 {
+        let regs = regs();
+        let ctx = Context {
+            regs: &regs,
+            pc: 0x8000,
+            cycles,
+            peek: &peek,
+            access: Some((0x2002, 0x80)),
+        };
+        Condition::parse(text).unwrap().evaluate(&ctx)
+    }
+
+    fn ctx_at<'a>(regs: &'a CpuState, pc: u16) -> Context<'a> {
+        Context {
+            regs,
+            pc,
+            cycles: 0,
+            peek: &peek,
+            access: None,
+        }
+    }
+
+    #[test]
+    fn the_documented_example() {
+        let condition = "a == $40 && [$0300] > 3 && frame > 100";
+        //frame 101 starts after 101 * 341 * 262 / 3 cycles
+        assert_eq!(eval(condition, 3_007_847), 0);
+        assert_eq!(eval(condition, 3_007_848), 1);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", 0), 7);
+        assert_eq!(eval("(1 + 2) * 3", 0), 9);
+        assert_eq!(eval("1 | 2 ^ 3 & 6", 0), 1); //left to right would give 0
+        assert_eq!(eval("1 < 2 == 1", 0), 1);
+        assert_eq!(eval("0 || 1 && 0", 0), 0);
+        assert_eq!(eval("-2 * 3 + ~0 + !5 + 7 % 4", 0), -6 - 1 + 3);
+        assert_eq!(eval("8 / 0 + 8 % 0", 0), 0);
+    }
+
+    #[test]
+    fn names_numbers_and_memory() {
+        assert_eq!(eval("a + x + y + sp", 0), 0x40 + 2 + 0xff + 0xfd);
+        assert_eq!(eval("p", 0), 0x25);
+        assert_eq!(eval("pc", 0), 0x8000);
+        assert_eq!(eval("c * 10 + z", 0), 10);
+        assert_eq!(eval("cycles", 1234), 1234);
+        assert_eq!(eval("addr == $2002 && value == 128", 0), 1);
+        assert_eq!(eval("0x10 + $10 + 10", 0), 42);
+        assert_eq!(eval("[$0300]", 0), 5);
+        assert_eq!(eval("[$12 + x]", 0), 0x14);
+        assert_eq!(eval("A == $40", 0), 1);
+    }
+
+    #[test]
+    fn malformed_conditions() {
+        let error = |text| Condition::parse(text).unwrap_err();
+        assert_eq!(error("a =="), "expression ends too early");
+        assert_eq!(error("(a + 1"), "expected ')'");
+        assert_eq!(error("[$0300"), "expected ']'");
+        assert_eq!(error("a == 1 2"), "junk after the expression");
+        assert_eq!(error("q > 1"), "unknown name 'q'");
+        assert_eq!(error("a # 1"), "unexpected '#'");
+        assert_eq!(error("a == )"), "unexpected ')'");
+    }
+
+    #[test]
+    fn one_shot_ignore_counts_and_resume() {
+        let regs = regs();
+        let mut breakpoints = Breakpoints::new();
+        let once = breakpoints.add(Trigger::Execute, 0x8000, 0x8000);
+        breakpoints.get_mut(once).unwrap().one_shot = true;
+        let counted = breakpoints.add(Trigger::Execute, 0x9000, 0x9000);
+        breakpoints.get_mut(counted).unwrap().ignore = 2;
+
+        assert_eq!(breakpoints.check_execute(&ctx_at(&regs, 0x8000)).map(|b| b.id), Some(once));
+        assert!(breakpoints.get(once).is_none());
+        assert!(breakpoints.check_execute(&ctx_at(&regs, 0x8000)).is_none());
+
+        //two hits go by, the third stops
+        for _ in 0..2 {
+            assert!(breakpoints.check_execute(&ctx_at(&regs, 0x9000)).is_none());
+        }
+        assert_eq!(breakpoints.check_execute(&ctx_at(&regs, 0x9000)).map(|b| b.id), Some(counted));
+        //continuing from the stop doesn't hit it again right away, the next pass does
+        assert!(breakpoints.check_execute(&ctx_at(&regs, 0x9000)).is_none());
+        assert!(breakpoints.check_execute(&ctx_at(&regs, 0x9000)).is_some());
+
+        //resume_at skips only the address given, once
+        breakpoints.resume_at(0x9000);
+        assert!(breakpoints.check_execute(&ctx_at(&regs, 0x9000)).is_none());
+        breakpoints.resume_at(0x1234);
+        assert!(breakpoints.check_execute(&ctx_at(&regs, 0x9000)).is_some());
+        assert_eq!(breakpoints.get(counted).unwrap().hits, 5);
+    }
+
+    #[test]
+    fn conditions_and_watchpoints() {
+        let regs = regs();
+        let mut breakpoints = Breakpoints::new();
+        let id = breakpoints.add(Trigger::Write, 0x2000, 0x2007);
+        breakpoints.get_mut(id).unwrap().condition = Some(Condition::parse("value & $80").unwrap());
+        let mut ctx = ctx_at(&regs, 0x8000);
+        ctx.access = Some((0x2001, 0x1e));
+        assert!(breakpoints.check_access(true, &ctx).is_none());
+        ctx.access = Some((0x2000, 0x80));
+        assert!(breakpoints.check_access(false, &ctx).is_none());
+        let hit = breakpoints.check_access(true, &ctx).unwrap();
+        assert_eq!(hit.to_string(), format!("watchpoint {}: 8000 wrote 80 to 2000", id));
+    }
+}
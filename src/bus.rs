@@ -0,0 +1,22 @@
+//anything the cpu can be attached to: flat ram, the nes memory map, test rigs...
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, value: u8);
+
+    //read without side effects (no register clears, no mapper updates), for debuggers.
+    //None if the bus can't tell the value without touching the hardware
+    fn peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    //debugger write, patches rom too where the bus can. a plain write by default
+    fn poke(&mut self, addr: u16, value: u8) {
+        self.write(addr, value);
+    }
+
+    //offset into the cartridge prg-rom of the byte at addr, see Mapper::prg_offset
+    fn prg_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+}
@@ -0,0 +1,420 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+pub const PRG_BANK_SIZE: usize = 0x4000; //16 KiB
+pub const CHR_BANK_SIZE: usize = 0x2000; //8 KiB
+
+const MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1a];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    INes,
+    Nes20,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended(u8), //nes 2.0 byte 13, low nibble
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    BadMagic([u8; 4]),
+    Truncated { expected: usize, found: usize },
+    NoPrgRom,
+    UnsupportedMapper(u16),
+    //nes 2.0 exponent notation that doesn't fit in memory
+    SizeOverflow { field: &'static str },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "i/o error: {}", e),
+            CartridgeError::BadMagic(m) => write!(
+                f,
+                "not an iNES file, magic is {:02x} {:02x} {:02x} {:02x}",
+                m[0], m[1], m[2], m[3]
+            ),
+            CartridgeError::Truncated { expected, found } => write!(
+                f,
+                "file truncated, file needs {} bytes but has {}",
+                expected, found
+            ),
+            CartridgeError::NoPrgRom => write!(f, "header declares no PRG-ROM"),
+            CartridgeError::UnsupportedMapper(m) => write!(f, "mapper {} is not supported", m),
+            CartridgeError::SizeOverflow { field } => {
+                write!(f, "{} size in exponent notation is too large", field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub format: Format,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub tv_system: TvSystem,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, CartridgeError> {
+        if data.len() < HEADER_SIZE {
+            return Err(CartridgeError::Truncated {
+                expected: HEADER_SIZE,
+                found: data.len(),
+            });
+        }
+        let magic = [data[0], data[1], data[2], data[3]];
+        if magic != MAGIC {
+            return Err(CartridgeError::BadMagic(magic));
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let format = if flags7 & 0x0c == 0x08 {
+            Format::Nes20
+        } else {
+            Format::INes
+        };
+
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0x02 != 0;
+        let trainer = flags6 & 0x04 != 0;
+
+        let console_type = match flags7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(data[13] & 0x0f),
+        };
+
+        let header = match format {
+            Format::Nes20 => {
+                let prg_rom_size =
+                    rom_size(data[4], data[9] & 0x0f, PRG_BANK_SIZE, "PRG-ROM")?;
+                let chr_rom_size = rom_size(data[5], data[9] >> 4, CHR_BANK_SIZE, "CHR-ROM")?;
+                let tv_system = match data[12] & 0x03 {
+                    0 => TvSystem::Ntsc,
+                    1 => TvSystem::Pal,
+                    2 => TvSystem::MultiRegion,
+                    _ => TvSystem::Dendy,
+                };
+                Header {
+                    format,
+                    prg_rom_size,
+                    chr_rom_size,
+                    mapper: (flags6 >> 4) as u16
+                        | (flags7 & 0xf0) as u16
+                        | ((data[8] & 0x0f) as u16) << 8,
+                    submapper: data[8] >> 4,
+                    prg_ram_size: ram_size(data[10] & 0x0f),
+                    prg_nvram_size: ram_size(data[10] >> 4),
+                    chr_ram_size: ram_size(data[11] & 0x0f),
+                    chr_nvram_size: ram_size(data[11] >> 4),
+                    mirroring,
+                    battery,
+                    trainer,
+                    tv_system,
+                    console_type,
+                    misc_roms: data[14] & 0x03,
+                    expansion_device: data[15] & 0x3f,
+                }
+            }
+            Format::INes => {
+                //old dumping tools wrote garbage ("DiskDude!") in bytes 7-15,
+                //in that case the upper mapper nibble can't be trusted
+                let dirty = data[12..16].iter().any(|b| *b != 0);
+                let mapper_high = if dirty { 0 } else { flags7 & 0xf0 };
+                let prg_ram_units = if dirty || data[8] == 0 { 1 } else { data[8] };
+                let tv_system = if !dirty && data[9] & 0x01 != 0 {
+                    TvSystem::Pal
+                } else {
+                    TvSystem::Ntsc
+                };
+                let prg_ram_size = prg_ram_units as usize * 0x2000;
+                let chr_rom_size = data[5] as usize * CHR_BANK_SIZE;
+                Header {
+                    format,
+                    prg_rom_size: data[4] as usize * PRG_BANK_SIZE,
+                    chr_rom_size,
+                    mapper: ((flags6 >> 4) | mapper_high) as u16,
+                    submapper: 0,
+                    prg_ram_size: if battery { 0 } else { prg_ram_size },
+                    prg_nvram_size: if battery { prg_ram_size } else { 0 },
+                    chr_ram_size: if chr_rom_size == 0 { CHR_BANK_SIZE } else { 0 },
+                    chr_nvram_size: 0,
+                    mirroring,
+                    battery,
+                    trainer,
+                    tv_system,
+                    console_type: if dirty { ConsoleType::Nes } else { console_type },
+                    misc_roms: 0,
+                    expansion_device: 0,
+                }
+            }
+        };
+
+        if header.prg_rom_size == 0 {
+            return Err(CartridgeError::NoPrgRom);
+        }
+        Ok(header)
+    }
+}
+
+//nes 2.0 rom size: if the msb nibble is 0xf the lsb byte is EEEEEEMM
+//and the size is 2^E * (MM * 2 + 1) bytes
+fn rom_size(lsb: u8, msb: u8, unit: usize, field: &'static str) -> Result<usize, CartridgeError> {
+    if msb == 0x0f {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|v| v.checked_mul(multiplier))
+            .ok_or(CartridgeError::SizeOverflow { field })
+    } else {
+        Ok((((msb as usize) << 8) | lsb as usize) * unit)
+    }
+}
+
+//nes 2.0 ram size: 0 means none, otherwise 64 << shift bytes
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub misc_rom: Vec<u8>, //whatever follows chr-rom
+}
+
+impl Cartridge {
+    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, CartridgeError> {
+        let header = Header::parse(data)?;
+
+        let trainer_size = if header.trainer { TRAINER_SIZE } else { 0 };
+        let expected = HEADER_SIZE
+            .checked_add(trainer_size)
+            .and_then(|v| v.checked_add(header.prg_rom_size))
+            .and_then(|v| v.checked_add(header.chr_rom_size))
+            .ok_or(CartridgeError::SizeOverflow { field: "ROM" })?;
+        if data.len() < expected {
+            return Err(CartridgeError::Truncated {
+                expected,
+                found: data.len(),
+            });
+        }
+
+        let mut pos = HEADER_SIZE;
+        let trainer = if header.trainer {
+            pos += TRAINER_SIZE;
+            Some(data[HEADER_SIZE..pos].to_vec())
+        } else {
+            None
+        };
+        let prg_rom = data[pos..pos + header.prg_rom_size].to_vec();
+        pos += header.prg_rom_size;
+        let chr_rom = data[pos..pos + header.chr_rom_size].to_vec();
+        pos += header.chr_rom_size;
+        let misc_rom = data[pos..].to_vec();
+
+        Ok(Cartridge {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+            misc_rom,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        let data = fs::read(path)?;
+        Cartridge::from_bytes(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //16 byte header with the given bytes 4 to 15
+    fn header(rest: [u8; 12]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&rest);
+        data
+    }
+
+    //a whole rom: header, trainer if flagged, then the banks filled with their number
+    fn rom(prg_banks: u8, chr_banks: u8, flags6: u8) -> Vec<u8> {
+        let mut data = header([prg_banks, chr_banks, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        if flags6 & 0x04 != 0 {
+            data.extend(vec![0xee; TRAINER_SIZE]);
+        }
+        for bank in 0..prg_banks {
+            data.extend(vec![bank; PRG_BANK_SIZE]);
+        }
+        for bank in 0..chr_banks {
+            data.extend(vec![0x80 | bank; CHR_BANK_SIZE]);
+        }
+        data
+    }
+
+    #[test]
+    fn rejects_bad_magic_and_short_headers() {
+        let mut data = rom(1, 1, 0);
+        data[3] = 0x1b;
+        match Cartridge::from_bytes(&data) {
+            Err(e @ CartridgeError::BadMagic(_)) => {
+                assert_eq!(e.to_string(), "not an iNES file, magic is 4e 45 53 1b")
+            }
+            _ => panic!("bad magic accepted"),
+        }
+        assert!(matches!(
+            Header::parse(&data[..10]),
+            Err(CartridgeError::Truncated {
+                expected: HEADER_SIZE,
+                found: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_header_without_prg_rom() {
+        assert!(matches!(Header::parse(&header([0; 12])), Err(CartridgeError::NoPrgRom)));
+    }
+
+    #[test]
+    fn truncated_body_reports_the_whole_file_size() {
+        let data = rom(2, 1, 0);
+        let short = &data[..data.len() - 1];
+        let error = Cartridge::from_bytes(short).err().unwrap();
+        assert!(matches!(error, CartridgeError::Truncated { expected, found }
+            if expected == data.len() && found == data.len() - 1));
+        assert_eq!(
+            error.to_string(),
+            format!("file truncated, file needs {} bytes but has {}", data.len(), data.len() - 1)
+        );
+    }
+
+    #[test]
+    fn trainer_comes_before_prg_rom() {
+        let cart = Cartridge::from_bytes(&rom(2, 1, 0x04)).unwrap();
+        assert_eq!(cart.trainer, Some(vec![0xee; TRAINER_SIZE]));
+        assert_eq!(cart.prg_rom.len(), 2 * PRG_BANK_SIZE);
+        assert_eq!(cart.prg_rom[0], 0);
+        assert_eq!(cart.prg_rom[PRG_BANK_SIZE], 1);
+        assert_eq!(cart.chr_rom, vec![0x80; CHR_BANK_SIZE]);
+        assert!(cart.misc_rom.is_empty());
+    }
+
+    #[test]
+    fn ines_fields() {
+        //mapper 0x42, vertical mirroring, battery, pal
+        let h = Header::parse(&header([2, 0, 0x23, 0x40, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(h.format, Format::INes);
+        assert_eq!(h.mapper, 0x42);
+        assert_eq!(h.mirroring, Mirroring::Vertical);
+        assert!(h.battery);
+        assert_eq!(h.prg_nvram_size, 0x2000);
+        assert_eq!(h.chr_ram_size, CHR_BANK_SIZE);
+        assert_eq!(h.tv_system, TvSystem::Pal);
+    }
+
+    #[test]
+    fn diskdude_garbage_drops_the_upper_mapper_nibble() {
+        let mut data = header([1, 1, 0x10, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data[7..16].copy_from_slice(b"DiskDude!");
+        let h = Header::parse(&data).unwrap();
+        assert_eq!(h.format, Format::INes);
+        assert_eq!(h.mapper, 1);
+        assert_eq!(h.console_type, ConsoleType::Nes);
+        assert_eq!(h.tv_system, TvSystem::Ntsc);
+    }
+
+    #[test]
+    fn nes20_sizes() {
+        //mapper 0x142 submapper 3, 0x102 prg banks, ram shift counts, dendy
+        let h = Header::parse(&header([0x02, 0x01, 0x20, 0x48, 0x31, 0x01, 0x70, 0x07, 0x03, 0, 0, 0])).unwrap();
+        assert_eq!(h.format, Format::Nes20);
+        assert_eq!(h.mapper, 0x142);
+        assert_eq!(h.submapper, 3);
+        assert_eq!(h.prg_rom_size, 0x102 * PRG_BANK_SIZE);
+        assert_eq!(h.chr_rom_size, CHR_BANK_SIZE);
+        assert_eq!(h.prg_ram_size, 0);
+        assert_eq!(h.prg_nvram_size, 64 << 7);
+        assert_eq!(h.chr_ram_size, 64 << 7);
+        assert_eq!(h.tv_system, TvSystem::Dendy);
+
+        //exponent notation: 2^4 * (1 * 2 + 1) bytes of prg-rom
+        let h = Header::parse(&header([0x11, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(h.prg_rom_size, 48);
+    }
+
+    #[test]
+    fn nes20_exponent_overflow() {
+        //2^63 * 3
+        let data = header([0x01, 0xfd, 0, 0x08, 0, 0xf0, 0, 0, 0, 0, 0, 0]);
+        match Header::parse(&data) {
+            Err(e @ CartridgeError::SizeOverflow { field: "CHR-ROM" }) => {
+                assert_eq!(e.to_string(), "CHR-ROM size in exponent notation is too large")
+            }
+            _ => panic!("overflow not reported"),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::opcodes::{Instruction, Mode, Opcode};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+//code/data logger in fceux's .cdl format: one flag byte per prg-rom byte
+//followed by one per chr-rom byte. attach with Cpu::set_code_data_logger, the
+//cpu reports opcode and operand fetches apart from data reads.
+//log_dmc and log_chr are api only: there's no apu or ppu to call them yet, so
+//PCM and the chr flags stay as loaded and only prg coverage is reported
+
+//prg-rom flags
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+pub const BANK_MASK: u8 = 0x0c; //bits 13-14 of the cpu address it was seen at
+pub const INDIRECT_CODE: u8 = 0x10; //target of jmp ($nnnn)
+pub const INDIRECT_DATA: u8 = 0x20; //read through ($nn,x) or ($nn),y
+pub const PCM: u8 = 0x40; //fetched by dmc dma
+//bit 7 is unused by fceux, we mark opcode bytes with it in memory and
+//leave it out of saved files
+pub const OPCODE: u8 = 0x80;
+
+//chr-rom flags
+pub const RENDERED: u8 = 0x01; //fetched by the ppu while drawing
+pub const CHR_READ: u8 = 0x02; //read by the cpu through $2007
+
+//bytes only ever read as data (or dmc samples) are shown as .byte by the
+//disassembler, unlogged ones are decoded
+pub fn is_data(flags: u8) -> bool {
+    flags & (DATA | PCM) != 0 && flags & CODE == 0
+}
+
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    instruction: (u16, u16), //first and last byte of the instruction running
+    indirect: bool,          //it reads data through a zero page pointer
+    jumped_indirect: bool,   //the previous one was jmp ($nnnn)
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_size: usize, chr_size: usize) -> CodeDataLogger {
+        CodeDataLogger {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+            instruction: (0, 0),
+            indirect: false,
+            jumped_indirect: false,
+        }
+    }
+
+    //reads a log saved for a rom of these sizes. a file of the wrong size
+    //belongs to another rom, that's an InvalidData error
+    pub fn load<P: AsRef<Path>>(path: P, prg_size: usize, chr_size: usize) -> io::Result<CodeDataLogger> {
+        let data = fs::read(path)?;
+        if data.len() != prg_size + chr_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "log has {} bytes, the rom needs {} prg + {} chr",
+                    data.len(),
+                    prg_size,
+                    chr_size
+                ),
+            ));
+        }
+        let mut log = CodeDataLogger::new(prg_size, chr_size);
+        log.prg.copy_from_slice(&data[..prg_size]);
+        log.chr.copy_from_slice(&data[prg_size..]);
+        Ok(log)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut data: Vec<u8> = self.prg.iter().map(|flags| flags & !OPCODE).collect();
+        data.extend_from_slice(&self.chr);
+        fs::write(path, data)
+    }
+
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    fn mark_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        if let Some(byte) = self.prg.get_mut(offset) {
+            *byte = (*byte & !BANK_MASK) | flags | ((addr >> 11) as u8 & BANK_MASK);
+        }
+    }
+
+    //the cpu is about to run opcode at pc, prg_offset maps its bytes into rom
+    pub fn log_instruction(&mut self, pc: u16, opcode: Opcode, prg_offset: &dyn Fn(u16) -> Option<usize>) {
+        let last = pc.wrapping_add(opcode.size() as u16 - 1);
+        for i in 0..opcode.size() as u16 {
+            let addr = pc.wrapping_add(i);
+            if let Some(offset) = prg_offset(addr) {
+                let mut flags = CODE;
+                if i == 0 {
+                    flags |= OPCODE;
+                    if self.jumped_indirect {
+                        flags |= INDIRECT_CODE;
+                    }
+                }
+                self.mark_prg(offset, addr, flags);
+            }
+        }
+        self.instruction = (pc, last);
+        self.indirect = matches!(opcode.mode, Mode::IndirectX | Mode::IndirectY);
+        self.jumped_indirect = opcode.instruction == Instruction::Jmp && opcode.mode == Mode::Indirect;
+    }
+
+    //a cpu read, fetches of the running instruction were logged already
+    pub fn log_read(&mut self, addr: u16, offset: Option<usize>) {
+        let (first, last) = self.instruction;
+        let fetch = if first <= last {
+            (first..=last).contains(&addr)
+        } else {
+            addr >= first || addr <= last
+        };
+        if let (false, Some(offset)) = (fetch, offset) {
+            let flags = if self.indirect { DATA | INDIRECT_DATA } else { DATA };
+            self.mark_prg(offset, addr, flags);
+        }
+    }
+
+    //sample byte fetched by dmc dma, for the apu
+    pub fn log_dmc(&mut self, addr: u16, offset: usize) {
+        self.mark_prg(offset, addr, PCM);
+    }
+
+    //chr-rom byte fetched by the ppu, rendered or through $2007
+    pub fn log_chr(&mut self, offset: usize, rendered: bool) {
+        if let Some(byte) = self.chr.get_mut(offset) {
+            *byte |= if rendered { RENDERED } else { CHR_READ };
+        }
+    }
+
+    //code, data and unlogged prg bytes, for a summary
+    pub fn prg_coverage(&self) -> (usize, usize, usize) {
+        let code = self.prg.iter().filter(|f| *f & CODE != 0).count();
+        let data = self.prg.iter().filter(|f| is_data(**f)).count();
+        let unlogged = self.prg.iter().filter(|f| *f & (CODE | DATA | PCM) == 0).count();
+        (code, data, unlogged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::OPCODES;
+    use std::env;
+    use std::process;
+
+    //32k of prg at $8000
+    fn prg_offset(addr: u16) -> Option<usize> {
+        addr.checked_sub(0x8000).map(|offset| offset as usize)
+    }
+
+    #[test]
+    fn flags_and_bank_bits() {
+        let mut log = CodeDataLogger::new(0x8000, 0x2000);
+        //lda ($10),y at $c000, then the byte it reads at $e123
+        log.log_instruction(0xc000, OPCODES[0xb1], &prg_offset);
+        log.log_read(0xc001, prg_offset(0xc001)); //its own operand fetch
+        log.log_read(0xe123, prg_offset(0xe123));
+        assert_eq!(log.prg()[0x4000], OPCODE | CODE | 0x08);
+        assert_eq!(log.prg()[0x4001], CODE | 0x08);
+        assert_eq!(log.prg()[0x6123], DATA | INDIRECT_DATA | 0x0c);
+
+        //jmp ($0200) marks where it lands, plain reads aren't indirect
+        log.log_instruction(0x8000, OPCODES[0x6c], &prg_offset);
+        log.log_instruction(0x9000, OPCODES[0xea], &prg_offset);
+        log.log_read(0xa001, prg_offset(0xa001));
+        assert_eq!(log.prg()[0x1000], OPCODE | CODE | INDIRECT_CODE);
+        assert_eq!(log.prg()[0x2001], DATA | 0x04);
+
+        log.log_dmc(0xa000, 0x2000);
+        log.log_chr(0, true);
+        log.log_chr(1, false);
+        assert_eq!(log.prg()[0x2000], PCM | 0x04);
+        assert_eq!(&log.chr()[..2], [RENDERED, CHR_READ]);
+        assert_eq!(log.prg_coverage(), (6, 3, 0x8000 - 9));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("cdl-test-{}.cdl", process::id()));
+        let mut log = CodeDataLogger::new(0x4000, 0x2000);
+        log.log_instruction(0xc000, OPCODES[0xea], &|addr| Some(addr as usize - 0xc000));
+        log.log_chr(5, true);
+        log.save(&path).unwrap();
+
+        //fceux doesn't know the opcode bit, it isn't saved
+        let loaded = CodeDataLogger::load(&path, 0x4000, 0x2000).unwrap();
+        assert_eq!(loaded.prg()[0], CODE | 0x08);
+        assert_eq!(loaded.chr()[5], RENDERED);
+
+        let err = CodeDataLogger::load(&path, 0x8000, 0x2000).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,7 +1,23 @@
+use crate::breakpoints::{Break, Breakpoints, Context};
+use crate::bus::Bus;
+use crate::cdl::CodeDataLogger;
 use crate::memory::Memory;
+use crate::opcodes::{self, Access, Instruction, Mode, Opcode};
+use crate::opcodes::Instruction::*;
+use crate::profile::Profiler;
+use crate::trace::Tracer;
 use crate::utils::*;
+use std::fmt;
 
-struct Registers {
+mod tick;
+#[cfg(test)]
+mod tests;
+
+use tick::MicroState;
+
+//the programmer visible registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuState {
     a: u8,   //accumulator
     x: u8,   //index
     y: u8,   //index
@@ -10,280 +26,836 @@ struct Registers {
     p: u8,   //status register
 }
 
-impl Registers {
-    pub fn new() -> Registers {
-        Registers {
+impl CpuState {
+    fn new() -> CpuState {
+        CpuState {
             a: 0x0,
             x: 0x0,
             y: 0x0,
-            pc: 0x8000,
-            sp: 0xfd,
-            p: 0x34,
+            pc: 0x0000, //loaded from the reset vector by Cpu::reset
+            sp: 0x00,   //reset takes it down to 0xfd
+            p: 0x24,    //b only exists on the stack, bit 5 always reads 1
+        }
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn p(&self) -> u8 {
+        self.p
+    }
+
+    pub fn set_a(&mut self, value: u8) {
+        self.a = value;
+    }
+
+    pub fn set_x(&mut self, value: u8) {
+        self.x = value;
+    }
+
+    pub fn set_y(&mut self, value: u8) {
+        self.y = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn set_sp(&mut self, value: u8) {
+        self.sp = value;
+    }
+
+    //raw value, bits 4 and 5 are kept as given
+    pub fn set_p(&mut self, value: u8) {
+        self.p = value;
+    }
+
+    //bit is one of the constants in utils (CARRY, ZERO, ...)
+    pub fn flag(&self, bit: u8) -> bool {
+        get_bit_at(self.p, bit) == SET
+    }
+
+    pub fn set_flag(&mut self, bit: u8, value: bool) {
+        if value {
+            self.p |= 1 << bit;
+        } else {
+            self.p &= !(1 << bit);
         }
     }
 }
 
-pub struct Cpu {
-    mem: Memory,
-    regs: Registers,
+//everything needed to put the cpu back where it was, except the bus
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuSnapshot {
+    state: CpuState,
+    cycles: u64,
+    nmi_pending: bool,
+    irq_line: bool,
+    pending: Option<Interrupt>,
+    jammed: Option<u8>,
+    waiting: bool,
+    frames: Vec<StackFrame>,
+    micro: MicroState,
+}
+
+impl CpuSnapshot {
+    pub fn state(&self) -> &CpuState {
+        &self.state
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+}
+
+pub const NMI_VECTOR: u16 = 0xfffa;
+pub const RESET_VECTOR: u16 = 0xfffc;
+pub const IRQ_VECTOR: u16 = 0xfffe;
+
+//bits 4 and 5 of the status register only exist when it is pushed
+const BREAK_BIT: u8 = 0x10;
+const UNUSED_BIT: u8 = 0x20;
+
+pub const INTERRUPT_CYCLES: u8 = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Interrupt {
+    Nmi,
+    Irq,
 }
 
-impl Cpu {
-    pub fn new() -> Cpu {
+//which chip the core behaves like
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuVariant {
+    Ricoh2A03, //nes cpu, the decimal flag exists but adc/sbc ignore it
+    Nmos6502,  //stock 6502 with bcd arithmetic
+    Cmos65C02, //wdc 65c02, new opcodes and the nmos bugs fixed
+}
+
+impl CpuVariant {
+    //the 65c02 has no cycle-stepped core, Cpu::tick runs its instructions whole
+    //on the first cycle and idles for the rest
+    pub fn cycle_stepped(self) -> bool {
+        self != CpuVariant::Cmos65C02
+    }
+}
+
+//what to do when the decoder hits an opcode the 6502 doesn't document
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    Halt,    //stop with an error, pc stays on the opcode
+    Nop,     //skip it as a one byte nop
+    Execute, //run it the way the nmos silicon does
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    IllegalOpcode {
+        opcode: u8,
+        pc: u16,         //where the opcode was fetched from
+        regs: CpuState, //state before the opcode
+    },
+    //a kil/jam opcode locked the cpu, only reset gets it going again
+    Jammed {
+        opcode: u8,
+        pc: u16,
+    },
+    //not a failure: a breakpoint stopped the cpu before the instruction at its
+    //address, or a watchpoint after the instruction that made the access
+    Break(Break),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, pc, regs } => write!(
+                f,
+                "illegal opcode {:02X} at {:04X} (A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X})",
+                opcode, pc, regs.a, regs.x, regs.y, regs.p, regs.sp
+            ),
+            CpuError::Jammed { opcode, pc } => {
+                write!(f, "cpu jammed by opcode {:02X} at {:04X}", opcode, pc)
+            }
+            CpuError::Break(hit) => write!(f, "{}", hit),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+pub const STACK_PAGE: u16 = 0x0100;
+const MAX_FRAMES: usize = 128; //more than fits in the stack page anyway
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Jsr,
+    Brk,
+    Nmi,
+    Irq,
+}
+
+//a return address on the stack, as shown to debuggers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    pub kind: FrameKind,
+    pub sp: u8,             //stack pointer before the frame was pushed
+    pub return_addr: u16,   //where execution continues after rts/rti
+    pub status: Option<u8>, //pushed p, for brk and interrupts
+}
+
+fn crosses_page(a: u16, b: u16) -> bool {
+    a & 0xff00 != b & 0xff00
+}
+
+pub struct Cpu<B: Bus> {
+    bus: B,
+    regs: CpuState,
+    cycles: u64,        //total cycles executed
+    page_crossed: bool, //set by the indexed addressing modes
+    extra_cycles: u8,   //taken branch penalties
+    nmi_pending: bool,  //edge seen on the nmi line, not serviced yet
+    irq_line: bool,     //level of the irq line, true = asserted
+    pending: Option<Interrupt>, //what the last poll decided to service next
+    illegal_policy: IllegalOpcodePolicy,
+    jammed: Option<u8>, //kil (or 65c02 stp) opcode that froze the cpu
+    waiting: bool,      //65c02 wai, sleeping until an interrupt line goes active
+    variant: CpuVariant,
+    frames: Vec<StackFrame>, //return addresses still on the stack, oldest first
+    micro: MicroState,       //instruction in flight when stepping with tick
+    tracer: Option<Tracer>,
+    breakpoints: Breakpoints,
+    op_pc: u16,                //address of the instruction running, for watchpoints
+    watch_hit: Option<Break>, //watchpoint that fired during the current instruction
+    cdl: Option<CodeDataLogger>,
+    profiler: Option<Profiler>,
+}
+
+impl Default for Cpu<Memory> {
+    fn default() -> Self {
+        Self::new(Memory::new())
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn new(bus: B) -> Cpu<B> {
         Cpu {
-            mem: Memory::new(),
-            regs: Registers::new(),
+            bus,
+            regs: CpuState::new(),
+            cycles: 0,
+            page_crossed: false,
+            extra_cycles: 0,
+            nmi_pending: false,
+            irq_line: false,
+            pending: None,
+            illegal_policy: IllegalOpcodePolicy::Halt,
+            jammed: None,
+            waiting: false,
+            variant: CpuVariant::Ricoh2A03,
+            frames: Vec::new(),
+            micro: MicroState::default(),
+            tracer: None,
+            breakpoints: Breakpoints::new(),
+            op_pc: 0,
+            watch_hit: None,
+            cdl: None,
+            profiler: None,
         }
     }
 
-    fn set_zero_flag(&mut self, status: bool) {
-        if status {
-            self.regs.p = self.regs.p | 0x02; //z = 1
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    pub fn set_variant(&mut self, variant: CpuVariant) {
+        self.variant = variant;
+    }
+
+    pub fn illegal_policy(&self) -> IllegalOpcodePolicy {
+        self.illegal_policy
+    }
+
+    pub fn set_illegal_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_policy = policy;
+    }
+
+    fn read_vector(&mut self, vector: u16) -> u16 {
+        let mut addr: u16 = self.read(vector) as u16;
+        addr += (self.read(vector.wrapping_add(1)) as u16) << 8;
+        addr
+    }
+
+    //same sequence as an interrupt but the stack writes are turned into reads,
+    //so sp goes down by 3 and nothing is written
+    pub fn reset(&mut self) {
+        self.regs.sp = self.regs.sp.wrapping_sub(3);
+        self.set_interrupt_flag(true);
+        self.regs.pc = self.read_vector(RESET_VECTOR);
+        self.nmi_pending = false;
+        self.pending = None;
+        self.jammed = None;
+        self.waiting = false;
+        self.frames.clear();
+        self.micro = MicroState::default();
+        self.cycles += INTERRUPT_CYCLES as u64;
+    }
+
+    //nmi is edge triggered, call this once per falling edge (e.g. vblank start)
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    //irq is level triggered and keeps firing while asserted and i = 0
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    //the cpu polls the interrupt lines before the last cycle of each instruction,
+    //irq_masked is the i flag as it was at that point
+    fn poll_interrupts(&mut self, irq_masked: bool) {
+        self.pending = if self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if self.irq_line && !irq_masked {
+            Some(Interrupt::Irq)
         } else {
-            self.regs.p = self.regs.p & 0xfd; //z = 0
+            None
+        };
+    }
+
+    fn push_interrupt(&mut self, vector: u16, kind: FrameKind) {
+        let ret = self.regs.pc;
+        let sp = self.regs.sp;
+        self.push(((ret & 0xff00) >> 8) as u8);
+        self.push((ret & 0x00ff) as u8);
+        let p = if kind == FrameKind::Brk {
+            self.regs.p | BREAK_BIT | UNUSED_BIT
+        } else {
+            (self.regs.p & !BREAK_BIT) | UNUSED_BIT
+        };
+        self.push(p);
+        self.push_frame(kind, sp, ret, Some(p));
+        self.set_interrupt_flag(true);
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.set_decimal_flag(false);
         }
+        self.regs.pc = self.read_vector(vector);
     }
 
-    fn set_negative_flag(&mut self, status: bool) {
+    fn service_interrupt(&mut self, interrupt: Interrupt) {
+        match interrupt {
+            Interrupt::Nmi => {
+                self.nmi_pending = false;
+                self.push_interrupt(NMI_VECTOR, FrameKind::Nmi);
+            }
+            Interrupt::Irq => self.push_interrupt(IRQ_VECTOR, FrameKind::Irq),
+        }
+    }
+
+    //status pulled by plp and rti, b is dropped and bit 5 forced
+    fn set_status(&mut self, value: u8) {
+        self.regs.p = (value & !BREAK_BIT) | UNUSED_BIT;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn state(&self) -> &CpuState {
+        &self.regs
+    }
+
+    pub fn state_mut(&mut self) -> &mut CpuState {
+        &mut self.regs
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            state: self.regs,
+            cycles: self.cycles,
+            nmi_pending: self.nmi_pending,
+            irq_line: self.irq_line,
+            pending: self.pending,
+            jammed: self.jammed,
+            waiting: self.waiting,
+            frames: self.frames.clone(),
+            micro: self.micro,
+        }
+    }
+
+    //the bus isn't part of the snapshot, restore memory separately if needed
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.regs = snapshot.state;
+        self.cycles = snapshot.cycles;
+        self.nmi_pending = snapshot.nmi_pending;
+        self.irq_line = snapshot.irq_line;
+        self.pending = snapshot.pending;
+        self.jammed = snapshot.jammed;
+        self.waiting = snapshot.waiting;
+        self.frames = snapshot.frames.clone();
+        self.micro = snapshot.micro;
+    }
+
+    //logs every instruction before it runs, see trace::Tracer
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    fn trace(&mut self) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(self);
+            self.tracer = Some(tracer);
+        }
+    }
+
+    //a ring buffer trace is written out when the cpu stops on an error
+    fn trace_error(&mut self, error: &CpuError) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.dump(&error.to_string());
+        }
+    }
+
+    //marks prg-rom bytes as code or data as they are used, see cdl::CodeDataLogger
+    pub fn set_code_data_logger(&mut self, cdl: Option<CodeDataLogger>) {
+        self.cdl = cdl;
+    }
+
+    pub fn code_data_logger(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_ref()
+    }
+
+    pub fn code_data_logger_mut(&mut self) -> Option<&mut CodeDataLogger> {
+        self.cdl.as_mut()
+    }
+
+    fn log_code(&mut self) {
+        if let Some(cdl) = self.cdl.as_mut() {
+            let bus = &self.bus;
+            let pc = self.regs.pc;
+            let opcode = opcodes::table(self.variant)[bus.peek(pc).unwrap_or(0) as usize];
+            cdl.log_instruction(pc, opcode, &|addr| bus.prg_offset(addr));
+        }
+    }
+
+    //charges cycles to the routines on the call stack, see profile::Profiler
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    fn profile(&mut self) {
+        if let Some(mut profiler) = self.profiler.take() {
+            profiler.record(self);
+            self.profiler = Some(profiler);
+        }
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    //every cpu access goes through read and write so watchpoints and the
+    //code/data logger see them
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.bus.read(addr);
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.log_read(addr, self.bus.prg_offset(addr));
+        }
+        if self.breakpoints.watching() {
+            self.watch(false, addr, value);
+        }
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bus.write(addr, value);
+        if self.breakpoints.watching() {
+            self.watch(true, addr, value);
+        }
+    }
+
+    fn watch(&mut self, write: bool, addr: u16, value: u8) {
+        let bus = &self.bus;
+        let ctx = Context {
+            regs: &self.regs,
+            pc: self.op_pc,
+            cycles: self.cycles,
+            peek: &|a| bus.peek(a),
+            access: Some((addr, value)),
+        };
+        if let Some(hit) = self.breakpoints.check_access(write, &ctx) {
+            self.watch_hit.get_or_insert(hit);
+        }
+    }
+
+    //execute breakpoint at pc, called before the instruction there starts
+    fn check_breakpoint(&mut self) -> Result<(), CpuError> {
+        self.op_pc = self.regs.pc;
+        self.watch_hit = None;
+        if self.breakpoints.is_empty() {
+            return Ok(());
+        }
+        let bus = &self.bus;
+        let ctx = Context {
+            regs: &self.regs,
+            pc: self.regs.pc,
+            cycles: self.cycles,
+            peek: &|a| bus.peek(a),
+            access: None,
+        };
+        match self.breakpoints.check_execute(&ctx) {
+            Some(hit) => Err(CpuError::Break(hit)),
+            None => Ok(()),
+        }
+    }
+
+    //a watchpoint hit is reported once the instruction has finished
+    fn take_watch_hit(&mut self) -> Result<(), CpuError> {
+        match self.watch_hit.take() {
+            Some(hit) => Err(CpuError::Break(hit)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    fn set_zero_flag(&mut self, status: bool) {
         if status {
-            self.regs.p = self.regs.p | 0x80; //n = 1
+            self.regs.p |= 0x02; //z = 1
         } else {
-            self.regs.p = self.regs.p & 0x7f; //n = 0
+            self.regs.p &= 0xfd; //z = 0
         }
     }
 
-    fn set_overflow_flag_ex(&mut self, aux: u8) {
-        if aux & 0x80 == self.regs.a & 0x80 {
-            self.regs.p = self.regs.p | 0x40; //v = 1
+    fn set_negative_flag(&mut self, status: bool) {
+        if status {
+            self.regs.p |= 0x80; //n = 1
         } else {
-            self.regs.p = self.regs.p & 0xbf; //v = 0
+            self.regs.p &= 0x7f; //n = 0
         }
     }
 
     fn set_overflow_flag(&mut self, status: bool) {
         if status {
-            self.regs.p = self.regs.p | 0x40; //v = 1
+            self.regs.p |= 0x40; //v = 1
         } else {
-            self.regs.p = self.regs.p & 0xbf; //v = 0
+            self.regs.p &= 0xbf; //v = 0
         }
     }
 
     fn set_carry_flag(&mut self, status: bool) {
         if status {
-            self.regs.p = self.regs.p | 0x01; //c =1
+            self.regs.p |= 0x01; //c =1
         } else {
-            self.regs.p = self.regs.p & 0xfe; //c = 0
+            self.regs.p &= 0xfe; //c = 0
         }
     }
 
     fn set_decimal_flag(&mut self, status: bool) {
         if status {
-            self.regs.p = self.regs.p | 0x08; //d = 1
+            self.regs.p |= 0x08; //d = 1
         } else {
-            self.regs.p = self.regs.p & 0xf7; //d = 0
+            self.regs.p &= 0xf7; //d = 0
         }
     }
 
     fn set_interrupt_flag(&mut self, status: bool) {
         if status {
-            self.regs.p = self.regs.p | 0x04; //i = 1
-        } else {
-            self.regs.p = self.regs.p & 0xfb; //i = 0
-        }
-    }
-
-    fn check_wrap_carry_adc(&mut self, aux: u8) {
-        if aux > self.regs.a {
-            self.set_carry_flag(true);
+            self.regs.p |= 0x04; //i = 1
         } else {
-            self.set_carry_flag(false);
+            self.regs.p &= 0xfb; //i = 0
         }
     }
 
-    fn check_wrap_carry_sbc(&mut self, aux: u8) {
-        if aux < self.regs.a {
-            self.set_carry_flag(true);
-        } else {
-            self.set_carry_flag(false);
-        }
+    //the 2a03 has the d flag but no bcd circuitry
+    fn decimal_mode(&self) -> bool {
+        self.variant != CpuVariant::Ricoh2A03 && get_bit_at(self.regs.p, DECIMAL) == SET
     }
 
     fn adc(&mut self, value: u8) {
+        if self.decimal_mode() {
+            self.adc_decimal(value);
+            return;
+        }
         let carry = self.regs.p & 0x01;
-        let aux = self.regs.a;
-        self.regs.a = self.regs.a.wrapping_add(value).wrapping_add(carry);
-        self.check_wrap_carry_adc(aux);
-        self.set_overflow_flag_ex(aux);
-        self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET);        
+        let sum = self.regs.a as u16 + value as u16 + carry as u16;
+        let result = sum as u8;
+        self.set_carry_flag(sum > 0xff);
+        //v = 1 if both operands have the same sign and the result doesn't
+        self.set_overflow_flag((self.regs.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.regs.a = result;
+        self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET);
         self.set_zero_flag(self.regs.a == 0);
     }
 
     fn sbc(&mut self, value: u8) {
-        let carry = self.regs.p & 0x01;
-        let aux = self.regs.a;
-        self.regs.a = self.regs.a.wrapping_sub(value).wrapping_sub(1 - carry);
-        self.check_wrap_carry_sbc(aux);
-        self.set_overflow_flag_ex(aux);
-        self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET);        
-        self.set_zero_flag(self.regs.a == 0);
+        if self.decimal_mode() {
+            self.sbc_decimal(value);
+            return;
+        }
+        //a - m - (1 - c) == a + !m + c
+        self.adc(!value);
+    }
+
+    //bcd add as described in 6502.org's "decimal mode" tutorial (appendix a).
+    //the nmos chip takes n and v from the half adjusted sum and z from the binary
+    //sum, the 65c02 sets n and z from the result and takes one more cycle
+    fn adc_decimal(&mut self, value: u8) {
+        let a = self.regs.a;
+        let carry = (self.regs.p & 0x01) as i16;
+        let mut low = (a & 0x0f) as i16 + (value & 0x0f) as i16 + carry;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (a & 0xf0) as i16 + (value & 0xf0) as i16 + low;
+        let signed = (a & 0xf0) as i8 as i16 + (value & 0xf0) as i8 as i16 + low;
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        let result = sum as u8;
+        self.set_carry_flag(sum >= 0x100);
+        self.set_overflow_flag(!(-128..=127).contains(&signed));
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.set_negative_flag(get_bit_at(result, NEGATIVE) == SET);
+            self.set_zero_flag(result == 0);
+            self.extra_cycles += 1;
+        } else {
+            let binary = a.wrapping_add(value).wrapping_add(carry as u8);
+            self.set_negative_flag(get_bit_at(signed as u8, NEGATIVE) == SET);
+            self.set_zero_flag(binary == 0);
+        }
+        self.regs.a = result;
+    }
+
+    //bcd subtract, c and v always come from the binary subtraction,
+    //n and z too on the nmos chip
+    fn sbc_decimal(&mut self, value: u8) {
+        let a = self.regs.a;
+        let borrow = 1 - (self.regs.p & 0x01) as i16;
+        let binary = a as i16 - value as i16 - borrow;
+        let mut low = (a & 0x0f) as i16 - (value & 0x0f) as i16 - borrow;
+        let result = if self.variant == CpuVariant::Cmos65C02 {
+            let mut r = binary;
+            if r < 0 {
+                r -= 0x60;
+            }
+            if low < 0 {
+                r -= 0x06;
+            }
+            r as u8
+        } else {
+            if low < 0 {
+                low = ((low - 0x06) & 0x0f) - 0x10;
+            }
+            let mut r = (a & 0xf0) as i16 - (value & 0xf0) as i16 + low;
+            if r < 0 {
+                r -= 0x60;
+            }
+            r as u8
+        };
+        self.set_carry_flag(binary >= 0);
+        self.set_overflow_flag((a ^ value) & (a ^ binary as u8) & 0x80 != 0);
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.set_negative_flag(get_bit_at(result, NEGATIVE) == SET);
+            self.set_zero_flag(result == 0);
+            self.extra_cycles += 1;
+        } else {
+            self.set_negative_flag(get_bit_at(binary as u8, NEGATIVE) == SET);
+            self.set_zero_flag(binary as u8 == 0);
+        }
+        self.regs.a = result;
     }
 
     fn and(&mut self, value: u8) {
-        self.regs.a = self.regs.a & value;
+        self.regs.a &= value;
         self.set_zero_flag(self.regs.a == 0);
         self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET);
     }
 
     fn get_immediate(&mut self) -> u16 {
         let ret = self.regs.pc;
-        self.regs.pc += 1;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         ret
     }
 
     fn get_zero(&mut self) -> u16 {
-        let addr: u16 = 0xff & self.mem.read(self.regs.pc) as u16;
-        self.regs.pc += 1;
+        let addr: u16 = 0xff & self.read(self.regs.pc) as u16;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         addr
     }
 
     fn get_zero_x(&mut self) -> u16 {
-        let addr: u8 = self.mem.read(self.regs.pc).wrapping_add(self.regs.x);
-        self.regs.pc += 1;
+        let addr: u8 = self.read(self.regs.pc).wrapping_add(self.regs.x);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         addr as u16
     }
 
     fn get_zero_y(&mut self) -> u16 {
-        let addr: u8 = self.mem.read(self.regs.pc).wrapping_add(self.regs.y);
-        self.regs.pc += 1;
+        let addr: u8 = self.read(self.regs.pc).wrapping_add(self.regs.y);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         addr as u16
     }
 
     fn get_absolute(&mut self) -> u16 {
-        let mut addr: u16 = self.mem.read(self.regs.pc) as u16;
-        self.regs.pc += 1;
-        addr += (self.mem.read(self.regs.pc) as u16) << 8;
-        self.regs.pc += 1;
+        let mut addr: u16 = self.read(self.regs.pc) as u16;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        addr += (self.read(self.regs.pc) as u16) << 8;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         addr
     }
 
     fn get_absolute_x(&mut self) -> u16 {
-        let mut addr: u16 = self.mem.read(self.regs.pc) as u16;
-        self.regs.pc += 1;
-        addr += (self.mem.read(self.regs.pc) as u16) << 8;
-        self.regs.pc += 1;
+        let mut addr: u16 = self.read(self.regs.pc) as u16;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        addr += (self.read(self.regs.pc) as u16) << 8;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        let base = addr;
         addr = addr.wrapping_add(self.regs.x as u16);
+        self.page_crossed = crosses_page(base, addr);
         addr
     }
 
     fn get_absolute_y(&mut self) -> u16 {
-        let mut addr: u16 = self.mem.read(self.regs.pc) as u16;
-        self.regs.pc += 1;
-        addr += (self.mem.read(self.regs.pc) as u16) << 8;
-        self.regs.pc += 1;
+        let mut addr: u16 = self.read(self.regs.pc) as u16;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        addr += (self.read(self.regs.pc) as u16) << 8;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        let base = addr;
         addr = addr.wrapping_add(self.regs.y as u16);
+        self.page_crossed = crosses_page(base, addr);
 
         addr
     }
 
+    //jmp ($xxxx) on the nmos chip: the pointer increment doesn't carry,
+    //so a pointer at $xxff takes its high byte from $xx00
     fn get_indirect(&mut self) -> u16 {
-        let zero_addr: u8 = self.mem.read(self.regs.pc);
-        self.regs.pc += 1;
-        let mut addr: u16 = self.mem.read(zero_addr as u16) as u16;
-        addr += (self.mem.read(zero_addr.wrapping_add(1) as u16) as u16) << 8;
+        let ptr = self.get_absolute();
+        let hi_ptr = (ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff);
+        let mut addr: u16 = self.read(ptr) as u16;
+        addr += (self.read(hi_ptr) as u16) << 8;
+
+        addr
+    }
+
+    //65c02 (zp) mode, the pointer wraps inside the zero page
+    fn get_zero_indirect(&mut self) -> u16 {
+        let zero_addr: u8 = self.read(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        let mut addr: u16 = self.read(zero_addr as u16) as u16;
+        addr += (self.read(zero_addr.wrapping_add(1) as u16) as u16) << 8;
 
         addr
     }
 
     fn get_indirect_x(&mut self) -> u16 {
-        let mut zero_addr: u8 = self.mem.read(self.regs.pc);
-        self.regs.pc += 1;
+        let mut zero_addr: u8 = self.read(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         zero_addr = zero_addr.wrapping_add(self.regs.x);
-        let mut addr: u16 = self.mem.read(zero_addr as u16) as u16;
-        addr += (self.mem.read(zero_addr.wrapping_add(1) as u16) as u16) << 8;
+        let mut addr: u16 = self.read(zero_addr as u16) as u16;
+        addr += (self.read(zero_addr.wrapping_add(1) as u16) as u16) << 8;
 
         addr
     }
 
     fn get_indirect_y(&mut self) -> u16 {
-        let zero_addr: u8 = self.mem.read(self.regs.pc);
-        self.regs.pc += 1;
-        let mut addr: u16 = self.mem.read(zero_addr as u16) as u16;
-        addr += (self.mem.read(zero_addr.wrapping_add(1) as u16) as u16) << 8;
+        let zero_addr: u8 = self.read(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        let mut addr: u16 = self.read(zero_addr as u16) as u16;
+        addr += (self.read(zero_addr.wrapping_add(1) as u16) as u16) << 8;
+        let base = addr;
         addr = addr.wrapping_add(self.regs.y as u16);
-        
+        self.page_crossed = crosses_page(base, addr);
+
         addr
     }
 
-    fn branch_if(&mut self, bit: u8, set: u8) {
-        let jump = self.mem.read(self.regs.pc);
-        self.regs.pc += 1;
-        if get_bit_at(self.regs.p, bit) == set {
-            self.regs.pc += jump as u16;
+    fn branch(&mut self, taken: bool) {
+        let jump = self.read(self.regs.pc) as i8;
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        if taken {
+            let target = self.regs.pc.wrapping_add(jump as u16);
+            self.extra_cycles += 1; //taken
+            if crosses_page(self.regs.pc, target) {
+                self.extra_cycles += 1;
+            }
+            self.regs.pc = target;
         }
     }
 
-    fn asl_acc(&mut self) {
-        self.set_carry_flag(get_bit_at(self.regs.a, NEGATIVE) != 0);  //c = 1 if bits[7] == 1 else c = 0
-        self.regs.a = self.regs.a << 1;
-        self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET);
-        self.set_zero_flag(self.regs.a == 0);
-    }
-
-    fn asl_mem(&mut self, addr: u16) {
-        let mut value = self.mem.read(addr);
-        self.set_carry_flag(get_bit_at(value, NEGATIVE) == SET);  //c = 1 if bits[7] == 1 else c = 0        
-        value = value << 1;
+    fn asl(&mut self, mut value: u8) -> u8 {
+        self.set_carry_flag(get_bit_at(value, NEGATIVE) == SET);  //c = 1 if bits[7] == 1 else c = 0
+        value <<= 1;
         self.set_negative_flag(get_bit_at(value, NEGATIVE) == SET);
         self.set_zero_flag(value == 0);
-        self.mem.write(addr, value);
-    }
-
-    fn lsr_acc(&mut self) {
-        self.set_carry_flag(get_bit_at(self.regs.a, 0) == SET);
-        self.set_negative_flag(false);
-        self.regs.a = self.regs.a >> 1;
-        self.set_zero_flag(self.regs.a == 0);
+        value
     }
 
-    fn lsr_mem(&mut self, addr: u16) {
-        let mut value = self.mem.read(addr);
+    fn lsr(&mut self, mut value: u8) -> u8 {
         self.set_negative_flag(false);
         self.set_carry_flag(get_bit_at(value, 0) == SET);
-        value = value >> 1;
+        value >>= 1;
         self.set_zero_flag(value == 0);
-        self.mem.write(addr, value);
+        value
     }
 
-    fn ror_acc(&mut self) {
-        self.set_carry_flag(get_bit_at(self.regs.a, 0) == SET);
-        self.set_negative_flag(get_bit_at(self.regs.a, 0) == SET);
-        self.set_zero_flag(self.regs.a == 0);
-        self.regs.a = self.regs.a.rotate_right(1);
+    fn lsr_acc(&mut self) {
+        self.regs.a = self.lsr(self.regs.a);
     }
 
-    fn ror_mem(&mut self, addr: u16) {
-        let mut value = self.mem.read(addr);
+    //rotates go through the carry, old c enters on one side and leaves on the other
+    fn ror(&mut self, value: u8) -> u8 {
+        let carry = self.regs.p & 0x01;
+        let result = (value >> 1) | (carry << 7);
         self.set_carry_flag(get_bit_at(value, 0) == SET);
-        self.set_negative_flag(get_bit_at(value, 0) == SET);
-        self.set_zero_flag(self.regs.a == 0);
-        value = value.rotate_right(1);
-        self.mem.write(addr, value);
+        self.set_negative_flag(get_bit_at(result, NEGATIVE) == SET);
+        self.set_zero_flag(result == 0);
+        result
     }
 
-    fn rol_acc(&mut self) {
-        self.set_carry_flag(get_bit_at(self.regs.a, 7) == SET);
-        self.set_negative_flag(get_bit_at(self.regs.a, 6) == SET);
-        self.set_zero_flag(self.regs.a == 0);
-        self.regs.a = self.regs.a.rotate_left(1);
+    fn rol(&mut self, value: u8) -> u8 {
+        let carry = self.regs.p & 0x01;
+        let result = (value << 1) | carry;
+        self.set_carry_flag(get_bit_at(value, 7) == SET);
+        self.set_negative_flag(get_bit_at(result, NEGATIVE) == SET);
+        self.set_zero_flag(result == 0);
+        result
     }
 
-    fn rol_mem(&mut self, addr: u16) {
-        let mut value = self.mem.read(addr);
-        self.set_carry_flag(get_bit_at(value, 7) == SET);
-        self.set_negative_flag(get_bit_at(value, 6) == SET);
-        self.set_zero_flag(self.regs.a == 0);
-        value = value.rotate_left(1);
-        self.mem.write(addr, value);
+    fn ror_acc(&mut self) {
+        self.regs.a = self.ror(self.regs.a);
     }
 
     fn cmp(&mut self, value: u8) {
@@ -307,12 +879,11 @@ impl Cpu {
         self.set_negative_flag(get_bit_at(result, NEGATIVE) == SET);
     }
 
-    fn dec(&mut self, addr: u16) {
-        let mut value = self.mem.read(addr);
+    fn decrement(&mut self, mut value: u8) -> u8 {
         value = value.wrapping_sub(1);
         self.set_zero_flag(value == 0);
         self.set_negative_flag(get_bit_at(value, NEGATIVE) == SET);
-        self.mem.write(addr, value);
+        value
     }
 
     fn dex(&mut self) {
@@ -327,12 +898,11 @@ impl Cpu {
         self.set_negative_flag(get_bit_at(self.regs.y, NEGATIVE) == SET);
     }
 
-    fn inc(&mut self, addr: u16) {
-        let mut value = self.mem.read(addr);
+    fn increment(&mut self, mut value: u8) -> u8 {
         value = value.wrapping_add(1);
         self.set_zero_flag(value == 0);
         self.set_negative_flag(get_bit_at(value, NEGATIVE) == SET);
-        self.mem.write(addr, value);
+        value
     }
 
     fn inx(&mut self) {
@@ -348,13 +918,13 @@ impl Cpu {
     }
 
     fn eor(&mut self, value: u8) {
-        self.regs.a = self.regs.a ^ value;
+        self.regs.a ^= value;
         self.set_zero_flag(self.regs.a == 0);
         self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET)
     }
 
     fn ora(&mut self, value: u8) {
-        self.regs.a = self.regs.a | value;
+        self.regs.a |= value;
         self.set_zero_flag(self.regs.a == 0);
         self.set_negative_flag(get_bit_at(self.regs.a, NEGATIVE) == SET)
     }
@@ -363,38 +933,91 @@ impl Cpu {
         self.regs.pc = addr;
     }
 
+    //the stack lives in page one, sp points to the next free byte
     fn push(&mut self, value: u8) {
-        self.mem.write(self.regs.sp as u16, value);
-        self.regs.sp -= 1;
+        self.write(STACK_PAGE | self.regs.sp as u16, value);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
     }
 
     fn pop(&mut self) -> u8 {
-        let ret = self.mem.read(self.regs.sp as u16);
-        self.regs.sp += 1;
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        let ret = self.read(STACK_PAGE | self.regs.sp as u16);
+        self.drop_frames();
         ret
     }
 
+    fn push_frame(&mut self, kind: FrameKind, sp: u8, return_addr: u16, status: Option<u8>) {
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(StackFrame {
+            kind,
+            sp,
+            return_addr,
+            status,
+        });
+    }
+
+    //forget frames whose bytes have been popped (or skipped over with txs)
+    fn drop_frames(&mut self) {
+        let sp = self.regs.sp;
+        while let Some(frame) = self.frames.last() {
+            if frame.sp > sp {
+                break;
+            }
+            self.frames.pop();
+        }
+    }
+
+    //frames as jsr and the interrupts pushed them, oldest first
+    pub fn call_frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    //return addresses currently on the stack, innermost first.
+    //bytes are re-read from the bus so changes made by the program show up
+    pub fn stack_view(&self) -> Vec<StackFrame> {
+        let peek = |addr: u8| self.bus.peek(STACK_PAGE | addr as u16);
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let mut frame = *frame;
+                let hi = peek(frame.sp);
+                let lo = peek(frame.sp.wrapping_sub(1));
+                if let (Some(hi), Some(lo)) = (hi, lo) {
+                    let pushed = ((hi as u16) << 8) | lo as u16;
+                    //jsr pushes the address of its last byte, rts adds one
+                    frame.return_addr = match frame.kind {
+                        FrameKind::Jsr => pushed.wrapping_add(1),
+                        _ => pushed,
+                    };
+                }
+                if frame.status.is_some() {
+                    frame.status = peek(frame.sp.wrapping_sub(2)).or(frame.status);
+                }
+                frame
+            })
+            .collect()
+    }
+
     fn jsr(&mut self, addr: u16) {
-        let ret = self.regs.pc;
+        let ret = self.regs.pc.wrapping_sub(1);
+        let sp = self.regs.sp;
         self.push(((ret & 0xff00) >> 8) as u8);
         self.push((ret & 0x00ff) as u8);
+        self.push_frame(FrameKind::Jsr, sp, self.regs.pc, None);
         self.jmp(addr);
     }
 
     fn brk(&mut self) {
-        let ret = self.regs.pc;
-        self.push(((ret & 0xff00) >> 8) as u8);
-        self.push((ret & 0x00ff) as u8);
-        self.push(self.regs.p); 
-
-        let mut irq: u16 = (self.mem.read(0xffff) as u16) << 8;
-        irq += self.mem.read(0xfffe) as u16;
-
-        self.regs.pc = irq;////////////////////////////////////////////////////////////////B FLAG
+        self.regs.pc = self.regs.pc.wrapping_add(1); //padding byte, the return address skips it
+        self.push_interrupt(IRQ_VECTOR, FrameKind::Brk);
     }
 
     fn rti(&mut self) {
-        self.regs.p = self.pop();
+        let p = self.pop();
+        self.set_status(p);
         let mut pc: u16 = self.pop() as u16;
         pc += (self.pop() as u16) << 8;
         self.regs.pc = pc;
@@ -403,7 +1026,7 @@ impl Cpu {
     fn rts(&mut self) {
         let mut pc: u16 = self.pop() as u16;
         pc += (self.pop() as u16) << 8;
-        self.regs.pc = pc;
+        self.regs.pc = pc.wrapping_add(1);
     }
 
     fn lda(&mut self, value: u8) {
@@ -460,643 +1083,425 @@ impl Cpu {
         self.set_negative_flag(get_bit_at(self.regs.x, NEGATIVE) == SET)
     }
 
-    fn bit(&mut self, addr: u16) {
-        let mem = self.mem.read(addr);
+    fn bit_value(&mut self, mem: u8) {
         let and = self.regs.a & mem;
         self.set_zero_flag(and == 0);
         self.set_overflow_flag(get_bit_at(mem, OVERFLOW) == SET);
         self.set_negative_flag(get_bit_at(mem, NEGATIVE) == SET);
     }
 
-    pub fn next_instruction(&mut self) {
-        let opcode = self.mem.read(self.regs.pc);
-        self.regs.pc += 1;
-        let value: u8;
-        let addr: u16;
+    //undocumented opcodes
 
-        match opcode {
-            //ADC
-            0x69 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x65 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x75 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x6d => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x7d => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x79 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x61 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            0x71 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.adc(value);
-            },
-            //AND
-            0x29 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x25 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x35 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x2d => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x3d => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x39 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x21 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            0x31 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.and(value);
-            },
-            //ASL
-            0x0a => self.asl_acc(),
-            0x06 => {
-                addr = self.get_zero();
-                self.asl_mem(addr);
-            },
-            0x16 => {
-                addr = self.get_zero_x();
-                self.asl_mem(addr);
-            },
-            0x0e => {
-                addr = self.get_absolute();
-                self.asl_mem(addr);
-            },
-            0x1e => {
-                addr = self.get_absolute_x();
-                self.asl_mem(addr);
-            },
-            //BCC
-            0x90 => self.branch_if(CARRY, CLEAR),
-            //BCS
-            0xb0 => self.branch_if(CARRY, SET),
-            //BEQ
-            0xf0 => self.branch_if(ZERO, SET),
-            //BIT
-            0x24 => {
-                addr = self.get_zero();
-                self.bit(addr);
-            },
-            0x2c => {
-                addr = self.get_absolute();
-                self.bit(addr);
-            },
-            //BMI
-            0x30 => self.branch_if(NEGATIVE, SET),
-            //BNE
-            0xd0 => self.branch_if(ZERO, CLEAR),
-            //BPL
-            0x10 => self.branch_if(NEGATIVE, CLEAR),
-            //BRK
-            0x00 => self.brk(),
-            //BVC
-            0x50 => self.branch_if(OVERFLOW, CLEAR),
-            //BVS
-            0x70 => self.branch_if(OVERFLOW, SET),
-            //CLC
-            0x18 => self.set_carry_flag(false),
-            //CLD
-            0xd8 => self.set_decimal_flag(false),
-            //CLI
-            0x58 => self.set_interrupt_flag(false),
-            //CLV
-            0xb8 => self.set_overflow_flag(false),
-            //CMP
-            0xc9 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xc5 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xd5 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xcd => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xdd => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xd9 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xc1 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            0xd1 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.cmp(value);
-            },
-            //CPX
-            0xe0 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.cpx(value);
-            },
-            0xe4 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.cpx(value);
-            },
-            0xec => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.cpx(value);
-            },
-            //CPY
-            0xc0 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.cpy(value);
-            },
-            0xc4 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.cpy(value);
-            },
-            0xcc => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.cpy(value);
-            },
-            //DEC
-            0xc6 => {
-                addr = self.get_zero();
-                self.dec(addr);
-            },
-            0xd6 => {
-                addr = self.get_zero_x();
-                self.dec(addr);
-            },
-            0xce => {
-                addr = self.get_absolute();
-                self.dec(addr);
-            },
-            0xde => {
-                addr = self.get_absolute_x();
-                self.dec(addr);
-            },
-            //DEX
-            0xca => self.dex(),
-            //DEY
-            0x88 => self.dey(),
-            //EOR
-            0x49 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x45 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x55 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x4d => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x5d => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x59 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x41 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            0x51 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.eor(value);
-            },
-            //INC
-            0xe6 => {
-                addr = self.get_zero();
-                self.inc(addr);
-            },
-            0xf6 => {
-                addr = self.get_zero_x();
-                self.inc(addr);
-            },
-            0xee => {
-                addr = self.get_absolute();
-                self.inc(addr);
-            }, 
-            0xfe => {
-                addr = self.get_absolute_x();
-                self.inc(addr);
-            },
-            //INX
-            0xe8 => self.inx(),
-            //INY
-            0xc8 => self.iny(),
-            //JMP
-            0x4c => {
-                addr = self.get_absolute();
-                self.jmp(addr);
-            },
-            0x6c => {
-                addr = self.get_indirect();
-                self.jmp(addr);
-            },
-            //JSR
-            0x20 => {
-                addr = self.get_absolute();
-                self.jsr(addr);
-            },
-            //LDA
-            0xa9 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xa5 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xb5 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xad => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xbd => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xb9 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xa1 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            0xb1 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.lda(value);
-            },
-            //LDX
-            0xa2 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.ldx(value);
-            },
-            0xa6 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.ldx(value);
-            },
-            0xb6 => {
-                addr = self.get_zero_y();
-                value = self.mem.read(addr);
-                self.ldx(value);
-            },
-            0xae => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.ldx(value);
-            },
-            0xbe => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.ldx(value);
-            },
-            //LDY
-            0xa0 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.ldy(value);
-            },
-            0xa4 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.ldy(value);
-            },
-            0xb4 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.ldy(value);
-            },
-            0xac => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.ldy(value);
-            },
-            0xbc => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.ldy(value);
-            },
-            //LSR
-            0x4a => self.lsr_acc(),
-            0x46 => {
-                addr = self.get_zero();
-                self.lsr_mem(addr);
-            },
-            0x56 => {
-                addr = self.get_zero_x();
-                self.lsr_mem(addr);
-            },
-            0x4e => {
-                addr = self.get_absolute();
-                self.lsr_mem(addr);
-            },
-            0x5e => {
-                addr = self.get_absolute_x();
-                self.lsr_mem(addr);
-            },
-            //NOP
-            0xea => (),
-            //ORA
-            0x09 => {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            0x05 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            0x15 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            0x0d => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.ora(value);
-            }, 
-            0x1d => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            0x19 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            0x01 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            0x11 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.ora(value);
-            },
-            //PHA
-            0x48 => self.push(self.regs.a),
-            //PHP
-            0x08 => self.push(self.regs.p),
-            //PLA
-            0x68 => self.pla(),
-            //PLP
-            0x28 => self.regs.p = self.pop(),
-            //ROL
-            0x2a => self.rol_acc(),
-            0x26 => {
-                addr = self.get_zero();
-                self.rol_mem(addr);
-            },
-            0x36 => {
-                addr = self.get_zero_x();
-                self.rol_mem(addr);
-            },
-            0x2e => {
-                addr = self.get_absolute();
-                self.rol_mem(addr);
-            },
-            0x3e => {
-                addr = self.get_absolute_x();
-                self.rol_mem(addr);
-            },
-            //ROR
-            0x6a => self.ror_acc(),
-            0x66 => {
-                addr = self.get_zero();
-                self.ror_mem(addr);
-            },
-            0x76 => {
-                addr = self.get_zero_x();
-                self.ror_mem(addr);
-            },
-            0x6e => {
-                addr = self.get_absolute();
-                self.ror_mem(addr);
-            },
-            0x7e => {
-                addr = self.get_absolute_x();
-                self.ror_mem(addr);
-            },
-            //RTI
-            0x40 => self.rti(),
-            //RTS
-            0x60 => self.rts(),
-            //SBC
-            0xe9 =>  {
-                addr = self.get_immediate();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xe5 => {
-                addr = self.get_zero();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xf5 => {
-                addr = self.get_zero_x();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xed => {
-                addr = self.get_absolute();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xfd => {
-                addr = self.get_absolute_x();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xf9 => {
-                addr = self.get_absolute_y();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xe1 => {
-                addr = self.get_indirect_x();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            0xf1 => {
-                addr = self.get_indirect_y();
-                value = self.mem.read(addr);
-                self.sbc(value);
-            },
-            //SEC
-            0x38 => self.set_carry_flag(true),
-            //SED
-            0xf8 => self.set_decimal_flag(true),
-            //SEI
-            0x78 => self.set_interrupt_flag(true),
-            //STA
-            0x85 => {
-                addr = self.get_zero();
-                self.mem.write(addr, self.regs.a);
-            },
-            0x95 => {
-                addr = self.get_zero_x();
-                self.mem.write(addr, self.regs.a);
-            },
-            0x8d => {
-                addr = self.get_absolute();
-                self.mem.write(addr, self.regs.a);
-            },
-            0x9d => {
-                addr = self.get_absolute_x();
-                self.mem.write(addr, self.regs.a);
-            },
-            0x99 => {
-                addr = self.get_absolute_y();
-                self.mem.write(addr, self.regs.a);
-            },
-            0x81 => {
-                addr = self.get_indirect_x();
-                self.mem.write(addr, self.regs.a);
-            },
-            0x91 => {
-                addr = self.get_indirect_y();
-                self.mem.write(addr, self.regs.a);
-            },
-            //STX
-            0x86 => {
-                addr = self.get_zero();
-                self.mem.write(addr, self.regs.x);
-            },
-            0x96 => {
-                addr = self.get_zero_y();
-                self.mem.write(addr, self.regs.x);
-            },
-            0x8e => {
-                addr = self.get_absolute();
-                self.mem.write(addr, self.regs.x);
-            },
-            //STY
-            0x84 => {
-                addr = self.get_zero();
-                self.mem.write(addr, self.regs.y);
-            },
-            0x94 => {
-                addr = self.get_zero_x();
-                self.mem.write(addr, self.regs.y);
+    fn lax(&mut self, value: u8) {
+        self.lda(value);
+        self.regs.x = value;
+    }
+
+    fn anc(&mut self, value: u8) {
+        self.and(value);
+        self.set_carry_flag(get_bit_at(self.regs.a, NEGATIVE) == SET);
+    }
+
+    fn alr(&mut self, value: u8) {
+        self.and(value);
+        self.lsr_acc();
+    }
+
+    fn arr(&mut self, value: u8) {
+        if self.decimal_mode() {
+            self.arr_decimal(value);
+            return;
+        }
+        self.regs.a &= value;
+        self.ror_acc();
+        //c comes from bit 6 and v from bit 6 xor bit 5 of the result
+        self.set_carry_flag(get_bit_at(self.regs.a, 6) == SET);
+        self.set_overflow_flag(get_bit_at(self.regs.a, 6) ^ get_bit_at(self.regs.a, 5) == SET);
+    }
+
+    //nmos arr with d set: flags from the binary rotate, then each nibble is bcd fixed
+    fn arr_decimal(&mut self, value: u8) {
+        let and = self.regs.a & value;
+        let carry = self.regs.p & 0x01;
+        let mut result = (and >> 1) | (carry << 7);
+        self.set_negative_flag(carry == SET);
+        self.set_zero_flag(result == 0);
+        self.set_overflow_flag((and ^ result) & 0x40 != 0);
+        if (and & 0x0f) + (and & 0x01) > 0x05 {
+            result = (result & 0xf0) | (result.wrapping_add(0x06) & 0x0f);
+        }
+        let high_fix = (and & 0xf0) as u16 + (and & 0x10) as u16 > 0x50;
+        if high_fix {
+            result = result.wrapping_add(0x60);
+        }
+        self.set_carry_flag(high_fix);
+        self.regs.a = result;
+    }
+
+    fn axs(&mut self, value: u8) {
+        let and = self.regs.a & self.regs.x;
+        self.set_carry_flag(and >= value);
+        self.regs.x = and.wrapping_sub(value);
+        self.set_zero_flag(self.regs.x == 0);
+        self.set_negative_flag(get_bit_at(self.regs.x, NEGATIVE) == SET);
+    }
+
+    //xaa and lxa depend on analog effects, 0xee is the constant most
+    //2a03s and the common test suites agree on
+    fn xaa(&mut self, value: u8) {
+        self.lda((self.regs.a | 0xee) & self.regs.x & value);
+    }
+
+    fn lxa(&mut self, value: u8) {
+        self.lax((self.regs.a | 0xee) & value);
+    }
+
+    fn las(&mut self, value: u8) {
+        let result = value & self.regs.sp;
+        self.lax(result);
+        self.regs.sp = result;
+    }
+
+    //sha, shx, shy and tas store reg & (high byte of the base address + 1),
+    //when the index crosses a page that same value replaces the high byte
+    fn sh(&mut self, addr: u16, index: u8, reg: u8) {
+        let base = addr.wrapping_sub(index as u16);
+        let value = reg & ((base >> 8) as u8).wrapping_add(1);
+        let target = if crosses_page(base, addr) {
+            ((value as u16) << 8) | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.write(target, value);
+    }
+
+    fn kil(&mut self, opcode: u8, pc: u16) -> CpuError {
+        self.jammed = Some(opcode);
+        self.regs.pc = pc;
+        CpuError::Jammed { opcode, pc }
+    }
+
+    //65c02 only
+
+    //jmp ($xxxx) with the page wrap bug fixed (it costs the 65c02 one more cycle)
+    fn get_absolute_indirect(&mut self) -> u16 {
+        let ptr = self.get_absolute();
+        let mut addr: u16 = self.read(ptr) as u16;
+        addr += (self.read(ptr.wrapping_add(1)) as u16) << 8;
+        addr
+    }
+
+    fn get_absolute_indirect_x(&mut self) -> u16 {
+        let ptr = self.get_absolute().wrapping_add(self.regs.x as u16);
+        let mut addr: u16 = self.read(ptr) as u16;
+        addr += (self.read(ptr.wrapping_add(1)) as u16) << 8;
+        addr
+    }
+
+    fn bit_immediate(&mut self, value: u8) {
+        self.set_zero_flag(self.regs.a & value == 0);
+    }
+
+    //executes one instruction (or services a pending interrupt)
+    //and returns the cycles it took. CpuError::Break means a breakpoint stopped
+    //it, cycles() still counts whatever ran
+    pub fn next_instruction(&mut self) -> Result<u8, CpuError> {
+        if let Some(opcode) = self.jammed {
+            return Err(CpuError::Jammed {
+                opcode,
+                pc: self.regs.pc,
+            });
+        }
+        if !self.at_instruction_boundary() {
+            return self.finish_ticked_instruction();
+        }
+        if self.pending.is_none() && !self.waiting {
+            self.check_breakpoint()?;
+            self.trace();
+            self.log_code();
+            self.profile();
+        }
+        let result = self.step();
+        if let Err(e) = &result {
+            self.trace_error(e);
+        }
+        let cycles = result?;
+        self.take_watch_hit()?;
+        Ok(cycles)
+    }
+
+    fn step(&mut self) -> Result<u8, CpuError> {
+        if self.waiting {
+            //wai wakes up on any interrupt line, even a masked irq
+            if !self.nmi_pending && !self.irq_line {
+                self.cycles += 1;
+                return Ok(1);
+            }
+            self.waiting = false;
+            self.poll_interrupts(get_bit_at(self.regs.p, INTERRUPT) == SET);
+        }
+        if let Some(interrupt) = self.pending.take() {
+            self.service_interrupt(interrupt);
+            self.poll_interrupts(true);
+            self.cycles += INTERRUPT_CYCLES as u64;
+            return Ok(INTERRUPT_CYCLES);
+        }
+
+        //cli, sei and plp change i after the poll, so the old value is used for them
+        let i_before = get_bit_at(self.regs.p, INTERRUPT) == SET;
+        let pc = self.regs.pc;
+        let opcode = self.read(pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        self.page_crossed = false;
+        self.extra_cycles = 0;
+
+        let info = opcodes::table(self.variant)[opcode as usize];
+        if !info.official && self.illegal_policy != IllegalOpcodePolicy::Execute {
+            if self.illegal_policy == IllegalOpcodePolicy::Halt {
+                self.regs.pc = pc;
+                return Err(CpuError::IllegalOpcode {
+                    opcode,
+                    pc,
+                    regs: self.regs,
+                });
+            }
+            self.cycles += 2;
+            self.poll_interrupts(get_bit_at(self.regs.p, INTERRUPT) == SET);
+            return Ok(2);
+        }
+        self.execute(info, opcode, pc)?;
+
+        let mut cycles = info.cycles;
+        if self.page_crossed && info.page_penalty {
+            cycles += 1;
+        }
+        cycles += self.extra_cycles;
+        self.cycles += cycles as u64;
+
+        let irq_masked = match opcode {
+            0x58 | 0x78 | 0x28 => i_before,
+            _ => get_bit_at(self.regs.p, INTERRUPT) == SET,
+        };
+        self.poll_interrupts(irq_masked);
+        Ok(cycles)
+    }
+
+    //addressing mode and instruction both come from the opcode table
+    fn operand_address(&mut self, mode: Mode) -> u16 {
+        match mode {
+            Mode::Implied | Mode::Accumulator | Mode::Relative => 0,
+            Mode::ZeroPage | Mode::ZeroPageRelative => self.get_zero(),
+            Mode::Immediate => self.get_immediate(),
+            Mode::ZeroPageX => self.get_zero_x(),
+            Mode::ZeroPageY => self.get_zero_y(),
+            Mode::Absolute => self.get_absolute(),
+            Mode::AbsoluteX => self.get_absolute_x(),
+            Mode::AbsoluteY => self.get_absolute_y(),
+            Mode::Indirect if self.variant == CpuVariant::Cmos65C02 => self.get_absolute_indirect(),
+            Mode::Indirect => self.get_indirect(),
+            Mode::IndirectX => self.get_indirect_x(),
+            Mode::IndirectY => self.get_indirect_y(),
+            Mode::ZeroPageIndirect => self.get_zero_indirect(),
+            Mode::AbsoluteIndirectX => self.get_absolute_indirect_x(),
+        }
+    }
+
+    fn execute(&mut self, info: Opcode, opcode: u8, pc: u16) -> Result<(), CpuError> {
+        let instruction = info.instruction;
+        let addr = self.operand_address(info.mode);
+
+        match instruction.access() {
+            Access::Read if info.mode == Mode::Implied => (), //NOP
+            //65c02 bit #imm only sets z
+            Access::Read if instruction == Bit && info.mode == Mode::Immediate => {
+                let value = self.read(addr);
+                self.bit_immediate(value);
+            },
+            Access::Read => {
+                let value = self.read(addr);
+                self.read_op(instruction, value);
+            },
+            Access::Modify if info.mode == Mode::Accumulator => {
+                let value = self.regs.a;
+                self.regs.a = self.modify_op(instruction, value);
+            },
+            Access::Modify => {
+                let value = self.read(addr);
+                let result = self.modify_op(instruction, value);
+                self.write(addr, result);
+            },
+            Access::Write => self.store(instruction, info.mode, addr),
+            Access::None => match instruction {
+                Bcc | Bcs | Beq | Bmi | Bne | Bpl | Bvc | Bvs => {
+                    let taken = self.branch_condition(instruction);
+                    self.branch(taken);
+                },
+                Bra => self.branch(true),
+                Brk => self.brk(),
+                Jmp => self.jmp(addr),
+                Jsr => self.jsr(addr),
+                Rti => self.rti(),
+                Rts => self.rts(),
+                Pha => self.push(self.regs.a),
+                Php => self.push(self.regs.p | BREAK_BIT | UNUSED_BIT),
+                Pla => self.pla(),
+                Plp => {
+                    let value = self.pop();
+                    self.set_status(value);
+                },
+                Phx => self.push(self.regs.x),
+                Phy => self.push(self.regs.y),
+                Plx => {
+                    let value = self.pop();
+                    self.ldx(value);
+                },
+                Ply => {
+                    let value = self.pop();
+                    self.ldy(value);
+                },
+                Wai => self.waiting = true,
+                //pc stays on the stp, the same as kil
+                Stp => {
+                    self.jammed = Some(opcode);
+                    self.regs.pc = pc;
+                },
+                Kil => return Err(self.kil(opcode, pc)),
+                _ => match instruction.bit_operation() {
+                    //bbr / bbs
+                    Some((bit, set)) => {
+                        let value = self.read(addr);
+                        self.branch((get_bit_at(value, bit) == SET) == set);
+                    },
+                    None => self.implied_op(instruction),
+                },
             },
-            0x8c => {
-                addr = self.get_absolute();
-                self.mem.write(addr, self.regs.y);
+        }
+        Ok(())
+    }
+
+    fn branch_condition(&self, instruction: Instruction) -> bool {
+        let (flag, wanted) = match instruction {
+            Bcc => (CARRY, CLEAR),
+            Bcs => (CARRY, SET),
+            Beq => (ZERO, SET),
+            Bmi => (NEGATIVE, SET),
+            Bne => (ZERO, CLEAR),
+            Bpl => (NEGATIVE, CLEAR),
+            Bvc => (OVERFLOW, CLEAR),
+            _ => (OVERFLOW, SET), //BVS
+        };
+        get_bit_at(self.regs.p, flag) == wanted
+    }
+
+    //flag changes, transfers and register increments
+    fn implied_op(&mut self, instruction: Instruction) {
+        match instruction {
+            Clc => self.set_carry_flag(false),
+            Cld => self.set_decimal_flag(false),
+            Cli => self.set_interrupt_flag(false),
+            Clv => self.set_overflow_flag(false),
+            Sec => self.set_carry_flag(true),
+            Sed => self.set_decimal_flag(true),
+            Sei => self.set_interrupt_flag(true),
+            Dex => self.dex(),
+            Dey => self.dey(),
+            Inx => self.inx(),
+            Iny => self.iny(),
+            Tax => self.tax(),
+            Tay => self.tay(),
+            Tsx => self.tsx(),
+            Txa => self.txa(),
+            Tya => self.tya(),
+            Txs => {
+                self.regs.sp = self.regs.x;
+                self.drop_frames();
+            },
+            _ => (), //NOP
+        }
+    }
+
+    fn read_op(&mut self, instruction: Instruction, value: u8) {
+        match instruction {
+            Adc => self.adc(value),
+            And => self.and(value),
+            Bit => self.bit_value(value),
+            Cmp => self.cmp(value),
+            Cpx => self.cpx(value),
+            Cpy => self.cpy(value),
+            Eor => self.eor(value),
+            Lda => self.lda(value),
+            Ldx => self.ldx(value),
+            Ldy => self.ldy(value),
+            Ora => self.ora(value),
+            Sbc => self.sbc(value),
+            Lax => self.lax(value),
+            Las => self.las(value),
+            Anc => self.anc(value),
+            Alr => self.alr(value),
+            Arr => self.arr(value),
+            Axs => self.axs(value),
+            Xaa => self.xaa(value),
+            Lxa => self.lxa(value),
+            _ => (), //NOP
+        }
+    }
+
+    //returns the value to write back, the undocumented combos also run their
+    //second operation on it
+    fn modify_op(&mut self, instruction: Instruction, value: u8) -> u8 {
+        let result = match instruction {
+            Asl | Slo => self.asl(value),
+            Lsr | Sre => self.lsr(value),
+            Rol | Rla => self.rol(value),
+            Ror | Rra => self.ror(value),
+            Inc | Isc => self.increment(value),
+            Dec | Dcp => self.decrement(value),
+            Tsb => {
+                self.set_zero_flag(self.regs.a & value == 0);
+                value | self.regs.a
+            },
+            Trb => {
+                self.set_zero_flag(self.regs.a & value == 0);
+                value & !self.regs.a
+            },
+            //rmb / smb
+            _ => match instruction.bit_operation() {
+                Some((bit, true)) => value | 1 << bit,
+                Some((bit, false)) => value & !(1 << bit),
+                None => value,
+            },
+        };
+        match instruction {
+            Slo => self.ora(result),
+            Sre => self.eor(result),
+            Rla => self.and(result),
+            Rra => self.adc(result),
+            Isc => self.sbc(result),
+            Dcp => self.cmp(result),
+            _ => (),
+        }
+        result
+    }
+
+    fn store(&mut self, instruction: Instruction, mode: Mode, addr: u16) {
+        let index = match mode {
+            Mode::AbsoluteX => self.regs.x,
+            _ => self.regs.y,
+        };
+        match instruction {
+            Sta => self.write(addr, self.regs.a),
+            Stx => self.write(addr, self.regs.x),
+            Sty => self.write(addr, self.regs.y),
+            Sax => self.write(addr, self.regs.a & self.regs.x),
+            Sha => self.sh(addr, index, self.regs.a & self.regs.x),
+            Shx => self.sh(addr, index, self.regs.x),
+            Shy => self.sh(addr, index, self.regs.y),
+            Stz => self.write(addr, 0),
+            _ => {
+                //TAS
+                self.regs.sp = self.regs.a & self.regs.x;
+                self.sh(addr, index, self.regs.sp);
             },
-            //TAX
-            0xaa => self.tax(),
-            //TAY
-            0xa8 => self.tay(),
-            //TSX
-            0xba => self.tsx(),
-            //TXA
-            0x8a => self.txa(),
-            //TXS
-            0x9a => self.regs.sp = self.regs.x,
-            //TYA
-            0x98 => self.tya(),
-            _ => println!("Error"),
         }
     }
 }
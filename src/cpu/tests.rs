@@ -0,0 +1,618 @@
+use super::*;
+use crate::asm;
+use crate::asm::Program;
+use crate::harte::RecordingBus;
+
+//loads the program into flat ram and points pc at its first segment
+fn cpu_with(variant: CpuVariant, program: &Program) -> Cpu<Memory> {
+    let mut cpu = Cpu::default();
+    cpu.set_variant(variant);
+    program.write_to(cpu.bus_mut());
+    cpu.state_mut().set_pc(program.segments[0].0);
+    cpu.state_mut().set_sp(0xfd);
+    cpu
+}
+
+//runs n instructions and returns the cycles the last one took
+fn run(cpu: &mut Cpu<Memory>, n: usize) -> u8 {
+    let mut cycles = 0;
+    for _ in 0..n {
+        cycles = cpu.next_instruction().unwrap();
+    }
+    cycles
+}
+
+fn flags(cpu: &Cpu<Memory>) -> (bool, bool, bool, bool) {
+    let regs = cpu.state();
+    (regs.flag(NEGATIVE), regs.flag(OVERFLOW), regs.flag(ZERO), regs.flag(CARRY))
+}
+
+#[test]
+fn adc_sets_overflow_and_carry() {
+    let program = asm!(0x0600, "clc", "lda #$50", "adc #$50", "clc", "lda #$ff", "adc #$01");
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.state().a(), 0xa0);
+    assert_eq!(flags(&cpu), (true, true, false, false));
+    run(&mut cpu, 3);
+    assert_eq!(cpu.state().a(), 0x00);
+    assert_eq!(flags(&cpu), (false, false, true, true));
+}
+
+#[test]
+fn sbc_borrows_and_overflows() {
+    let program = asm!(0x0600, "sec", "lda #$50", "sbc #$b0", "sec", "lda #$00", "sbc #$01");
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.state().a(), 0xa0);
+    assert_eq!(flags(&cpu), (true, true, false, false));
+    run(&mut cpu, 3);
+    assert_eq!(cpu.state().a(), 0xff);
+    assert_eq!(flags(&cpu), (true, false, false, false));
+}
+
+#[test]
+fn compare_and_bit_flags() {
+    let program = asm!(
+        0x0600,
+        "lda #$40",
+        "cmp #$41",
+        "cmp #$40",
+        "lda #$c0",
+        "sta $10",
+        "lda #$01",
+        "bit $10"
+    );
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 2);
+    assert_eq!(flags(&cpu), (true, false, false, false));
+    run(&mut cpu, 1);
+    assert_eq!(flags(&cpu), (false, false, true, true));
+    run(&mut cpu, 4);
+    assert_eq!(flags(&cpu), (true, true, true, true));
+}
+
+#[test]
+fn indexed_reads_pay_for_page_crossings() {
+    let program = asm!(
+        0x0600,
+        "ldx #$01",
+        "ldy #$01",
+        "lda $10fe,x",
+        "lda $10ff,x",
+        "lda $10ff,y",
+        "sta $10ff,x", //stores always take the extra cycle
+        "lda #$ff",
+        "sta $20",
+        "lda #$10",
+        "sta $21",
+        "lda ($20),y"
+    );
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 2);
+    assert_eq!(run(&mut cpu, 1), 4);
+    assert_eq!(run(&mut cpu, 1), 5);
+    assert_eq!(run(&mut cpu, 1), 5);
+    assert_eq!(run(&mut cpu, 1), 5);
+    run(&mut cpu, 4);
+    assert_eq!(run(&mut cpu, 1), 6);
+}
+
+#[test]
+fn branches_pay_for_taken_and_page_crossings() {
+    let program = asm!(
+        0x06f0,
+        "clc",
+        "bcs skip", //not taken
+        "bcc near", //taken, same page
+        "skip: nop",
+        "near: ldx #$00",
+        "beq far", //taken, into the next page
+        ".org $0710",
+        "far: nop"
+    );
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 1);
+    assert_eq!(run(&mut cpu, 1), 2);
+    assert_eq!(run(&mut cpu, 1), 3);
+    run(&mut cpu, 1);
+    assert_eq!(run(&mut cpu, 1), 4);
+    assert_eq!(cpu.state().pc(), 0x0710);
+}
+
+#[test]
+fn decimal_mode_by_variant() {
+    let program = asm!(0x0600, "sed", "clc", "lda #$09", "adc #$01", "clc", "lda #$99", "adc #$01");
+
+    //the 2a03 has no bcd
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 4);
+    assert_eq!(cpu.state().a(), 0x0a);
+
+    let mut cpu = cpu_with(CpuVariant::Nmos6502, &program);
+    run(&mut cpu, 4);
+    assert_eq!(cpu.state().a(), 0x10);
+    assert_eq!(run(&mut cpu, 3), 2);
+    assert_eq!(cpu.state().a(), 0x00);
+    //z comes from the binary sum on the nmos chip
+    assert_eq!(flags(&cpu), (true, false, false, true));
+
+    let mut cpu = cpu_with(CpuVariant::Cmos65C02, &program);
+    run(&mut cpu, 6);
+    assert_eq!(run(&mut cpu, 1), 3);
+    assert_eq!(cpu.state().a(), 0x00);
+    assert_eq!(flags(&cpu), (false, false, true, true));
+}
+
+#[test]
+fn decimal_subtract() {
+    let program = asm!(0x0600, "sed", "sec", "lda #$10", "sbc #$01", "sec", "lda #$00", "sbc #$01");
+    let mut cpu = cpu_with(CpuVariant::Nmos6502, &program);
+    run(&mut cpu, 4);
+    assert_eq!(cpu.state().a(), 0x09);
+    assert!(cpu.state().flag(CARRY));
+    run(&mut cpu, 3);
+    assert_eq!(cpu.state().a(), 0x99);
+    assert!(!cpu.state().flag(CARRY));
+}
+
+#[test]
+fn jmp_indirect_wraps_inside_the_page() {
+    let program = asm!(
+        0x0600,
+        "jmp ($02ff)",
+        ".org $02ff",
+        ".byte $00",
+        ".org $0300",
+        ".byte $80",
+        ".org $0200",
+        ".byte $c0"
+    );
+    //the nmos chip takes the high byte from $0200
+    let mut cpu = cpu_with(CpuVariant::Nmos6502, &program);
+    assert_eq!(run(&mut cpu, 1), 5);
+    assert_eq!(cpu.state().pc(), 0xc000);
+
+    //the 65c02 reads $0300 and takes a cycle more
+    let mut cpu = cpu_with(CpuVariant::Cmos65C02, &program);
+    assert_eq!(run(&mut cpu, 1), 6);
+    assert_eq!(cpu.state().pc(), 0x8000);
+}
+
+#[test]
+fn jsr_and_rts_round_trip_through_the_stack() {
+    let program = asm!(0x0600, "jsr sub", "ldx #$01", "sub: ldy #$02", "rts");
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    assert_eq!(run(&mut cpu, 1), 6);
+    assert_eq!(cpu.state().sp(), 0xfb);
+    assert_eq!(cpu.stack_view()[0].return_addr, 0x0603);
+    run(&mut cpu, 1);
+    assert_eq!(run(&mut cpu, 1), 6);
+    assert_eq!(cpu.state().pc(), 0x0603);
+    assert_eq!(cpu.state().sp(), 0xfd);
+}
+
+#[test]
+fn cmos_instructions_come_from_the_65c02_table() {
+    let source = "
+        lda #$81
+        sta $10
+        stz $11
+        rmb0 $10
+        smb1 $11
+        bbs7 $10, skip
+        nop
+    skip:
+        lda #$08
+        tsb $11
+        bra done
+        nop
+    done:
+        phx
+        ply
+        lda ($10)";
+    let program = asm::assemble_for(source, 0x0600, CpuVariant::Cmos65C02).unwrap();
+    let mut cpu = cpu_with(CpuVariant::Cmos65C02, &program);
+    cpu.bus_mut().write(0x0a80, 0x42);
+    run(&mut cpu, 5);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x80);
+    assert_eq!(cpu.bus_mut().read(0x11), 0x02);
+    assert_eq!(run(&mut cpu, 1), 6); //5, plus one for the taken branch
+    run(&mut cpu, 2);
+    assert_eq!(cpu.bus_mut().read(0x11), 0x0a);
+    assert!(cpu.state().flag(ZERO)); //a & old value was 0
+    assert_eq!(run(&mut cpu, 1), 3);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.state().a(), 0x42);
+
+    //the nmos table has none of them
+    assert!(asm::assemble("stz $10", 0x0600).is_err());
+}
+
+//the cycle-stepped core, one bus access per tick
+
+fn ticking_cpu(program: &Program) -> Cpu<RecordingBus> {
+    let mut cpu = Cpu::new(RecordingBus::new());
+    program.write_to(cpu.bus_mut());
+    cpu.state_mut().set_pc(program.segments[0].0);
+    cpu.state_mut().set_sp(0xfd);
+    cpu
+}
+
+//ticks through one instruction, "r 0600" for a read and "w 0010 41" for a write
+fn bus_cycles(cpu: &mut Cpu<RecordingBus>) -> Vec<String> {
+    cpu.bus_mut().log.clear();
+    let mut ticks = 0;
+    loop {
+        ticks += 1;
+        if cpu.tick().unwrap() {
+            break;
+        }
+    }
+    let log = &cpu.bus().log;
+    assert_eq!(log.len(), ticks, "one access per cycle");
+    log.iter()
+        .map(|c| {
+            if c.write {
+                format!("w {:04X} {:02X}", c.addr, c.value)
+            } else {
+                format!("r {:04X}", c.addr)
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn indexed_read_repeats_the_read_on_a_page_cross() {
+    let program = asm!(0x0600, "lda $1000,x", "lda $10ff,x");
+    let mut cpu = ticking_cpu(&program);
+    cpu.state_mut().set_x(1);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0600", "r 0601", "r 0602", "r 1001"]);
+    //the first read goes to the address before the carry reached the high byte
+    assert_eq!(bus_cycles(&mut cpu), ["r 0603", "r 0604", "r 0605", "r 1000", "r 1100"]);
+}
+
+#[test]
+fn indexed_store_always_reads_first() {
+    let program = asm!(0x0600, "lda #$55", "sta $1000,x", "sta $10ff,x");
+    let mut cpu = ticking_cpu(&program);
+    cpu.state_mut().set_x(1);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0600", "r 0601"]);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0602", "r 0603", "r 0604", "r 1001", "w 1001 55"]);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0605", "r 0606", "r 0607", "r 1000", "w 1100 55"]);
+}
+
+#[test]
+fn implied_instructions_read_the_next_byte() {
+    let program = asm!(0x0600, "inx", "pha", "pla");
+    let mut cpu = ticking_cpu(&program);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0600", "r 0601"]);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0601", "r 0602", "w 01FD 00"]);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0602", "r 0603", "r 01FC", "r 01FD"]);
+}
+
+#[test]
+fn read_modify_write_writes_the_old_value_back_first() {
+    let program = asm!(0x0600, "inc $10", "asl $1234", ".org $0010", ".byte $41", ".org $1234", ".byte $41");
+    let mut cpu = ticking_cpu(&program);
+    assert_eq!(bus_cycles(&mut cpu), ["r 0600", "r 0601", "r 0010", "w 0010 41", "w 0010 42"]);
+    assert_eq!(
+        bus_cycles(&mut cpu),
+        ["r 0602", "r 0603", "r 0604", "r 1234", "w 1234 41", "w 1234 82"]
+    );
+}
+
+#[test]
+fn jsr_rts_and_rti_bus_cycles() {
+    let program = asm!(0x0600, "jsr sub", "rti", ".org $0700", "sub: rts");
+    let mut cpu = ticking_cpu(&program);
+    assert_eq!(
+        bus_cycles(&mut cpu),
+        ["r 0600", "r 0601", "r 01FD", "w 01FD 06", "w 01FC 02", "r 0602"]
+    );
+    assert_eq!(cpu.state().pc(), 0x0700);
+    assert_eq!(
+        bus_cycles(&mut cpu),
+        ["r 0700", "r 0701", "r 01FB", "r 01FC", "r 01FD", "r 0602"]
+    );
+    assert_eq!(cpu.state().pc(), 0x0603);
+
+    //rti pulls p, then pc, and doesn't add one to it
+    for (addr, value) in [(0x01fb, 0xc3), (0x01fc, 0x34), (0x01fd, 0x12)] {
+        cpu.bus_mut().write(addr, value);
+    }
+    cpu.state_mut().set_sp(0xfa);
+    assert_eq!(
+        bus_cycles(&mut cpu),
+        ["r 0603", "r 0604", "r 01FA", "r 01FB", "r 01FC", "r 01FD"]
+    );
+    assert_eq!(cpu.state().pc(), 0x1234);
+    assert_eq!(cpu.state().p(), 0xe3);
+}
+
+#[test]
+fn nmi_takes_over_a_brk() {
+    let program = asm!(
+        0x0600,
+        "brk",
+        ".org $fffa",
+        ".word $0800", //nmi
+        ".word $0000",
+        ".word $0900" //irq/brk
+    );
+    let mut cpu = ticking_cpu(&program);
+    cpu.state_mut().set_p(0x20);
+    cpu.bus_mut().log.clear();
+    cpu.tick().unwrap(); //opcode fetch
+    cpu.trigger_nmi();
+    let mut cycles = bus_cycles(&mut cpu);
+    cycles.insert(0, "r 0600".to_string());
+    //pushed with b set, but the vector is the nmi one
+    assert_eq!(
+        cycles,
+        ["r 0600", "r 0601", "w 01FD 06", "w 01FC 02", "w 01FB 30", "r FFFA", "r FFFB"]
+    );
+    assert_eq!(cpu.state().pc(), 0x0800);
+    assert_eq!(cpu.stack_view()[0].kind, FrameKind::Nmi);
+}
+
+//interrupts on the instruction-stepped core
+
+fn with_vectors(lines: &[&str]) -> Program {
+    let mut source = lines.join("\n");
+    source.push_str("\n.org $0700\nnmi: rti\n.org $0800\nirq: rti\n.org $fffa\n.word nmi, $0000, irq");
+    asm::assemble(&source, 0x0600).unwrap()
+}
+
+fn stack(cpu: &Cpu<Memory>) -> [u8; 3] {
+    let peek = |addr| cpu.bus().peek(addr).unwrap();
+    [peek(0x01fb), peek(0x01fc), peek(0x01fd)]
+}
+
+#[test]
+fn nmi_pushes_pc_and_status_without_b() {
+    let program = with_vectors(&["sec", "nop"]);
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.state_mut().set_p(0x24);
+    run(&mut cpu, 1);
+    cpu.trigger_nmi();
+    run(&mut cpu, 1); //the nmi is seen during the nop
+    assert_eq!(run(&mut cpu, 1), 7);
+    assert_eq!(cpu.state().pc(), 0x0700);
+    assert_eq!(cpu.state().sp(), 0xfa);
+    assert_eq!(stack(&cpu), [0x25, 0x02, 0x06]);
+    assert!(cpu.state().flag(INTERRUPT));
+    assert_eq!(cpu.stack_view()[0].kind, FrameKind::Nmi);
+
+    //rti puts both back
+    cpu.state_mut().set_p(0x26);
+    assert_eq!(run(&mut cpu, 1), 6);
+    assert_eq!(cpu.state().pc(), 0x0602);
+    assert_eq!(cpu.state().p(), 0x25);
+    assert!(cpu.stack_view().is_empty());
+}
+
+#[test]
+fn irq_waits_for_the_i_flag() {
+    let program = with_vectors(&["nop", "cli", "nop", "nop"]);
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.state_mut().set_p(0x24);
+    cpu.set_irq_line(true);
+    //masked, and cli only lets it in after the next instruction
+    assert_eq!(run(&mut cpu, 1), 2);
+    assert_eq!(run(&mut cpu, 1), 2);
+    assert_eq!(run(&mut cpu, 1), 2);
+    assert_eq!(cpu.state().pc(), 0x0603);
+    assert_eq!(run(&mut cpu, 1), 7);
+    assert_eq!(cpu.state().pc(), 0x0800);
+    assert_eq!(stack(&cpu), [0x20, 0x03, 0x06]);
+    assert_eq!(cpu.stack_view()[0].kind, FrameKind::Irq);
+}
+
+#[test]
+fn nmi_wins_over_irq() {
+    let program = with_vectors(&["nop"]);
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.state_mut().set_p(0x20);
+    cpu.set_irq_line(true);
+    cpu.trigger_nmi();
+    run(&mut cpu, 1);
+    assert_eq!(run(&mut cpu, 1), 7);
+    assert_eq!(cpu.state().pc(), 0x0700);
+}
+
+#[test]
+fn brk_skips_a_byte_and_pushes_b() {
+    let program = with_vectors(&["brk", ".byte $ff", "nop"]);
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.state_mut().set_p(0x20);
+    assert_eq!(run(&mut cpu, 1), 7);
+    assert_eq!(cpu.state().pc(), 0x0800);
+    assert_eq!(stack(&cpu), [0x30, 0x02, 0x06]);
+    assert!(cpu.state().flag(INTERRUPT));
+    assert_eq!(cpu.stack_view()[0].kind, FrameKind::Brk);
+    run(&mut cpu, 1);
+    assert_eq!(cpu.state().pc(), 0x0602);
+    assert_eq!(cpu.state().p(), 0x20); //b isn't a real flag
+}
+
+//illegal opcode policies and jams
+
+#[test]
+fn illegal_opcodes_follow_the_policy() {
+    let program = asm!(0x0600, "lda #$01", "lax $10", "nop");
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    run(&mut cpu, 1);
+    let regs = *cpu.state();
+    match cpu.next_instruction() {
+        Err(CpuError::IllegalOpcode { opcode: 0xa7, pc: 0x0602, regs: r }) => assert_eq!(r, regs),
+        other => panic!("expected an illegal opcode error, got {:?}", other),
+    }
+    assert_eq!(cpu.state().pc(), 0x0602);
+    assert_eq!(cpu.cycles(), 2);
+
+    //nop skips just the opcode byte, the operand runs as the next instruction
+    cpu.set_illegal_policy(IllegalOpcodePolicy::Nop);
+    assert_eq!(run(&mut cpu, 1), 2);
+    assert_eq!(cpu.state().pc(), 0x0603);
+    assert_eq!(cpu.state().a(), 0x01);
+}
+
+#[test]
+fn kil_and_stp_leave_pc_on_the_opcode() {
+    let program = asm!(0x0600, "nop", "jam");
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.set_illegal_policy(IllegalOpcodePolicy::Execute);
+    run(&mut cpu, 1);
+    let jammed = Err(CpuError::Jammed { opcode: 0x02, pc: 0x0601 });
+    assert_eq!(cpu.next_instruction(), jammed);
+    assert_eq!(cpu.state().pc(), 0x0601);
+    assert_eq!(cpu.next_instruction(), jammed);
+
+    let program = asm::assemble_for("nop\nstp", 0x0600, CpuVariant::Cmos65C02).unwrap();
+    let mut cpu = cpu_with(CpuVariant::Cmos65C02, &program);
+    run(&mut cpu, 1);
+    assert_eq!(run(&mut cpu, 1), 3);
+    assert_eq!(cpu.state().pc(), 0x0601);
+    assert_eq!(cpu.next_instruction(), Err(CpuError::Jammed { opcode: 0xdb, pc: 0x0601 }));
+}
+
+//runs a program on the nmos core with the undocumented opcodes enabled
+fn illegal_cpu(lines: &[&str]) -> Cpu<Memory> {
+    let program = asm::assemble(&lines.join("\n"), 0x0600).unwrap();
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.set_illegal_policy(IllegalOpcodePolicy::Execute);
+    cpu.state_mut().set_p(0x24);
+    run(&mut cpu, lines.len());
+    cpu
+}
+
+#[test]
+fn lax_and_sax() {
+    let cpu = illegal_cpu(&["lda #$80", "sta $10", "lda #$00", "lax $10"]);
+    assert_eq!((cpu.state().a(), cpu.state().x()), (0x80, 0x80));
+    assert_eq!(flags(&cpu), (true, false, false, false));
+
+    //sax stores a & x and leaves the flags alone
+    let mut cpu = illegal_cpu(&["lda #$f0", "ldx #$3c", "sax $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x30);
+    assert_eq!(flags(&cpu), (false, false, false, false));
+}
+
+#[test]
+fn read_modify_write_combos() {
+    //dcp: dec then cmp
+    let mut cpu = illegal_cpu(&["lda #$41", "sta $10", "lda #$40", "dcp $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x40);
+    assert_eq!(flags(&cpu), (false, false, true, true));
+
+    //isc: inc then sbc
+    let mut cpu = illegal_cpu(&["lda #$0f", "sta $10", "lda #$20", "sec", "isc $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x10);
+    assert_eq!(cpu.state().a(), 0x10);
+    assert_eq!(flags(&cpu), (false, false, false, true));
+
+    //slo: asl then ora
+    let mut cpu = illegal_cpu(&["lda #$81", "sta $10", "lda #$01", "slo $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x02);
+    assert_eq!(cpu.state().a(), 0x03);
+    assert_eq!(flags(&cpu), (false, false, false, true));
+
+    //rla: rol then and
+    let mut cpu = illegal_cpu(&["lda #$80", "sta $10", "lda #$ff", "sec", "rla $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x01);
+    assert_eq!(cpu.state().a(), 0x01);
+    assert_eq!(flags(&cpu), (false, false, false, true));
+
+    //sre: lsr then eor
+    let mut cpu = illegal_cpu(&["lda #$03", "sta $10", "lda #$ff", "sre $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x01);
+    assert_eq!(cpu.state().a(), 0xfe);
+    assert_eq!(flags(&cpu), (true, false, false, true));
+
+    //rra: ror then adc with the carry ror shifted out
+    let mut cpu = illegal_cpu(&["lda #$02", "sta $10", "lda #$01", "sec", "rra $10"]);
+    assert_eq!(cpu.bus_mut().read(0x10), 0x81);
+    assert_eq!(cpu.state().a(), 0x82);
+    assert_eq!(flags(&cpu), (true, false, false, false));
+}
+
+#[test]
+fn immediate_combos() {
+    //anc copies n into c
+    let cpu = illegal_cpu(&["lda #$ff", "anc #$80"]);
+    assert_eq!(cpu.state().a(), 0x80);
+    assert_eq!(flags(&cpu), (true, false, false, true));
+
+    let cpu = illegal_cpu(&["lda #$ff", "alr #$03"]);
+    assert_eq!(cpu.state().a(), 0x01);
+    assert_eq!(flags(&cpu), (false, false, false, true));
+
+    //arr: c from bit 6, v from bit 6 xor bit 5
+    let cpu = illegal_cpu(&["lda #$ff", "clc", "arr #$c0"]);
+    assert_eq!(cpu.state().a(), 0x60);
+    assert_eq!(flags(&cpu), (false, false, false, true));
+    let cpu = illegal_cpu(&["lda #$ff", "sec", "arr #$40"]);
+    assert_eq!(cpu.state().a(), 0xa0);
+    assert_eq!(flags(&cpu), (true, true, false, false));
+
+    //sbx: x = (a & x) - value, carry like cmp, a untouched
+    let cpu = illegal_cpu(&["lda #$f0", "ldx #$3c", "sbx #$10"]);
+    assert_eq!((cpu.state().a(), cpu.state().x()), (0xf0, 0x20));
+    assert_eq!(flags(&cpu), (false, false, false, true));
+    let cpu = illegal_cpu(&["lda #$f0", "ldx #$3c", "sbx #$31"]);
+    assert_eq!(cpu.state().x(), 0xff);
+    assert_eq!(flags(&cpu), (true, false, false, false));
+}
+
+#[test]
+fn sh_stores_and_the_high_byte() {
+    //no page cross: x & ($12 + 1) lands where it was aimed
+    let mut cpu = illegal_cpu(&["ldx #$05", "ldy #$01", "shx $1200,y"]);
+    assert_eq!(cpu.bus_mut().read(0x1201), 0x01);
+
+    //a page cross replaces the high byte of the address with the value
+    let mut cpu = illegal_cpu(&["ldx #$05", "ldy #$01", "shx $12ff,y"]);
+    assert_eq!(cpu.bus_mut().read(0x0100), 0x01);
+    assert_eq!(cpu.bus_mut().read(0x1300), 0x00);
+
+    let mut cpu = illegal_cpu(&["ldy #$06", "ldx #$02", "shy $12ff,x"]);
+    assert_eq!(cpu.bus_mut().read(0x0201), 0x02);
+
+    //sha stores a & x
+    let mut cpu = illegal_cpu(&["lda #$0b", "ldx #$0e", "ldy #$01", "sha $12ff,y"]);
+    assert_eq!(cpu.bus_mut().read(0x0200), 0x02);
+}
+
+//snapshots
+
+#[test]
+fn restoring_a_snapshot_replays_the_same_run() {
+    let program = with_vectors(&["jsr sub", "brk", "sub: inx", "iny", "rts"]);
+    let mut cpu = cpu_with(CpuVariant::Ricoh2A03, &program);
+    cpu.state_mut().set_p(0x24);
+    run(&mut cpu, 2);
+    cpu.trigger_nmi();
+    run(&mut cpu, 1); //the nmi is now pending behind the iny
+    let saved = cpu.snapshot();
+    let frames = cpu.stack_view();
+
+    assert_eq!(run(&mut cpu, 1), 7);
+    run(&mut cpu, 2);
+    let after = cpu.snapshot();
+    assert_ne!(after, saved);
+
+    cpu.restore(&saved);
+    assert_eq!(cpu.snapshot(), saved);
+    assert_eq!(*cpu.state(), *saved.state());
+    assert_eq!(cpu.cycles(), saved.cycles());
+    assert_eq!(cpu.stack_view(), frames);
+    assert_eq!(frames[0].kind, FrameKind::Jsr);
+
+    //the pending nmi comes back with it
+    assert_eq!(run(&mut cpu, 1), 7);
+    assert_eq!(cpu.state().pc(), 0x0700);
+    assert_eq!(cpu.stack_view()[0].kind, FrameKind::Nmi);
+    run(&mut cpu, 2);
+    assert_eq!(cpu.snapshot(), after);
+}
@@ -0,0 +1,564 @@
+use super::*;
+
+//cycle stepped execution: every tick is exactly one bus access, dummy reads and
+//the double write of read-modify-write instructions included, following the
+//cycle tables in 6502_cpu.txt. only the nmos timing is modelled
+
+//cycle (counting the opcode fetch as 0) in which the operand itself is accessed
+fn operand_step(mode: Mode) -> u8 {
+    match mode {
+        Mode::ZeroPage => 2,
+        Mode::ZeroPageX | Mode::ZeroPageY | Mode::Absolute => 3,
+        Mode::AbsoluteX | Mode::AbsoluteY => 4,
+        _ => 5, //(zp,x) and (zp),y
+    }
+}
+
+//latches of the instruction in flight
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct MicroState {
+    step: u8, //cycle of the current instruction, 0 = the next tick fetches an opcode
+    opcode: u8,
+    pc: u16,    //address the opcode was fetched from
+    addr: u16,  //effective address being built
+    base_hi: u8, //high byte of the address before indexing
+    crossed: bool,
+    ptr: u8, //zero page pointer of the indirect modes
+    data: u8,
+    interrupt: Option<Interrupt>, //running an interrupt sequence instead of an opcode
+    sample: (bool, bool),         //nmi / unmasked irq seen at the start of this cycle
+    prev_sample: (bool, bool),    //same, one cycle earlier
+    remaining: u8,                //65c02 fallback, cycles left of an atomic instruction
+}
+
+impl<B: Bus> Cpu<B> {
+    //runs one cycle, returns true when an instruction (or interrupt sequence) finished
+    pub fn tick(&mut self) -> Result<bool, CpuError> {
+        if let Some(opcode) = self.jammed {
+            return Err(CpuError::Jammed {
+                opcode,
+                pc: self.regs.pc,
+            });
+        }
+        if !self.variant.cycle_stepped() {
+            return self.tick_atomic();
+        }
+        if self.micro.step == 0 && self.pending.is_none() {
+            self.check_breakpoint()?;
+            self.trace();
+            self.log_code();
+            self.profile();
+        }
+        let result = self.tick_cycle();
+        if let Err(e) = &result {
+            self.trace_error(e);
+        }
+        let done = result?;
+        if done {
+            self.take_watch_hit()?;
+        }
+        Ok(done)
+    }
+
+    fn tick_cycle(&mut self) -> Result<bool, CpuError> {
+        self.micro.prev_sample = self.micro.sample;
+        self.micro.sample = (
+            self.nmi_pending,
+            self.irq_line && get_bit_at(self.regs.p, INTERRUPT) == CLEAR,
+        );
+        self.cycles += 1;
+
+        let step = self.micro.step;
+        self.micro.step += 1;
+        let done = if step == 0 {
+            self.tick_fetch()?;
+            false
+        } else if let Some(interrupt) = self.micro.interrupt {
+            self.tick_interrupt(step, Some(interrupt))
+        } else {
+            self.tick_opcode(step)?
+        };
+
+        if done {
+            self.micro.step = 0;
+        }
+        Ok(done)
+    }
+
+    //true if no instruction is half done, next_instruction can be used again
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.micro.step == 0 && self.micro.remaining == 0
+    }
+
+    //finishes an instruction started with tick, returns the cycles it still needed
+    pub(crate) fn finish_ticked_instruction(&mut self) -> Result<u8, CpuError> {
+        let mut cycles = 0;
+        loop {
+            cycles += 1;
+            if self.tick()? {
+                return Ok(cycles);
+            }
+        }
+    }
+
+    //the 65c02 has its own dummy cycles, there it runs the instruction on the
+    //first tick and idles for the rest
+    fn tick_atomic(&mut self) -> Result<bool, CpuError> {
+        if self.micro.remaining == 0 {
+            let cycles = self.next_instruction()?;
+            self.micro.remaining = cycles;
+        }
+        self.micro.remaining -= 1;
+        Ok(self.micro.remaining == 0)
+    }
+
+    //decides what runs after the instruction that ends in this cycle. the lines
+    //are sampled before the last cycle, or before the operand fetch for a taken
+    //branch that stays in the same page
+    fn tick_poll(&mut self, branch_delay: bool) {
+        let (nmi, irq) = if branch_delay {
+            self.micro.prev_sample
+        } else {
+            self.micro.sample
+        };
+        self.pending = if nmi && self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if irq {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
+    }
+
+    fn fetch_pc(&mut self) -> u8 {
+        let value = self.read(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        value
+    }
+
+    fn dummy_read_pc(&mut self) {
+        self.read(self.regs.pc);
+    }
+
+    fn dummy_read_stack(&mut self) {
+        self.read(STACK_PAGE | self.regs.sp as u16);
+    }
+
+    fn tick_fetch(&mut self) -> Result<(), CpuError> {
+        if let Some(interrupt) = self.pending.take() {
+            //the opcode fetch still happens but pc isn't incremented
+            self.dummy_read_pc();
+            self.micro.interrupt = Some(interrupt);
+            return Ok(());
+        }
+
+        self.micro.interrupt = None;
+        self.micro.pc = self.regs.pc;
+        self.micro.opcode = self.fetch_pc();
+        if !opcodes::table(self.variant)[self.micro.opcode as usize].official && self.illegal_policy != IllegalOpcodePolicy::Execute {
+            if self.illegal_policy == IllegalOpcodePolicy::Halt {
+                let pc = self.micro.pc;
+                self.regs.pc = pc;
+                self.micro.step = 0;
+                self.cycles -= 1;
+                return Err(CpuError::IllegalOpcode {
+                    opcode: self.micro.opcode,
+                    pc,
+                    regs: self.regs,
+                });
+            }
+            self.micro.opcode = 0xea; //run it as a plain nop
+        }
+        Ok(())
+    }
+
+    //steps 1-6 of brk, nmi and irq (None = brk)
+    fn tick_interrupt(&mut self, step: u8, interrupt: Option<Interrupt>) -> bool {
+        match step {
+            1 => {
+                if interrupt.is_some() {
+                    self.dummy_read_pc();
+                } else {
+                    self.fetch_pc(); //brk padding byte
+                }
+                false
+            }
+            2 => {
+                self.push((self.regs.pc >> 8) as u8);
+                false
+            }
+            3 => {
+                self.push(self.regs.pc as u8);
+                false
+            }
+            4 => {
+                //an nmi arriving up to here takes over the vector (even for brk)
+                let kind = if self.nmi_pending {
+                    self.nmi_pending = false;
+                    self.micro.addr = NMI_VECTOR;
+                    FrameKind::Nmi
+                } else {
+                    self.micro.addr = IRQ_VECTOR;
+                    if interrupt.is_some() {
+                        FrameKind::Irq
+                    } else {
+                        FrameKind::Brk
+                    }
+                };
+                let p = if interrupt.is_none() {
+                    self.regs.p | BREAK_BIT | UNUSED_BIT
+                } else {
+                    (self.regs.p & !BREAK_BIT) | UNUSED_BIT
+                };
+                let sp = self.regs.sp.wrapping_add(2); //before the pc was pushed
+                self.push(p);
+                self.push_frame(kind, sp, self.regs.pc, Some(p));
+                false
+            }
+            5 => {
+                self.micro.data = self.read(self.micro.addr);
+                self.set_interrupt_flag(true);
+                false
+            }
+            _ => {
+                let hi = self.read(self.micro.addr.wrapping_add(1));
+                self.regs.pc = ((hi as u16) << 8) | self.micro.data as u16;
+                self.tick_poll(false);
+                true
+            }
+        }
+    }
+
+    fn tick_opcode(&mut self, step: u8) -> Result<bool, CpuError> {
+        let opcode = self.micro.opcode;
+        let info = opcodes::table(self.variant)[opcode as usize];
+
+        let done = match info.instruction {
+            Brk => return Ok(self.tick_interrupt(step, None)),
+            Kil => {
+                self.dummy_read_pc();
+                let pc = self.micro.pc;
+                self.micro.step = 0;
+                return Err(self.kil(opcode, pc));
+            }
+            Rti => match step {
+                1 => {
+                    self.dummy_read_pc();
+                    false
+                }
+                2 => {
+                    self.dummy_read_stack();
+                    false
+                }
+                3 => {
+                    let p = self.pop();
+                    self.set_status(p);
+                    false
+                }
+                4 => {
+                    self.micro.data = self.pop();
+                    false
+                }
+                _ => {
+                    let hi = self.pop();
+                    self.regs.pc = ((hi as u16) << 8) | self.micro.data as u16;
+                    true
+                }
+            },
+            Rts => match step {
+                1 => {
+                    self.dummy_read_pc();
+                    false
+                }
+                2 => {
+                    self.dummy_read_stack();
+                    false
+                }
+                3 => {
+                    self.micro.data = self.pop();
+                    false
+                }
+                4 => {
+                    let hi = self.pop();
+                    self.regs.pc = ((hi as u16) << 8) | self.micro.data as u16;
+                    false
+                }
+                _ => {
+                    self.fetch_pc();
+                    true
+                }
+            },
+            Jsr => match step {
+                1 => {
+                    self.micro.data = self.fetch_pc();
+                    false
+                }
+                2 => {
+                    self.dummy_read_stack();
+                    false
+                }
+                3 => {
+                    self.push((self.regs.pc >> 8) as u8);
+                    false
+                }
+                4 => {
+                    self.push(self.regs.pc as u8);
+                    false
+                }
+                _ => {
+                    let hi = self.read(self.regs.pc);
+                    let sp = self.regs.sp.wrapping_add(2);
+                    let ret = self.regs.pc.wrapping_add(1);
+                    self.push_frame(FrameKind::Jsr, sp, ret, None);
+                    self.regs.pc = ((hi as u16) << 8) | self.micro.data as u16;
+                    true
+                }
+            },
+            Pha | Php => {
+                if step == 1 {
+                    self.dummy_read_pc();
+                    false
+                } else {
+                    let value = if info.instruction == Pha {
+                        self.regs.a
+                    } else {
+                        self.regs.p | BREAK_BIT | UNUSED_BIT
+                    };
+                    self.push(value);
+                    true
+                }
+            }
+            Pla | Plp => match step {
+                1 => {
+                    self.dummy_read_pc();
+                    false
+                }
+                2 => {
+                    self.dummy_read_stack();
+                    false
+                }
+                _ => {
+                    if info.instruction == Pla {
+                        self.pla();
+                    } else {
+                        let value = self.pop();
+                        self.set_status(value);
+                    }
+                    true
+                }
+            },
+            Jmp => self.tick_jmp(step, info.mode),
+            _ => match info.mode {
+                Mode::Accumulator => {
+                    self.dummy_read_pc();
+                    let value = self.regs.a;
+                    self.regs.a = self.modify_op(info.instruction, value);
+                    true
+                }
+                Mode::Implied => {
+                    self.dummy_read_pc();
+                    self.implied_op(info.instruction);
+                    true
+                }
+                Mode::Immediate => {
+                    let value = self.fetch_pc();
+                    self.read_op(info.instruction, value);
+                    true
+                }
+                Mode::Relative => return Ok(self.tick_branch(step, info.instruction)),
+                mode => self.tick_memory(step, mode, info.instruction),
+            },
+        };
+        if done {
+            self.tick_poll(false);
+        }
+        Ok(done)
+    }
+
+    fn tick_jmp(&mut self, step: u8, mode: Mode) -> bool {
+        match step {
+            1 => {
+                self.micro.data = self.fetch_pc();
+                false
+            }
+            2 if mode == Mode::Absolute => {
+                let hi = self.read(self.regs.pc);
+                self.regs.pc = ((hi as u16) << 8) | self.micro.data as u16;
+                true
+            }
+            2 => {
+                let hi = self.fetch_pc();
+                self.micro.addr = ((hi as u16) << 8) | self.micro.data as u16;
+                false
+            }
+            3 => {
+                self.micro.data = self.read(self.micro.addr);
+                false
+            }
+            _ => {
+                //the pointer increment doesn't carry into the high byte
+                let ptr = self.micro.addr;
+                let hi = self.read((ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff));
+                self.regs.pc = ((hi as u16) << 8) | self.micro.data as u16;
+                true
+            }
+        }
+    }
+
+    fn tick_branch(&mut self, step: u8, instruction: Instruction) -> bool {
+        match step {
+            1 => {
+                self.micro.data = self.fetch_pc();
+                if self.branch_condition(instruction) {
+                    return false;
+                }
+                self.tick_poll(false);
+                true
+            }
+            2 => {
+                self.dummy_read_pc();
+                let target = self.regs.pc.wrapping_add(self.micro.data as i8 as u16);
+                self.micro.addr = target;
+                if !crosses_page(self.regs.pc, target) {
+                    self.regs.pc = target;
+                    self.tick_poll(true);
+                    return true;
+                }
+                //pcl is fixed first, pch one cycle later
+                self.regs.pc = (self.regs.pc & 0xff00) | (target & 0x00ff);
+                false
+            }
+            _ => {
+                self.dummy_read_pc();
+                self.regs.pc = self.micro.addr;
+                self.tick_poll(false);
+                true
+            }
+        }
+    }
+
+    //addressing cycles, then the read, write or read-modify-write of the operand
+    fn tick_memory(&mut self, step: u8, mode: Mode, instruction: Instruction) -> bool {
+        let access = instruction.access();
+        let operand = operand_step(mode);
+
+        if step < operand {
+            match (mode, step) {
+                (Mode::ZeroPage, _) => self.micro.addr = self.fetch_pc() as u16,
+                (Mode::ZeroPageX, 1) | (Mode::ZeroPageY, 1) | (Mode::IndirectX, 1) | (Mode::IndirectY, 1) => {
+                    self.micro.ptr = self.fetch_pc();
+                }
+                (Mode::ZeroPageX, _) | (Mode::ZeroPageY, _) => {
+                    self.read(self.micro.ptr as u16);
+                    let index = if mode == Mode::ZeroPageX {
+                        self.regs.x
+                    } else {
+                        self.regs.y
+                    };
+                    self.micro.addr = self.micro.ptr.wrapping_add(index) as u16;
+                }
+                (Mode::Absolute, 1) | (Mode::AbsoluteX, 1) | (Mode::AbsoluteY, 1) => {
+                    self.micro.data = self.fetch_pc();
+                }
+                (Mode::Absolute, _) => {
+                    let hi = self.fetch_pc();
+                    self.micro.addr = ((hi as u16) << 8) | self.micro.data as u16;
+                }
+                (Mode::AbsoluteX, 2) | (Mode::AbsoluteY, 2) => {
+                    let hi = self.fetch_pc();
+                    let index = if mode == Mode::AbsoluteX {
+                        self.regs.x
+                    } else {
+                        self.regs.y
+                    };
+                    self.index_address(hi, self.micro.data, index);
+                }
+                (Mode::IndirectX, 2) => {
+                    self.read(self.micro.ptr as u16);
+                    self.micro.ptr = self.micro.ptr.wrapping_add(self.regs.x);
+                }
+                (Mode::IndirectX, 3) | (Mode::IndirectY, 2) => {
+                    self.micro.data = self.read(self.micro.ptr as u16);
+                }
+                (Mode::IndirectX, _) => {
+                    let hi = self.read(self.micro.ptr.wrapping_add(1) as u16);
+                    self.micro.addr = ((hi as u16) << 8) | self.micro.data as u16;
+                }
+                (Mode::IndirectY, 3) => {
+                    let hi = self.read(self.micro.ptr.wrapping_add(1) as u16);
+                    self.index_address(hi, self.micro.data, self.regs.y);
+                }
+                _ => {
+                    //indexed modes: read from the address before the high byte is fixed.
+                    //a read that didn't cross a page is already the real one
+                    let unfixed = ((self.micro.base_hi as u16) << 8) | (self.micro.addr & 0x00ff);
+                    let value = self.read(unfixed);
+                    if access == Access::Read && !self.micro.crossed {
+                        self.read_op(instruction, value);
+                        return true;
+                    }
+                }
+            }
+            return false;
+        }
+
+        match (access, step - operand) {
+            (Access::Read, _) | (Access::None, _) => {
+                let value = self.read(self.micro.addr);
+                self.read_op(instruction, value);
+                true
+            }
+            (Access::Write, _) => {
+                let (addr, value) = self.write_value(instruction);
+                self.write(addr, value);
+                true
+            }
+            (Access::Modify, 0) => {
+                self.micro.data = self.read(self.micro.addr);
+                false
+            }
+            (Access::Modify, 1) => {
+                //the unmodified value is written back while the alu works
+                self.write(self.micro.addr, self.micro.data);
+                self.micro.data = self.modify_op(instruction, self.micro.data);
+                false
+            }
+            (Access::Modify, _) => {
+                self.write(self.micro.addr, self.micro.data);
+                true
+            }
+        }
+    }
+
+    fn index_address(&mut self, hi: u8, lo: u8, index: u8) {
+        let base = ((hi as u16) << 8) | lo as u16;
+        self.micro.base_hi = hi;
+        self.micro.addr = base.wrapping_add(index as u16);
+        self.micro.crossed = crosses_page(base, self.micro.addr);
+    }
+
+    //address and value of a store. sha/shx/shy/tas and the high byte glitch
+    //work like Cpu::sh
+    fn write_value(&mut self, instruction: Instruction) -> (u16, u8) {
+        let reg = match instruction {
+            Sta => return (self.micro.addr, self.regs.a),
+            Stx => return (self.micro.addr, self.regs.x),
+            Sty => return (self.micro.addr, self.regs.y),
+            Sax => return (self.micro.addr, self.regs.a & self.regs.x),
+            Shx => self.regs.x,
+            Shy => self.regs.y,
+            Tas => {
+                self.regs.sp = self.regs.a & self.regs.x;
+                self.regs.sp
+            }
+            _ => self.regs.a & self.regs.x, //SHA
+        };
+        let value = reg & self.micro.base_hi.wrapping_add(1);
+        let addr = if self.micro.crossed {
+            ((value as u16) << 8) | (self.micro.addr & 0x00ff)
+        } else {
+            self.micro.addr
+        };
+        (addr, value)
+    }
+}
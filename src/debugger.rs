@@ -0,0 +1,658 @@
+use crate::asm;
+use crate::breakpoints::{Break, Breakpoint, Condition, Context, Trigger};
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError, CpuVariant, FrameKind};
+use crate::disasm::{self, Line};
+use crate::opcodes::{self, Instruction};
+use crate::symbols::Symbols;
+use crate::trace;
+use crate::utils::*;
+use std::io::{self, BufRead, Write};
+
+//command line debugger on top of Cpu::next_instruction. commands come one per line
+//so a file piped into stdin replays a session. numbers are hex, $ and 0x optional,
+//addresses can also be labels from the symbol files. the step and ignore counts
+//are decimal
+
+//instructions a run command executes before giving up, there is no ctrl-c handling
+const RUN_LIMIT: u64 = 10_000_000;
+
+//lines shown before and after pc by a bare disasm
+const LINES_BEFORE: usize = 5;
+const LINES_AFTER: usize = 10;
+
+const HELP: &str = "\
+step|s [N]            run N instructions (default 1, N is decimal)
+next|n                step, running a jsr until it returns
+out|o                 run until the current subroutine or interrupt returns
+until|u ADDR          run until pc reaches ADDR
+continue|c            run until a breakpoint or error
+regs|r                show the registers
+set REG VALUE         a x y sp pc p, or a flag n v d i z c with 0/1
+mem|m ADDR [LEN]      hex dump, 64 bytes by default
+fill START END VALUE  set START..=END to VALUE
+poke ADDR VALUE...    write bytes from ADDR on (rom included)
+asm|a ADDR INSTR      assemble one instruction into memory
+disasm|d [ADDR] [N]   disassemble N (hex) lines from ADDR, around pc without ADDR
+symbols FILE          load labels from a .dbg, .nl or .mlb file
+break|b ADDR [if C]   stop before the instruction at ADDR, when C holds
+tbreak ADDR [if C]    same, deleted after it stops once
+watch RANGE [if C]    stop after a write to RANGE (ADDR or START-END)
+rwatch RANGE [if C]   same for reads, awatch for both
+condition ID [C]      set or clear the condition of a breakpoint
+ignore ID N           let the next N hits through (N is decimal)
+delete ID             remove a breakpoint
+enable ID, disable ID
+breaks                list breakpoints and watchpoints
+print EXPR            evaluate a condition expression
+conditions use a x y sp p pc, flags n v d i z c, frame, cycles, addr and value
+(watchpoints), [ADDR] for memory, decimal or $hex numbers and C operators,
+e.g. a == $40 && [$0300] > 3 && frame > 100
+stack|bt              return addresses on the stack
+reset                 reset the cpu
+quit|q";
+
+//why a run command gave control back
+enum Stop {
+    Done, //got where it was going
+    Break(Break),
+    Error(CpuError),
+    Limit,
+}
+
+pub struct Debugger<B: Bus> {
+    cpu: Cpu<B>,
+    symbols: Symbols,
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad number '{}'", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_number(text)?;
+    if value > 0xff {
+        return Err(format!("{} doesn't fit in a byte", text));
+    }
+    Ok(value as u8)
+}
+
+fn parse_id(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("bad breakpoint id '{}'", text))
+}
+
+fn argument<'a>(args: &[&'a str], index: usize, what: &str) -> Result<&'a str, String> {
+    args.get(index).copied().ok_or_else(|| format!("missing {}", what))
+}
+
+//count lines decoded one after the other from start
+fn decode_lines(peek: &dyn Fn(u16) -> Option<u8>, start: u16, count: usize, variant: CpuVariant) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = start;
+    while lines.len() < count {
+        match disasm::decode(peek, addr, variant) {
+            Some(line) => {
+                addr = addr.wrapping_add(line.len());
+                lines.push(line);
+            }
+            None => break,
+        }
+    }
+    lines
+}
+
+//disassembling backwards is guesswork: take the furthest start that still
+//decodes into an instruction boundary at pc and keep the last count lines
+fn lines_before(peek: &dyn Fn(u16) -> Option<u8>, pc: u16, count: usize, variant: CpuVariant) -> Vec<Line> {
+    for back in (1..=count as u32 * 3).rev() {
+        if back > pc as u32 {
+            continue;
+        }
+        let mut addr = pc as u32 - back;
+        let mut lines = Vec::new();
+        while addr < pc as u32 {
+            match disasm::decode(peek, addr as u16, variant) {
+                Some(line) => {
+                    addr += line.len() as u32;
+                    lines.push(line);
+                }
+                None => break,
+            }
+        }
+        if addr == pc as u32 {
+            let skip = lines.len().saturating_sub(count);
+            return lines.split_off(skip);
+        }
+    }
+    Vec::new()
+}
+
+impl<B: Bus> Debugger<B> {
+    pub fn new(cpu: Cpu<B>) -> Debugger<B> {
+        Debugger {
+            cpu,
+            symbols: Symbols::new(),
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    //a number or a label, rom labels resolve through the current banking
+    fn address(&self, text: &str) -> Result<u16, String> {
+        let bus = self.cpu.bus();
+        parse_number(text).or_else(|_| {
+            self.symbols
+                .address(text, &|a| bus.prg_offset(a))
+                .ok_or_else(|| format!("'{}' is neither a number nor a label", text))
+        })
+    }
+
+    //ADDR or START-END
+    fn range(&self, text: &str) -> Result<(u16, u16), String> {
+        match text.split_once('-') {
+            Some((start, end)) => Ok((self.address(start)?, self.address(end)?)),
+            None => self.address(text).map(|addr| (addr, addr)),
+        }
+    }
+
+    //"label (file.s:12)" for addr, whatever of the two is known
+    fn describe(&self, addr: u16) -> Option<String> {
+        let offset = self.cpu.bus().prg_offset(addr);
+        let label = self.symbols.label(addr, offset);
+        let source = self.symbols.source_line(addr, offset);
+        match (label, source) {
+            (Some(label), Some(source)) => Some(format!("{} ({})", label, source)),
+            (Some(label), None) => Some(label.to_string()),
+            (None, Some(source)) => Some(source.to_string()),
+            (None, None) => None,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu<B> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<B> {
+        &mut self.cpu
+    }
+
+    fn breakpoint_mut(&mut self, id: usize) -> Result<&mut Breakpoint, String> {
+        self.cpu
+            .breakpoints_mut()
+            .get_mut(id)
+            .ok_or_else(|| format!("no breakpoint {}", id))
+    }
+
+    //adds a breakpoint, condition is the text after "if"
+    fn add_breakpoint(
+        &mut self,
+        trigger: Trigger,
+        range: &str,
+        condition: Option<&str>,
+        one_shot: bool,
+    ) -> Result<usize, String> {
+        let (start, end) = self.range(range)?;
+        let condition = condition.map(Condition::parse).transpose()?;
+        let id = self.cpu.breakpoints_mut().add(trigger, start, end);
+        let b = self.breakpoint_mut(id)?;
+        b.condition = condition;
+        b.one_shot = one_shot;
+        Ok(id)
+    }
+
+    //reads commands until quit or end of input. interactive shows a prompt and
+    //repeats the last command on an empty line, otherwise commands are echoed
+    pub fn repl<R: BufRead>(&mut self, input: R, out: &mut dyn Write, interactive: bool) -> io::Result<()> {
+        let mut last = String::new();
+        writeln!(out, "{}", trace::nestest_line(&self.cpu))?;
+        let mut lines = input.lines();
+        loop {
+            if interactive {
+                write!(out, "(dbg) ")?;
+                out.flush()?;
+            }
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            let mut command = line.trim().to_string();
+            if command.starts_with('#') {
+                continue;
+            }
+            if command.is_empty() {
+                if !interactive || last.is_empty() {
+                    continue;
+                }
+                command = last.clone();
+            }
+            if !interactive {
+                writeln!(out, "> {}", command)?;
+            }
+            last = command.clone();
+            match self.execute(&command, out) {
+                Ok(true) => return Ok(()),
+                Ok(false) => (),
+                Err(message) => writeln!(out, "error: {}", message)?,
+            }
+        }
+    }
+
+    //runs one command, Ok(true) means quit
+    pub fn execute(&mut self, command: &str, out: &mut dyn Write) -> Result<bool, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (name.to_ascii_lowercase(), args),
+            None => return Ok(false),
+        };
+        //"... if condition" for the breakpoint commands
+        let (args, condition) = match args.iter().position(|w| w.eq_ignore_ascii_case("if")) {
+            Some(i) => (&args[..i], Some(args[i + 1..].join(" "))),
+            None => (args, None),
+        };
+        let result = match name.as_str() {
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(n) => n.parse::<u64>().map_err(|_| format!("bad count '{}'", n))?,
+                    None => 1,
+                };
+                if count == 0 {
+                    return Err("count must be at least 1".to_string());
+                }
+                let mut left = count;
+                let stop = self.run(&mut |_| {
+                    left -= 1;
+                    left == 0
+                });
+                self.report(stop, out)
+            }
+            "next" | "n" => {
+                let stop = self.step_over();
+                self.report(stop, out)
+            }
+            "out" | "o" | "finish" => {
+                let stop = self.step_out()?;
+                self.report(stop, out)
+            }
+            "until" | "u" => {
+                let target = self.address(argument(args, 0, "address")?)?;
+                let stop = self.run(&mut |cpu| cpu.state().pc() == target);
+                self.report(stop, out)
+            }
+            "continue" | "c" => {
+                let stop = self.run(&mut |_| false);
+                self.report(stop, out)
+            }
+            "regs" | "r" => self.show_regs(out),
+            "set" => {
+                self.set(argument(args, 0, "register")?, argument(args, 1, "value")?)?;
+                self.show_regs(out)
+            }
+            "mem" | "m" => {
+                let start = self.address(argument(args, 0, "address")?)?;
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len)? as u32,
+                    None => 0x40,
+                };
+                self.dump(start, len, out)
+            }
+            "fill" => {
+                let start = self.address(argument(args, 0, "start")?)?;
+                let end = self.address(argument(args, 1, "end")?)?;
+                let value = parse_byte(argument(args, 2, "value")?)?;
+                for addr in start..=end {
+                    self.cpu.bus_mut().poke(addr, value);
+                }
+                Ok(())
+            }
+            "poke" => {
+                let start = self.address(argument(args, 0, "address")?)?;
+                argument(args, 1, "value")?;
+                for (i, value) in args[1..].iter().enumerate() {
+                    let value = parse_byte(value)?;
+                    self.cpu.bus_mut().poke(start.wrapping_add(i as u16), value);
+                }
+                Ok(())
+            }
+            "asm" | "a" => {
+                let addr = self.address(argument(args, 0, "address")?)?;
+                argument(args, 1, "instruction")?;
+                let lines = self.patch(addr, &args[1..].join(" "))?;
+                self.write_lines(&lines, out)
+            }
+            "disasm" | "d" => {
+                let count = match args.get(1) {
+                    Some(n) => parse_number(n)? as usize,
+                    None => LINES_BEFORE + LINES_AFTER,
+                };
+                match args.first() {
+                    Some(addr) => self.show_disasm(self.address(addr)?, count, out),
+                    None => self.show_around_pc(out),
+                }
+            }
+            "break" | "b" | "tbreak" | "watch" | "rwatch" | "awatch" => {
+                let trigger = match name.as_str() {
+                    "watch" => Trigger::Write,
+                    "rwatch" => Trigger::Read,
+                    "awatch" => Trigger::ReadWrite,
+                    _ => Trigger::Execute,
+                };
+                let range = argument(args, 0, "address")?;
+                let id = self.add_breakpoint(trigger, range, condition.as_deref(), name == "tbreak")?;
+                let b = self.breakpoint_mut(id)?;
+                writeln!(out, "{}", b)
+            }
+            "condition" => {
+                let id = parse_id(argument(args, 0, "breakpoint id")?)?;
+                //the condition is whatever follows the id
+                let text = command.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+                let condition = if text.is_empty() {
+                    None
+                } else {
+                    Some(Condition::parse(&text)?)
+                };
+                self.breakpoint_mut(id)?.condition = condition;
+                Ok(())
+            }
+            "ignore" => {
+                let id = parse_id(argument(args, 0, "breakpoint id")?)?;
+                let count = argument(args, 1, "count")?;
+                let count = count.parse::<u64>().map_err(|_| format!("bad count '{}'", count))?;
+                let b = self.breakpoint_mut(id)?;
+                b.ignore = b.hits + count;
+                Ok(())
+            }
+            "delete" => {
+                let id = parse_id(argument(args, 0, "breakpoint id")?)?;
+                if !self.cpu.breakpoints_mut().remove(id) {
+                    return Err(format!("no breakpoint {}", id));
+                }
+                Ok(())
+            }
+            "enable" | "disable" => {
+                let id = parse_id(argument(args, 0, "breakpoint id")?)?;
+                self.breakpoint_mut(id)?.enabled = name == "enable";
+                Ok(())
+            }
+            "print" => {
+                let text = command.split_once(char::is_whitespace).map_or("", |(_, rest)| rest);
+                let condition = Condition::parse(text)?;
+                let bus = self.cpu.bus();
+                let regs = self.cpu.state();
+                let ctx = Context {
+                    regs,
+                    pc: regs.pc(),
+                    cycles: self.cpu.cycles(),
+                    peek: &|a| bus.peek(a),
+                    access: None,
+                };
+                let value = condition.evaluate(&ctx);
+                writeln!(out, "{} (${:X})", value, value)
+            }
+            "breaks" => self.show_breakpoints(out),
+            "symbols" => {
+                let path = argument(args, 0, "file")?;
+                self.symbols.load(path).map_err(|e| format!("{}: {}", path, e))?;
+                Ok(())
+            }
+            "stack" | "bt" => self.show_stack(out),
+            "reset" => {
+                self.cpu.reset();
+                writeln!(out, "{}", trace::nestest_line(&self.cpu))
+            }
+            "help" | "h" | "?" => writeln!(out, "{}", HELP),
+            "quit" | "q" | "exit" => return Ok(true),
+            _ => return Err(format!("unknown command '{}', try help", name)),
+        };
+        result.map_err(|e| e.to_string())?;
+        Ok(false)
+    }
+
+    //steps until done says so, a breakpoint is reached or the cpu fails.
+    //a breakpoint on the instruction we are stopped at doesn't fire again
+    fn run(&mut self, done: &mut dyn FnMut(&Cpu<B>) -> bool) -> Stop {
+        let pc = self.cpu.state().pc();
+        self.cpu.breakpoints_mut().resume_at(pc);
+        for _ in 0..RUN_LIMIT {
+            match self.cpu.next_instruction() {
+                Ok(_) => (),
+                Err(CpuError::Break(hit)) => return Stop::Break(hit),
+                Err(e) => return Stop::Error(e),
+            }
+            if done(&self.cpu) {
+                return Stop::Done;
+            }
+        }
+        Stop::Limit
+    }
+
+    fn step_over(&mut self) -> Stop {
+        let pc = self.cpu.state().pc();
+        let opcode = self.cpu.bus().peek(pc).unwrap_or(0);
+        if opcodes::table(self.cpu.variant())[opcode as usize].instruction != Instruction::Jsr {
+            return self.run(&mut |_| true);
+        }
+        //a recursive call passes the same return address with less stack
+        let ret = pc.wrapping_add(3);
+        let sp = self.cpu.state().sp();
+        self.run(&mut |cpu| cpu.state().pc() == ret && cpu.state().sp() >= sp)
+    }
+
+    fn step_out(&mut self) -> Result<Stop, String> {
+        let frame = match self.cpu.stack_view().first() {
+            Some(frame) => *frame,
+            None => return Err("no subroutine or interrupt to return from".to_string()),
+        };
+        Ok(self.run(&mut |cpu| {
+            cpu.state().pc() == frame.return_addr && cpu.state().sp() >= frame.sp
+        }))
+    }
+
+    fn report(&self, stop: Stop, out: &mut dyn Write) -> io::Result<()> {
+        match stop {
+            Stop::Done => (),
+            Stop::Break(hit) => writeln!(out, "{}", hit)?,
+            Stop::Error(e) => writeln!(out, "{}", e)?,
+            Stop::Limit => writeln!(out, "stopped after {} instructions", RUN_LIMIT)?,
+        }
+        if let Some(place) = self.describe(self.cpu.state().pc()) {
+            writeln!(out, "{}:", place)?;
+        }
+        writeln!(out, "{}", trace::nestest_line(&self.cpu))
+    }
+
+    fn show_regs(&self, out: &mut dyn Write) -> io::Result<()> {
+        let regs = self.cpu.state();
+        writeln!(
+            out,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{}",
+            regs.pc(),
+            regs.a(),
+            regs.x(),
+            regs.y(),
+            regs.sp(),
+            regs.p(),
+            trace::flags(regs.p()),
+            self.cpu.cycles()
+        )
+    }
+
+    fn set(&mut self, register: &str, value: &str) -> Result<(), String> {
+        let flag = match register.to_ascii_lowercase().as_str() {
+            "n" => NEGATIVE,
+            "v" => OVERFLOW,
+            "d" => DECIMAL,
+            "i" => INTERRUPT,
+            "z" => ZERO,
+            "c" => CARRY,
+            "pc" => {
+                self.cpu.state_mut().set_pc(parse_number(value)?);
+                return Ok(());
+            }
+            "a" | "x" | "y" | "sp" | "s" | "p" => {
+                let value = parse_byte(value)?;
+                let regs = self.cpu.state_mut();
+                match register.to_ascii_lowercase().as_str() {
+                    "a" => regs.set_a(value),
+                    "x" => regs.set_x(value),
+                    "y" => regs.set_y(value),
+                    "p" => regs.set_p(value),
+                    _ => regs.set_sp(value),
+                }
+                return Ok(());
+            }
+            _ => return Err(format!("unknown register '{}'", register)),
+        };
+        let set = match value {
+            "0" => false,
+            "1" => true,
+            _ => return Err(format!("flags take 0 or 1, not '{}'", value)),
+        };
+        self.cpu.state_mut().set_flag(flag, set);
+        Ok(())
+    }
+
+    //0300  00 01 02 ...  16 bytes a row, -- where the bus can't peek
+    fn dump(&self, start: u16, len: u32, out: &mut dyn Write) -> io::Result<()> {
+        let end = (start as u32 + len).min(0x10000);
+        let mut row = start as u32;
+        while row < end {
+            let bytes: Vec<String> = (row..end.min(row + 16))
+                .map(|addr| match self.cpu.bus().peek(addr as u16) {
+                    Some(value) => format!("{:02X}", value),
+                    None => "--".to_string(),
+                })
+                .collect();
+            writeln!(out, "{:04X}  {}", row, bytes.join(" "))?;
+            row += 16;
+        }
+        Ok(())
+    }
+
+    //assembles source at addr and pokes it in, returns the new code
+    fn patch(&mut self, addr: u16, source: &str) -> Result<Vec<Line>, String> {
+        let program = asm::assemble_for(source, addr, self.cpu.variant()).map_err(|e| e.message)?;
+        for (origin, bytes) in &program.segments {
+            for (i, value) in bytes.iter().enumerate() {
+                self.cpu.bus_mut().poke(origin.wrapping_add(i as u16), *value);
+            }
+        }
+        let len = program.bytes().len();
+        let peek = |a| self.cpu.bus().peek(a);
+        Ok(disasm::disassemble(&peek, addr, addr.wrapping_add(len.max(1) as u16 - 1), self.cpu.variant(), &|_| false))
+    }
+
+    fn show_disasm(&self, addr: u16, count: usize, out: &mut dyn Write) -> io::Result<()> {
+        let peek = |a| self.cpu.bus().peek(a);
+        let lines = decode_lines(&peek, addr, count, self.cpu.variant());
+        self.write_lines(&lines, out)
+    }
+
+    fn show_around_pc(&self, out: &mut dyn Write) -> io::Result<()> {
+        let peek = |a| self.cpu.bus().peek(a);
+        let pc = self.cpu.state().pc();
+        let mut lines = lines_before(&peek, pc, LINES_BEFORE, self.cpu.variant());
+        lines.extend(decode_lines(&peek, pc, LINES_AFTER, self.cpu.variant()));
+        self.write_lines(&lines, out)
+    }
+
+    //> marks pc, * an enabled breakpoint
+    fn write_lines(&self, lines: &[Line], out: &mut dyn Write) -> io::Result<()> {
+        let pc = self.cpu.state().pc();
+        let bus = self.cpu.bus();
+        for line in lines {
+            let current = if line.addr == pc { '>' } else { ' ' };
+            let stop = self.cpu.breakpoints().list().iter().any(|b| {
+                b.enabled && b.trigger == Trigger::Execute && b.start == line.addr
+            });
+            //a label comes out as a line of its own before the instruction
+            let text = disasm::listing(std::slice::from_ref(line), &self.symbols, &|a| bus.prg_offset(a));
+            if let Some((instruction, labels)) = text.split_last() {
+                for label in labels {
+                    writeln!(out, "   {}", label)?;
+                }
+                writeln!(out, "{}{} {}", current, if stop { '*' } else { ' ' }, instruction)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn show_breakpoints(&self, out: &mut dyn Write) -> io::Result<()> {
+        let breakpoints = self.cpu.breakpoints();
+        if breakpoints.is_empty() {
+            return writeln!(out, "no breakpoints");
+        }
+        for b in breakpoints.list() {
+            writeln!(out, "{}", b)?;
+        }
+        Ok(())
+    }
+
+    fn show_stack(&self, out: &mut dyn Write) -> io::Result<()> {
+        let frames = self.cpu.stack_view();
+        if frames.is_empty() {
+            return writeln!(out, "stack is empty");
+        }
+        for (i, frame) in frames.iter().enumerate() {
+            let kind = match frame.kind {
+                FrameKind::Jsr => "jsr",
+                FrameKind::Brk => "brk",
+                FrameKind::Nmi => "nmi",
+                FrameKind::Irq => "irq",
+            };
+            write!(out, "#{:<2} {} returns to {:04X}  SP:{:02X}", i, kind, frame.return_addr, frame.sp)?;
+            match frame.status {
+                Some(p) => writeln!(out, "  P:{:02X}", p)?,
+                None => writeln!(out)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    #[test]
+    fn scripted_session() {
+        let program = asm!(0x0600, "lda #$01", "sta $10", "loop: inx", "jmp loop");
+        let mut cpu = Cpu::default();
+        program.write_to(cpu.bus_mut());
+        cpu.state_mut().set_pc(0x0600);
+        cpu.state_mut().set_sp(0xfd);
+        let mut debugger = Debugger::new(cpu);
+
+        let script = "step 2\nbreak 0605 if x == 3\ncontinue\nmem 10 2\nset a 7f\nd 0600 2\nbogus\nquit\nstep\n";
+        let mut out = Vec::new();
+        debugger.repl(script.as_bytes(), &mut out, false).unwrap();
+        //commands after quit are never run
+        let expected = "\
+0600  A9 01     LDA #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0
+> step 2
+0604  E8        INX                             A:01 X:00 Y:00 P:24 SP:FD PPU:  0, 15 CYC:5
+> break 0605 if x == 3
+  1  exec   0605  hits 0  if x == 3
+> continue
+breakpoint 1 at 0605
+0605  4C 04 06  JMP $0604                       A:01 X:03 Y:00 P:24 SP:FD PPU:  0, 51 CYC:17
+> mem 10 2
+0010  01 00
+> set a 7f
+PC:0605 A:7F X:03 Y:00 SP:FD P:24 nvUbdIzc CYC:17
+> d 0600 2
+   0600  A9 01     lda #$01
+   0602  85 10     sta $10
+> bogus
+error: unknown command 'bogus', try help
+> quit
+";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
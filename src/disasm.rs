@@ -0,0 +1,284 @@
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuState, CpuVariant};
+use crate::opcodes::{self, Access, Instruction, Mode, Opcode};
+use crate::symbols::Symbols;
+use std::fmt;
+
+//turns memory into ca65 syntax assembly. bytes come from a peek function so the
+//same code works on a live bus, a prg-rom bank or any other slice
+
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: Option<Opcode>, //None for a data byte
+    pub operand: u16,           //operand value, branch targets already resolved
+    pub effective: Option<(u16, u8)>, //address the instruction touches and the value there
+}
+
+impl Line {
+    fn data(addr: u16, value: u8) -> Line {
+        Line {
+            addr,
+            bytes: vec![value],
+            opcode: None,
+            operand: 0,
+            effective: None,
+        }
+    }
+
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    //instruction text without address and bytes, e.g. "lda ($20),y"
+    pub fn text(&self) -> String {
+        self.text_with(&|_| None)
+    }
+
+    //same, with operand addresses that have a name (see symbols) replaced by it
+    pub fn text_with(&self, name: &dyn Fn(u16) -> Option<String>) -> String {
+        let opcode = match self.opcode {
+            Some(opcode) => opcode,
+            None => return format!(".byte ${:02X}", self.bytes[0]),
+        };
+        let mnemonic = ca65_mnemonic(opcode.instruction);
+        let v = self.operand;
+        let zero = name(v).unwrap_or_else(|| format!("${:02X}", v));
+        let wide = name(v).unwrap_or_else(|| format!("${:04X}", v));
+        let operand = match opcode.mode {
+            Mode::Implied => return mnemonic.to_string(),
+            Mode::Accumulator => "a".to_string(),
+            Mode::Immediate => format!("#${:02X}", v),
+            Mode::ZeroPage => zero,
+            Mode::ZeroPageX => format!("{},x", zero),
+            Mode::ZeroPageY => format!("{},y", zero),
+            //ca65 would pick zero page for a small address, a: forces absolute
+            Mode::Absolute if v < 0x100 => format!("a:{}", wide),
+            Mode::AbsoluteX if v < 0x100 => format!("a:{},x", wide),
+            Mode::AbsoluteY if v < 0x100 => format!("a:{},y", wide),
+            Mode::Absolute | Mode::Relative => wide,
+            Mode::AbsoluteX => format!("{},x", wide),
+            Mode::AbsoluteY => format!("{},y", wide),
+            Mode::Indirect => format!("({})", wide),
+            Mode::IndirectX => format!("({},x)", zero),
+            Mode::IndirectY => format!("({}),y", zero),
+            Mode::ZeroPageIndirect => format!("({})", zero),
+            Mode::AbsoluteIndirectX => format!("({},x)", wide),
+            //the operand is the branch target, the zero page address is the first byte
+            Mode::ZeroPageRelative => {
+                let zero = self.bytes[1] as u16;
+                let zero = name(zero).unwrap_or_else(|| format!("${:02X}", zero));
+                format!("{}, {}", zero, wide)
+            }
+        };
+        format!("{} {}", mnemonic, operand)
+    }
+
+    //full line as Display shows it, with names from text_with
+    pub fn format_with(&self, name: &dyn Fn(u16) -> Option<String>) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let text = self.text_with(name);
+        match self.effective {
+            Some((addr, value)) => format!(
+                "{:04X}  {:<8}  {:<16}; [${:04X}] = ${:02X}",
+                self.addr,
+                bytes.join(" "),
+                text,
+                addr,
+                value
+            ),
+            None => format!("{:04X}  {:<8}  {}", self.addr, bytes.join(" "), text),
+        }
+    }
+}
+
+//C000  4C F5 C5  jmp $C5F5
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(&|_| None))
+    }
+}
+
+//column the source line comments of a listing start at
+const SOURCE_COLUMN: usize = 40;
+
+//lines as text with labels on lines of their own and the source line each
+//instruction came from as a comment. prg_offset maps addresses into prg-rom
+pub fn listing(lines: &[Line], symbols: &Symbols, prg_offset: &dyn Fn(u16) -> Option<usize>) -> Vec<String> {
+    let namer = symbols.namer(prg_offset);
+    let mut out = Vec::new();
+    for line in lines {
+        let offset = prg_offset(line.addr);
+        //name+1 style labels inside a table aren't worth a line of their own
+        if let Some(label) = symbols.label(line.addr, offset).filter(|l| !l.contains('+')) {
+            out.push(format!("{}:", label));
+        }
+        let text = line.format_with(&namer);
+        match symbols.source_line(line.addr, offset) {
+            Some(source) => out.push(format!("{:<width$}; {}", text, source, width = SOURCE_COLUMN)),
+            None => out.push(text),
+        }
+    }
+    out
+}
+
+//names ca65 uses in 6502X mode where they differ from ours
+fn ca65_mnemonic(instruction: Instruction) -> String {
+    let name = match instruction {
+        Instruction::Kil => "jam",
+        Instruction::Xaa => "ane",
+        Instruction::Lxa => "lax",
+        i => i.name(),
+    };
+    name.to_ascii_lowercase()
+}
+
+//peek function over a prg-rom bank (or any slice) mapped at origin
+pub fn slice_peek(bytes: &[u8], origin: u16) -> impl Fn(u16) -> Option<u8> + '_ {
+    move |addr| bytes.get(addr.wrapping_sub(origin) as usize).copied()
+}
+
+//decodes the instruction at addr with the opcodes of variant, None if the opcode
+//byte can't be read. an operand that can't be read turns the opcode into a data byte
+pub fn decode(peek: &dyn Fn(u16) -> Option<u8>, addr: u16, variant: CpuVariant) -> Option<Line> {
+    let byte = peek(addr)?;
+    let opcode = opcodes::table(variant)[byte as usize];
+    let mut bytes = vec![byte];
+    for i in 1..opcode.size() as u16 {
+        match peek(addr.wrapping_add(i)) {
+            Some(b) => bytes.push(b),
+            None => return Some(Line::data(addr, byte)),
+        }
+    }
+
+    let mut operand = match bytes.len() {
+        1 => 0,
+        2 => bytes[1] as u16,
+        _ => bytes[1] as u16 | (bytes[2] as u16) << 8,
+    };
+    match opcode.mode {
+        Mode::Relative => operand = addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16),
+        Mode::ZeroPageRelative => operand = addr.wrapping_add(3).wrapping_add(bytes[2] as i8 as u16),
+        _ => (),
+    }
+    Some(Line {
+        addr,
+        bytes,
+        opcode: Some(opcode),
+        operand,
+        effective: None,
+    })
+}
+
+//disassembles start..=end. is_data marks addresses to show as .byte,
+//instructions that would run past end are cut into data bytes too
+pub fn disassemble(
+    peek: &dyn Fn(u16) -> Option<u8>,
+    start: u16,
+    end: u16,
+    variant: CpuVariant,
+    is_data: &dyn Fn(u16) -> bool,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let a = addr as u16;
+        let line = if is_data(a) {
+            peek(a).map(|value| Line::data(a, value))
+        } else {
+            decode(peek, a, variant).map(|line| {
+                let last = addr + line.len() as u32 - 1;
+                let overlaps = (addr + 1..=last).any(|b| b > end as u32 || is_data(b as u16));
+                if overlaps {
+                    Line::data(a, line.bytes[0])
+                } else {
+                    line
+                }
+            })
+        };
+        match line {
+            Some(line) => {
+                addr += line.len() as u32;
+                lines.push(line);
+            }
+            None => addr += 1,
+        }
+    }
+    lines
+}
+
+//fills in the effective address and value using the registers as they are now,
+//only meaningful for the instruction about to run
+pub fn resolve(line: &mut Line, regs: &CpuState, peek: &dyn Fn(u16) -> Option<u8>) {
+    let opcode = match line.opcode {
+        Some(opcode) if opcode.instruction.access() != Access::None => opcode,
+        _ => return,
+    };
+    let v = line.operand;
+    let zero_word = |zero: u8| {
+        let lo = peek(zero as u16).unwrap_or(0) as u16;
+        let hi = peek(zero.wrapping_add(1) as u16).unwrap_or(0) as u16;
+        lo | hi << 8
+    };
+    let addr = match opcode.mode {
+        Mode::ZeroPage | Mode::Absolute => v,
+        Mode::ZeroPageX => (v as u8).wrapping_add(regs.x()) as u16,
+        Mode::ZeroPageY => (v as u8).wrapping_add(regs.y()) as u16,
+        Mode::AbsoluteX => v.wrapping_add(regs.x() as u16),
+        Mode::AbsoluteY => v.wrapping_add(regs.y() as u16),
+        Mode::IndirectX => zero_word((v as u8).wrapping_add(regs.x())),
+        Mode::IndirectY => zero_word(v as u8).wrapping_add(regs.y() as u16),
+        Mode::ZeroPageIndirect => zero_word(v as u8),
+        _ => return,
+    };
+    if let Some(value) = peek(addr) {
+        line.effective = Some((addr, value));
+    }
+}
+
+//the instruction at pc, annotated with the current registers
+pub fn at_pc<B: Bus>(cpu: &Cpu<B>) -> Option<Line> {
+    let peek = |addr| cpu.bus().peek(addr);
+    let mut line = decode(&peek, cpu.state().pc(), cpu.variant())?;
+    resolve(&mut line, cpu.state(), &peek);
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_known_bytes() {
+        let bytes = [
+            0x4c, 0xf5, 0xc5, 0xa9, 0x10, 0xb1, 0x20, 0xd0, 0xfe, 0xad, 0x10, 0x00, 0x02, 0xea, 0x8d, 0xea,
+        ];
+        let peek = slice_peek(&bytes, 0xc000);
+        let lines = disassemble(&peek, 0xc000, 0xc00f, CpuVariant::Ricoh2A03, &|addr| addr == 0xc00d);
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "C000  4C F5 C5  jmp $C5F5",
+                "C003  A9 10     lda #$10",
+                "C005  B1 20     lda ($20),y",
+                "C007  D0 FE     bne $C007",
+                "C009  AD 10 00  lda a:$0010",
+                "C00C  02        jam",
+                "C00D  EA        .byte $EA",
+                "C00E  8D        .byte $8D", //the sta would run past the end
+                "C00F  EA        nop",
+            ]
+        );
+
+        //the same byte is a different instruction on the 65c02
+        let bytes = [0xdb, 0x00, 0x12];
+        let decoded = |variant| decode(&slice_peek(&bytes, 0xc000), 0xc000, variant).unwrap().text();
+        assert_eq!(decoded(CpuVariant::Ricoh2A03), "dcp $1200,y");
+        assert_eq!(decoded(CpuVariant::Cmos65C02), "stp");
+    }
+}
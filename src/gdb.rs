@@ -0,0 +1,528 @@
+use crate::breakpoints::{Break, Trigger};
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+//gdb remote serial protocol stub, one client per session. registers are laid out
+//like mame's 6502 stub (a x y p sp pc, pc little endian) and described by
+//target.xml, memory goes through Bus::peek and Bus::poke so reads have no side
+//effects and rom can be patched. Z0/Z1 become execute breakpoints, Z2-Z4
+//watchpoints, all through Cpu::breakpoints_mut
+
+const PACKET_SIZE: usize = 0x1000;
+
+//instructions a continue runs between checks for a ctrl-c from the client
+const POLL_INTERVAL: u32 = 4096;
+
+const INTERRUPT: u8 = 0x03;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.m6502.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+//signals in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+//a connection the stub can talk over
+pub trait Transport: Read + Write {
+    //a byte the client already sent, None if nothing is waiting. must not block
+    fn poll_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+macro_rules! socket_transport {
+    ($socket:ty) => {
+        impl Transport for $socket {
+            fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+                self.set_nonblocking(true)?;
+                let mut byte = [0];
+                let result = self.read(&mut byte);
+                self.set_nonblocking(false)?;
+                match result {
+                    Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(_) => Ok(Some(byte[0])),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    };
+}
+
+socket_transport!(TcpStream);
+#[cfg(unix)]
+socket_transport!(UnixStream);
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex_number(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+//"addr,len" as used by m, M and the Z packets
+fn addr_len(text: &str) -> Option<(u16, u32)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((hex_number(addr)? as u16, hex_number(len)?))
+}
+
+struct Session<'a, B: Bus, T: Transport> {
+    cpu: &'a mut Cpu<B>,
+    conn: T,
+    ack: bool, //until the client asks for QStartNoAckMode
+    points: HashMap<(u8, u16, u32), usize>, //Z packet type, addr and kind/len to breakpoint id
+    last_stop: String,
+    unread: VecDeque<u8>, //sent while the target ran, read before the connection
+}
+
+//serves one client until it detaches, kills the session or hangs up.
+//breakpoints the client left behind are removed
+pub fn serve<B: Bus, T: Transport>(cpu: &mut Cpu<B>, conn: T) -> io::Result<()> {
+    let mut session = Session {
+        cpu,
+        conn,
+        ack: true,
+        points: HashMap::new(),
+        last_stop: format!("S{:02x}", SIGTRAP),
+        unread: VecDeque::new(),
+    };
+    let result = session.run();
+    for id in session.points.values() {
+        session.cpu.breakpoints_mut().remove(*id);
+    }
+    match result {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(()),
+        result => result,
+    }
+}
+
+impl<'a, B: Bus, T: Transport> Session<'a, B, T> {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if let Some(byte) = self.unread.pop_front() {
+            return Ok(byte);
+        }
+        let mut byte = [0];
+        self.conn.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    //next packet body with escapes undone. acks and stray ctrl-c are skipped
+    fn receive(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                let byte = self.read_byte()?;
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                if byte == b'}' {
+                    let escaped = self.read_byte()?;
+                    sum = sum.wrapping_add(escaped);
+                    data.push(escaped ^ 0x20);
+                } else {
+                    data.push(byte);
+                }
+            }
+            let digits = [self.read_byte()?, self.read_byte()?];
+            let expected = std::str::from_utf8(&digits).ok().and_then(|d| u8::from_str_radix(d, 16).ok());
+            if !self.ack {
+                return Ok(data);
+            }
+            if expected == Some(sum) {
+                self.conn.write_all(b"+")?;
+                return Ok(data);
+            }
+            self.conn.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        loop {
+            self.conn.write_all(packet.as_bytes())?;
+            self.conn.flush()?;
+            if !self.ack || self.read_byte()? != b'-' {
+                return Ok(());
+            }
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = self.receive()?;
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            let reply = match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'X') => String::new(), //binary writes, gdb falls back to M
+                _ => self.handle(&packet),
+            };
+            self.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+    }
+
+    //reply to one packet, empty for the ones we don't support
+    fn handle(&mut self, packet: &str) -> String {
+        if !packet.is_char_boundary(1) {
+            return String::new();
+        }
+        let (command, args) = packet.split_at(1);
+        let reply = match command {
+            "?" => Some(self.last_stop.clone()),
+            "g" => Some(self.read_registers()),
+            "G" => from_hex(args).and_then(|bytes| self.write_registers(&bytes)),
+            "p" => hex_number(args).and_then(|n| self.read_register(n)),
+            "P" => args.split_once('=').and_then(|(n, value)| {
+                self.write_register(hex_number(n)?, &from_hex(value)?)
+            }),
+            "m" => addr_len(args).map(|(addr, len)| self.read_memory(addr, len)),
+            "M" => args.split_once(':').and_then(|(range, data)| {
+                let (addr, len) = addr_len(range)?;
+                let bytes = from_hex(data)?;
+                if bytes.len() != len as usize {
+                    return None;
+                }
+                self.write_memory(addr, &bytes);
+                Some("OK".to_string())
+            }),
+            "s" => Some(self.resume(args, true)),
+            "c" => Some(self.resume(args, false)),
+            "Z" => self.insert_point(args),
+            "z" => self.remove_point(args),
+            "H" => Some("OK".to_string()),
+            "T" => Some("OK".to_string()),
+            "q" | "Q" | "v" => return self.query(packet),
+            _ => return String::new(),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        let reply = match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            "qSymbol::" => "OK",
+            "vCont?" => "vCont;c;C;s;S",
+            _ if packet.starts_with("qSupported") => {
+                return format!(
+                    "PacketSize={:x};QStartNoAckMode+;swbreak+;hwbreak+;qXfer:features:read+;vContSupported+",
+                    PACKET_SIZE
+                )
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let range = &packet["qXfer:features:read:target.xml:".len()..];
+                return match addr_len(range) {
+                    Some((offset, len)) => {
+                        let xml = TARGET_XML.as_bytes();
+                        let start = (offset as usize).min(xml.len());
+                        let end = (start + len as usize).min(xml.len());
+                        let more = if end < xml.len() { 'm' } else { 'l' };
+                        format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]))
+                    }
+                    None => "E01".to_string(),
+                };
+            }
+            _ if packet.starts_with("qRcmd,") => return self.monitor(&packet["qRcmd,".len()..]),
+            _ if packet.starts_with("vCont;") => {
+                //a single action for the only thread, C and S ignore the signal
+                let action = packet["vCont;".len()..].split(';').next().unwrap_or("");
+                return match action.chars().next() {
+                    Some('s') | Some('S') => self.resume("", true),
+                    Some('c') | Some('C') => self.resume("", false),
+                    _ => "E01".to_string(),
+                };
+            }
+            _ => "",
+        };
+        reply.to_string()
+    }
+
+    //"monitor reset" from the gdb prompt
+    fn monitor(&mut self, command: &str) -> String {
+        let text = from_hex(command).map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string());
+        match text.as_deref() {
+            Some("reset") => {
+                self.cpu.reset();
+                "OK".to_string()
+            }
+            _ => to_hex(b"monitor commands: reset\n"),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let regs = self.cpu.state();
+        let pc = regs.pc();
+        to_hex(&[regs.a(), regs.x(), regs.y(), regs.p(), regs.sp(), pc as u8, (pc >> 8) as u8])
+    }
+
+    fn write_registers(&mut self, bytes: &[u8]) -> Option<String> {
+        if bytes.len() < 7 {
+            return None;
+        }
+        let regs = self.cpu.state_mut();
+        regs.set_a(bytes[0]);
+        regs.set_x(bytes[1]);
+        regs.set_y(bytes[2]);
+        regs.set_p(bytes[3]);
+        regs.set_sp(bytes[4]);
+        regs.set_pc(bytes[5] as u16 | (bytes[6] as u16) << 8);
+        Some("OK".to_string())
+    }
+
+    fn read_register(&self, n: u32) -> Option<String> {
+        let regs = self.cpu.state();
+        let value = match n {
+            0 => regs.a(),
+            1 => regs.x(),
+            2 => regs.y(),
+            3 => regs.p(),
+            4 => regs.sp(),
+            5 => return Some(to_hex(&regs.pc().to_le_bytes())),
+            _ => return None,
+        };
+        Some(to_hex(&[value]))
+    }
+
+    fn write_register(&mut self, n: u32, bytes: &[u8]) -> Option<String> {
+        let regs = self.cpu.state_mut();
+        match (n, bytes) {
+            (0, [value]) => regs.set_a(*value),
+            (1, [value]) => regs.set_x(*value),
+            (2, [value]) => regs.set_y(*value),
+            (3, [value]) => regs.set_p(*value),
+            (4, [value]) => regs.set_sp(*value),
+            (5, [lo, hi]) => regs.set_pc(*lo as u16 | (*hi as u16) << 8),
+            _ => return None,
+        }
+        Some("OK".to_string())
+    }
+
+    //bytes the bus can't peek read as 0
+    fn read_memory(&self, addr: u16, len: u32) -> String {
+        let len = len.min(PACKET_SIZE as u32 / 2);
+        let bytes: Vec<u8> = (0..len)
+            .map(|i| self.cpu.bus().peek(addr.wrapping_add(i as u16)).unwrap_or(0))
+            .collect();
+        to_hex(&bytes)
+    }
+
+    fn write_memory(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, value) in bytes.iter().enumerate() {
+            self.cpu.bus_mut().poke(addr.wrapping_add(i as u16), *value);
+        }
+    }
+
+    //s and c, with an optional address to resume at. returns the stop reply
+    fn resume(&mut self, addr: &str, step: bool) -> String {
+        if let Some(addr) = hex_number(addr) {
+            self.cpu.state_mut().set_pc(addr as u16);
+        }
+        let pc = self.cpu.state().pc();
+        self.cpu.breakpoints_mut().resume_at(pc);
+        let stop = match self.execute(step) {
+            Ok(stop) => stop,
+            Err(e) => format!("E{:02x}", e.raw_os_error().unwrap_or(1) as u8),
+        };
+        self.last_stop = stop.clone();
+        stop
+    }
+
+    fn execute(&mut self, step: bool) -> io::Result<String> {
+        let mut count = 0u32;
+        loop {
+            match self.cpu.next_instruction() {
+                Ok(_) => (),
+                Err(CpuError::Break(hit)) => return Ok(self.break_reply(&hit)),
+                Err(_) => return Ok(format!("S{:02x}", SIGILL)),
+            }
+            if step {
+                return Ok(format!("S{:02x}", SIGTRAP));
+            }
+            count += 1;
+            if count.is_multiple_of(POLL_INTERVAL) && self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    //true if the client sent ctrl-c, anything else it sent is kept for receive
+    fn interrupted(&mut self) -> io::Result<bool> {
+        while let Some(byte) = self.conn.poll_byte()? {
+            if byte == INTERRUPT {
+                return Ok(true);
+            }
+            self.unread.push_back(byte);
+        }
+        Ok(false)
+    }
+
+    //T05 with the reason gdb asked for through qSupported
+    fn break_reply(&self, hit: &Break) -> String {
+        let kind = self
+            .points
+            .iter()
+            .find(|(_, id)| **id == hit.id)
+            .map(|((kind, _, _), _)| *kind);
+        let reason = match (hit.trigger, kind) {
+            (Trigger::Execute, Some(1)) => "hwbreak:".to_string(),
+            (Trigger::Execute, _) => "swbreak:".to_string(),
+            (Trigger::Write, Some(2)) => format!("watch:{:04x}", hit.addr),
+            (_, Some(4)) => format!("awatch:{:04x}", hit.addr),
+            (_, _) => format!("rwatch:{:04x}", hit.addr),
+        };
+        format!("T{:02x}{};", SIGTRAP, reason)
+    }
+
+    //Z type,addr,kind: 0 and 1 are breakpoints, 2 write, 3 read and 4 access watchpoints
+    fn insert_point(&mut self, args: &str) -> Option<String> {
+        let (kind, range) = args.split_once(',')?;
+        let kind = hex_number(kind)? as u8;
+        let (addr, len) = addr_len(range)?;
+        let (trigger, end) = match kind {
+            0 | 1 => (Trigger::Execute, addr),
+            2 => (Trigger::Write, addr.wrapping_add(len.max(1) as u16 - 1)),
+            3 => (Trigger::Read, addr.wrapping_add(len.max(1) as u16 - 1)),
+            4 => (Trigger::ReadWrite, addr.wrapping_add(len.max(1) as u16 - 1)),
+            _ => return Some(String::new()),
+        };
+        if !self.points.contains_key(&(kind, addr, len)) {
+            let id = self.cpu.breakpoints_mut().add(trigger, addr, end.max(addr));
+            self.points.insert((kind, addr, len), id);
+        }
+        Some("OK".to_string())
+    }
+
+    fn remove_point(&mut self, args: &str) -> Option<String> {
+        let (kind, range) = args.split_once(',')?;
+        let kind = hex_number(kind)? as u8;
+        let (addr, len) = addr_len(range)?;
+        if let Some(id) = self.points.remove(&(kind, addr, len)) {
+            self.cpu.breakpoints_mut().remove(id);
+        }
+        Some("OK".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    //sends one packet and returns the reply, acks both ways while ack is set
+    fn exchange(conn: &mut TcpStream, packet: &str, ack: bool) -> String {
+        write!(conn, "${}#{:02x}", packet, checksum(packet.as_bytes())).unwrap();
+        if ack {
+            assert_eq!(next_byte(conn), b'+');
+        }
+        reply(conn, ack)
+    }
+
+    fn next_byte(conn: &mut TcpStream) -> u8 {
+        let mut byte = [0];
+        conn.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn reply(conn: &mut TcpStream, ack: bool) -> String {
+        assert_eq!(next_byte(conn), b'$');
+        let mut data = Vec::new();
+        loop {
+            match next_byte(conn) {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let digits = [next_byte(conn), next_byte(conn)];
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), 16).unwrap(), checksum(&data));
+        if ack {
+            conn.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn scripted_client_over_loopback() {
+        let program = asm!(0x0600, "lda #$42", "sta $10", "loop: jmp loop");
+        let mut cpu = Cpu::default();
+        program.write_to(cpu.bus_mut());
+        cpu.state_mut().set_pc(0x0600);
+        cpu.state_mut().set_sp(0xfd);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut conn = TcpStream::connect(address).unwrap();
+            conn.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let supported = exchange(&mut conn, "qSupported:swbreak+;hwbreak+", true);
+            assert!(supported.contains("swbreak+"));
+            assert!(supported.contains("QStartNoAckMode+"));
+            assert_eq!(exchange(&mut conn, "QStartNoAckMode", true), "OK");
+            assert_eq!(exchange(&mut conn, "g", false), "00000024fd0006");
+            assert_eq!(exchange(&mut conn, "Z0,0604,1", false), "OK");
+            assert_eq!(exchange(&mut conn, "c", false), "T05swbreak:;");
+            assert_eq!(exchange(&mut conn, "g", false), "42000024fd0406");
+            assert_eq!(exchange(&mut conn, "m0010,2", false), "4200");
+            assert_eq!(exchange(&mut conn, "z0,0604,1", false), "OK");
+
+            //the ? sent while the target runs isn't lost to the ctrl-c check
+            let c = format!("$c#{:02x}", checksum(b"c"));
+            let query = format!("$?#{:02x}", checksum(b"?"));
+            write!(conn, "{}{}", c, query).unwrap();
+            conn.write_all(&[INTERRUPT]).unwrap();
+            assert_eq!(reply(&mut conn, false), "S02");
+            assert_eq!(reply(&mut conn, false), "S02");
+            conn.write_all(format!("$k#{:02x}", checksum(b"k")).as_bytes()).unwrap();
+        });
+
+        let (conn, _) = listener.accept().unwrap();
+        serve(&mut cpu, conn).unwrap();
+        client.join().unwrap();
+        assert_eq!(cpu.state().pc(), 0x0604);
+        assert!(cpu.breakpoints_mut().is_empty());
+    }
+}
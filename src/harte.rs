@@ -0,0 +1,305 @@
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError, CpuVariant, IllegalOpcodePolicy};
+use crate::json::{self, Json};
+use crate::memory::Memory;
+use std::fmt;
+
+//runs the ProcessorTests / SingleStepTests 65x02 vectors (one json file per opcode)
+//against a flat ram bus that records every access
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusCycle {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+impl fmt::Display for BusCycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.write { "write" } else { "read" };
+        write!(f, "{} {:04X} = {:02X}", kind, self.addr, self.value)
+    }
+}
+
+pub struct State {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+pub struct TestCase {
+    pub name: String,
+    pub initial: State,
+    pub expected: State,
+    pub cycles: Vec<BusCycle>,
+}
+
+pub enum Mismatch {
+    Register {
+        name: &'static str,
+        expected: u16,
+        actual: u16,
+    },
+    Memory {
+        addr: u16,
+        expected: u8,
+        actual: u8,
+    },
+    CycleCount {
+        expected: usize,
+        actual: usize,
+    },
+    Bus {
+        cycle: usize,
+        expected: Option<BusCycle>,
+        actual: Option<BusCycle>,
+    },
+    Error(CpuError),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |c: &Option<BusCycle>| match c {
+            Some(c) => c.to_string(),
+            None => "nothing".to_string(),
+        };
+        match self {
+            Mismatch::Register {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{}: expected {:02X}, got {:02X}", name, expected, actual),
+            Mismatch::Memory {
+                addr,
+                expected,
+                actual,
+            } => write!(f, "[{:04X}]: expected {:02X}, got {:02X}", addr, expected, actual),
+            Mismatch::CycleCount { expected, actual } => {
+                write!(f, "cycles: expected {}, got {}", expected, actual)
+            }
+            Mismatch::Bus {
+                cycle,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "bus cycle {}: expected {}, got {}",
+                cycle,
+                show(expected),
+                show(actual)
+            ),
+            Mismatch::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub struct RecordingBus {
+    mem: Memory,
+    pub log: Vec<BusCycle>,
+}
+
+impl RecordingBus {
+    pub fn new() -> RecordingBus {
+        RecordingBus {
+            mem: Memory::new(),
+            log: Vec::new(),
+        }
+    }
+}
+
+impl Default for RecordingBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for RecordingBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.mem.read(addr);
+        self.log.push(BusCycle {
+            addr,
+            value,
+            write: false,
+        });
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.mem.write(addr, value);
+        self.log.push(BusCycle {
+            addr,
+            value,
+            write: true,
+        });
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.mem.peek(addr)
+    }
+}
+
+fn field(value: &Json, key: &str) -> Result<u64, String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_u64())
+        .ok_or(format!("missing or invalid \"{}\"", key))
+}
+
+fn parse_state(value: &Json) -> Result<State, String> {
+    let mut ram = Vec::new();
+    for entry in value.get("ram").and_then(|r| r.as_array()).unwrap_or(&[]) {
+        let pair = entry.as_array().unwrap_or(&[]);
+        match (pair.first().and_then(|v| v.as_u64()), pair.get(1).and_then(|v| v.as_u64())) {
+            (Some(addr), Some(val)) => ram.push((addr as u16, val as u8)),
+            _ => return Err("bad ram entry".to_string()),
+        }
+    }
+    Ok(State {
+        pc: field(value, "pc")? as u16,
+        s: field(value, "s")? as u8,
+        a: field(value, "a")? as u8,
+        x: field(value, "x")? as u8,
+        y: field(value, "y")? as u8,
+        p: field(value, "p")? as u8,
+        ram,
+    })
+}
+
+fn parse_cycle(value: &Json) -> Result<BusCycle, String> {
+    let items = value.as_array().unwrap_or(&[]);
+    let addr = items.first().and_then(|v| v.as_u64());
+    let val = items.get(1).and_then(|v| v.as_u64());
+    let kind = items.get(2).and_then(|v| v.as_str());
+    match (addr, val, kind) {
+        (Some(addr), Some(val), Some(kind)) => Ok(BusCycle {
+            addr: addr as u16,
+            value: val as u8,
+            write: kind == "write",
+        }),
+        _ => Err("bad cycle entry".to_string()),
+    }
+}
+
+pub fn parse_file(text: &str) -> Result<Vec<TestCase>, String> {
+    let root = json::parse(text)?;
+    let tests = root.as_array().ok_or("expected an array of tests")?;
+    let mut cases = Vec::new();
+    for (i, test) in tests.iter().enumerate() {
+        let name = test.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string();
+        let case = (|| {
+            let initial = parse_state(test.get("initial").ok_or("missing \"initial\"")?)?;
+            let expected = parse_state(test.get("final").ok_or("missing \"final\"")?)?;
+            let cycles = test
+                .get("cycles")
+                .and_then(|c| c.as_array())
+                .ok_or("missing \"cycles\"")?
+                .iter()
+                .map(parse_cycle)
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok::<TestCase, String>(TestCase {
+                name: name.clone(),
+                initial,
+                expected,
+                cycles,
+            })
+        })()
+        .map_err(|e| format!("test {} ({}): {}", i, name, e))?;
+        cases.push(case);
+    }
+    Ok(cases)
+}
+
+//bits 4 and 5 don't exist in the real register, don't compare them
+const STATUS_MASK: u8 = 0xcf;
+
+//the "6502" suite is the nmos chip, "nes6502" the 2a03 and "wdc65c02" the 65c02.
+//a variant without a cycle-stepped core only has its final state and cycle
+//count compared, the per-cycle bus log is left unchecked
+pub fn run_case(case: &TestCase, variant: CpuVariant, check_bus: bool) -> Vec<Mismatch> {
+    let mut bus = RecordingBus::new();
+    for (addr, value) in &case.initial.ram {
+        bus.mem.write(*addr, *value);
+    }
+    let mut cpu = Cpu::new(bus);
+    cpu.set_illegal_policy(IllegalOpcodePolicy::Execute);
+    cpu.set_variant(variant);
+    {
+        let state = cpu.state_mut();
+        state.set_pc(case.initial.pc);
+        state.set_sp(case.initial.s);
+        state.set_a(case.initial.a);
+        state.set_x(case.initial.x);
+        state.set_y(case.initial.y);
+        state.set_p(case.initial.p);
+    }
+
+    //stepped cycle by cycle so the bus log shows the dummy accesses
+    let mut mismatches = Vec::new();
+    let mut cycles = 0;
+    loop {
+        cycles += 1;
+        match cpu.tick() {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(e) => {
+                mismatches.push(Mismatch::Error(e));
+                return mismatches;
+            }
+        }
+    }
+
+    let state = *cpu.state();
+    let expected = &case.expected;
+    let registers: [(&'static str, u16, u16); 6] = [
+        ("pc", expected.pc, state.pc()),
+        ("s", expected.s as u16, state.sp() as u16),
+        ("a", expected.a as u16, state.a() as u16),
+        ("x", expected.x as u16, state.x() as u16),
+        ("y", expected.y as u16, state.y() as u16),
+        ("p", (expected.p & STATUS_MASK) as u16, (state.p() & STATUS_MASK) as u16),
+    ];
+    for (name, expected, actual) in registers.iter() {
+        if expected != actual {
+            mismatches.push(Mismatch::Register {
+                name,
+                expected: *expected,
+                actual: *actual,
+            });
+        }
+    }
+
+    for (addr, value) in &expected.ram {
+        let actual = cpu.bus().peek(*addr).unwrap_or(0);
+        if actual != *value {
+            mismatches.push(Mismatch::Memory {
+                addr: *addr,
+                expected: *value,
+                actual,
+            });
+        }
+    }
+
+    if cycles != case.cycles.len() {
+        mismatches.push(Mismatch::CycleCount {
+            expected: case.cycles.len(),
+            actual: cycles,
+        });
+    }
+
+    if check_bus && variant.cycle_stepped() {
+        let log = &cpu.bus().log;
+        let len = log.len().max(case.cycles.len());
+        if let Some(cycle) = (0..len).find(|i| log.get(*i) != case.cycles.get(*i)) {
+            mismatches.push(Mismatch::Bus {
+                cycle,
+                expected: case.cycles.get(cycle).copied(),
+                actual: log.get(cycle).copied(),
+            });
+        }
+    }
+    mismatches
+}
@@ -0,0 +1,131 @@
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuError, CpuVariant};
+use crate::memory::Memory;
+
+//runner for Klaus Dormann's 6502_functional_test and 6502_decimal_test.
+//both end in a jmp * (or a branch to itself), the trap address tells
+//whether everything passed
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Suite {
+    Functional,
+    Decimal,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    pub suite: Suite,
+    pub load_addr: u16,
+    pub start: u16,
+    pub success: Option<u16>, //trap address of the success loop, depends on the build
+    pub variant: CpuVariant,
+    pub max_instructions: u64,
+}
+
+//defaults for the binaries built from the unmodified sources
+pub const FUNCTIONAL: Config = Config {
+    suite: Suite::Functional,
+    load_addr: 0x0000,
+    start: 0x0400,
+    success: Some(0x3469),
+    variant: CpuVariant::Nmos6502,
+    max_instructions: 100_000_000,
+};
+
+pub const DECIMAL: Config = Config {
+    suite: Suite::Decimal,
+    load_addr: 0x0200,
+    start: 0x0200,
+    success: None,
+    variant: CpuVariant::Nmos6502,
+    max_instructions: 100_000_000,
+};
+
+//the functional test keeps the number of the running test here
+pub const TEST_CASE: u16 = 0x0200;
+//the decimal test leaves 0 here if it passed
+pub const DECIMAL_ERROR: u16 = 0x000b;
+
+#[derive(Debug)]
+pub enum Outcome {
+    Passed { trap: u16, cycles: u64 },
+    Failed { trap: u16, test: u8 },
+    Timeout { pc: u16 },
+    Error(CpuError),
+}
+
+pub fn setup(image: &[u8], config: &Config) -> Cpu<Memory> {
+    let mut mem = Memory::new();
+    mem.load(config.load_addr, image);
+    let mut cpu = Cpu::new(mem);
+    cpu.set_variant(config.variant);
+    cpu.state_mut().set_pc(config.start);
+    cpu
+}
+
+pub fn run(cpu: &mut Cpu<Memory>, config: &Config) -> Outcome {
+    for _ in 0..config.max_instructions {
+        let pc = cpu.state().pc();
+        if let Err(e) = cpu.next_instruction() {
+            return Outcome::Error(e);
+        }
+        if cpu.state().pc() != pc {
+            continue;
+        }
+
+        //trapped
+        let passed = match config.suite {
+            Suite::Functional => config.success == Some(pc),
+            Suite::Decimal => {
+                config.success.is_none_or(|s| s == pc)
+                    && cpu.bus().peek(DECIMAL_ERROR) == Some(0)
+            }
+        };
+        return if passed {
+            Outcome::Passed {
+                trap: pc,
+                cycles: cpu.cycles(),
+            }
+        } else {
+            let test = match config.suite {
+                Suite::Functional => cpu.bus().peek(TEST_CASE),
+                Suite::Decimal => cpu.bus().peek(DECIMAL_ERROR),
+            };
+            Outcome::Failed {
+                trap: pc,
+                test: test.unwrap_or(0),
+            }
+        };
+    }
+    Outcome::Timeout { pc: cpu.state().pc() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    //cargo test -- --ignored with the variable pointing at the assembled binary
+    fn run_suite(var: &str, config: &Config) {
+        let path = env::var(var).unwrap_or_else(|_| panic!("{} isn't set", var));
+        let image = fs::read(&path).unwrap();
+        let mut cpu = setup(&image, config);
+        match run(&mut cpu, config) {
+            Outcome::Passed { .. } => (),
+            outcome => panic!("{}: {:?}", path, outcome),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs KLAUS_FUNCTIONAL"]
+    fn functional_test_passes() {
+        run_suite("KLAUS_FUNCTIONAL", &FUNCTIONAL);
+    }
+
+    #[test]
+    #[ignore = "needs KLAUS_DECIMAL"]
+    fn decimal_test_passes() {
+        run_suite("KLAUS_DECIMAL", &DECIMAL);
+    }
+}
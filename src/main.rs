@@ -1,8 +1,702 @@
+pub mod asm;
+pub mod breakpoints;
+pub mod bus;
+pub mod cartridge;
+pub mod cdl;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod harte;
+pub mod json;
+pub mod klaus;
+pub mod mapper;
 pub mod memory;
+pub mod nes_bus;
+pub mod nestest;
+pub mod opcodes;
+pub mod profile;
+pub mod symbols;
+pub mod trace;
 pub mod utils;
 
+use bus::Bus;
+use cartridge::{Cartridge, PRG_BANK_SIZE};
+use cdl::CodeDataLogger;
+use cpu::{Cpu, CpuVariant, IllegalOpcodePolicy};
+use debugger::Debugger;
+use nes_bus::NesBus;
+use profile::Profiler;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process;
+use symbols::Symbols;
+use trace::{TraceFilter, TraceFormat, Tracer};
+
+fn usage(program: &str) -> ! {
+    eprintln!("usage: {} <rom.nes>", program);
+    eprintln!("       {} info <rom.nes>", program);
+    eprintln!("       {} nestest <nestest.nes> [nestest.log]", program);
+    eprintln!("       {} harte [--no-bus] [--variant V] <opcode.json>...", program);
+    eprintln!("             (65c02 runs whole instructions, its bus cycles are never compared)");
+    eprintln!("       {} klaus functional|decimal <test.bin> [--success ADDR] [--variant V]", program);
+    eprintln!("       {} disasm <rom.nes> [--bank N] [--org ADDR] [--range START-END] [--cdl FILE]", program);
+    eprintln!("             [--symbols FILE]... [--variant V]");
+    eprintln!("       {} trace <rom.nes> [--format nestest|fceux|mesen] [--out FILE] [--ring N]", program);
+    eprintln!("             [--pc START-END] [--bank N] [--frames FIRST-LAST] [--start ADDR] [--stop ADDR]");
+    eprintln!("             [--entry ADDR] [--instructions N] [--illegal] [--cdl FILE] [--symbols FILE]...");
+    eprintln!("       {} debug <rom.nes> [--entry ADDR] [--illegal] [--cdl FILE] [--symbols FILE]... < commands", program);
+    eprintln!("       {} gdb <rom.nes> [--listen HOST:PORT] [--unix PATH] [--entry ADDR] [--illegal] [--cdl FILE]", program);
+    eprintln!("       {} profile <rom.nes> [--frames N] [--instructions N] [--entry ADDR] [--illegal]", program);
+    eprintln!("             [--folded FILE] [--cdl FILE] [--symbols FILE]...");
+    eprintln!("symbol files: ca65 .dbg, fceux .nl (rom.nes.N.nl for bank N, rom.nes.ram.nl), mesen .mlb");
+    eprintln!("variants: 2a03, 6502, 65c02");
+    process::exit(1);
+}
+
+fn load_cartridge(path: &str) -> Cartridge {
+    match Cartridge::from_file(path) {
+        Ok(cart) => cart,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn info(path: &str) {
+    let cart = load_cartridge(path);
+    let h = &cart.header;
+    println!("format:      {:?}", h.format);
+    println!("mapper:      {}.{}", h.mapper, h.submapper);
+    println!("prg-rom:     {} bytes", h.prg_rom_size);
+    println!("chr-rom:     {} bytes", h.chr_rom_size);
+    println!("prg-ram:     {} bytes ({} battery backed)", h.prg_ram_size, h.prg_nvram_size);
+    println!("chr-ram:     {} bytes ({} battery backed)", h.chr_ram_size, h.chr_nvram_size);
+    println!("mirroring:   {:?}", h.mirroring);
+    println!("battery:     {}", h.battery);
+    println!("trainer:     {}", h.trainer);
+    println!("tv system:   {:?}", h.tv_system);
+    println!("console:     {:?}", h.console_type);
+}
+
+//without a reference log the trace is printed, with one only the first divergence is
+fn run_nestest(rom: &str, log: Option<&str>) {
+    let reference = log.map(|path| match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    });
+    let mut cpu = match nestest::setup(load_cartridge(rom)) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("{}: {}", rom, e);
+            process::exit(1);
+        }
+    };
+
+    let print = reference.is_none();
+    let outcome = nestest::run(&mut cpu, reference.as_deref(), |line| {
+        if print {
+            println!("{}", line);
+        }
+    });
+    let official = cpu.bus().peek(nestest::RESULT_OFFICIAL).unwrap_or(0);
+    let unofficial = cpu.bus().peek(nestest::RESULT_UNOFFICIAL).unwrap_or(0);
+
+    match outcome {
+        nestest::Outcome::Matched { lines } => {
+            if !print {
+                println!("{} lines match the reference log", lines);
+            }
+            println!("result codes: {:02X} {:02X}", official, unofficial);
+        }
+        nestest::Outcome::Diverged(d) => {
+            eprintln!("divergence at line {}:", d.line);
+            for line in &d.context {
+                eprintln!("    {}", line);
+            }
+            eprintln!("expected {}", d.expected);
+            eprintln!("actual   {}", d.actual);
+            eprintln!("         {}^", " ".repeat(d.column()));
+            process::exit(1);
+        }
+        nestest::Outcome::Failed { line, error } => {
+            eprintln!("line {}: {}", line, error);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_variant(name: &str) -> Option<CpuVariant> {
+    match name.to_ascii_lowercase().as_str() {
+        "2a03" | "nes" => Some(CpuVariant::Ricoh2A03),
+        "6502" | "nmos" => Some(CpuVariant::Nmos6502),
+        "65c02" | "cmos" => Some(CpuVariant::Cmos65C02),
+        _ => None,
+    }
+}
+
+//splits "--flag value" options from positional arguments
+fn parse_options<'a>(args: &'a [String], with_value: &[&str]) -> (Vec<&'a str>, Vec<(&'a str, &'a str)>) {
+    let mut positional = Vec::new();
+    let mut options = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if with_value.contains(&arg) {
+            match args.get(i + 1) {
+                Some(value) => options.push((arg, value.as_str())),
+                None => usage("nes-emulator"),
+            }
+            i += 2;
+        } else {
+            if arg.starts_with("--") {
+                options.push((arg, ""));
+            } else {
+                positional.push(arg);
+            }
+            i += 1;
+        }
+    }
+    (positional, options)
+}
+
+fn option<'a>(options: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    options.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+fn variant_option(options: &[(&str, &str)], default: CpuVariant) -> CpuVariant {
+    match option(options, "--variant") {
+        Some(name) => parse_variant(name).unwrap_or_else(|| usage("nes-emulator")),
+        None => default,
+    }
+}
+
+//failing cases printed per file
+const HARTE_SHOWN_FAILURES: usize = 3;
+
+fn run_harte(args: &[String]) {
+    let (paths, options) = parse_options(args, &["--variant"]);
+    let check_bus = option(&options, "--no-bus").is_none();
+    let variant = variant_option(&options, CpuVariant::Ricoh2A03);
+    let mut failed_files = 0;
+    for path in paths {
+        let cases = match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| harte::parse_file(&text))
+        {
+            Ok(cases) => cases,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed_files += 1;
+                continue;
+            }
+        };
+
+        let mut failures = 0;
+        for case in &cases {
+            let mismatches = harte::run_case(case, variant, check_bus);
+            if mismatches.is_empty() {
+                continue;
+            }
+            failures += 1;
+            if failures <= HARTE_SHOWN_FAILURES {
+                println!("{}: \"{}\"", path, case.name);
+                for m in &mismatches {
+                    println!("    {}", m);
+                }
+            }
+        }
+        let unchecked = if check_bus && !variant.cycle_stepped() {
+            ", bus cycles unchecked"
+        } else {
+            ""
+        };
+        println!("{}: {}/{} passed{}", path, cases.len() - failures, cases.len(), unchecked);
+        if failures > 0 {
+            failed_files += 1;
+        }
+    }
+    if failed_files > 0 {
+        process::exit(1);
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn run_klaus(args: &[String]) {
+    let (positional, options) = parse_options(args, &["--success", "--variant"]);
+    if positional.len() != 2 {
+        usage("nes-emulator");
+    }
+    let mut config = match positional[0] {
+        "functional" => klaus::FUNCTIONAL,
+        "decimal" => klaus::DECIMAL,
+        _ => usage("nes-emulator"),
+    };
+    if let Some(addr) = option(&options, "--success") {
+        config.success = Some(parse_hex(addr).unwrap_or_else(|| usage("nes-emulator")));
+    }
+    config.variant = variant_option(&options, config.variant);
+    let image = match fs::read(positional[1]) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}: {}", positional[1], e);
+            process::exit(1);
+        }
+    };
+
+    let mut cpu = klaus::setup(&image, &config);
+    match klaus::run(&mut cpu, &config) {
+        klaus::Outcome::Passed { trap, cycles } => {
+            println!("passed, trapped at {:04X} after {} cycles", trap, cycles)
+        }
+        klaus::Outcome::Failed { trap, test } => {
+            let what = match config.suite {
+                klaus::Suite::Functional => "test number",
+                klaus::Suite::Decimal => "error flag",
+            };
+            println!("failed, trapped at {:04X}, {} {:02X}", trap, what, test);
+            process::exit(1);
+        }
+        klaus::Outcome::Timeout { pc } => {
+            println!("no trap after {} instructions, pc {:04X}", config.max_instructions, pc);
+            process::exit(1);
+        }
+        klaus::Outcome::Error(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (start, end) = text.split_once('-')?;
+    let (start, end) = (parse_hex(start)?, parse_hex(end)?);
+    if start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+//every --symbols file, in the order given
+fn load_symbols(options: &[(&str, &str)]) -> Symbols {
+    let mut symbols = Symbols::new();
+    for (_, path) in options.iter().filter(|(name, _)| *name == "--symbols") {
+        if let Err(e) = symbols.load(path) {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    symbols
+}
+
+//without --range one 16 KiB prg bank is listed (the last one by default, at c000),
+//with it the cpu address space as mapped after power on
+fn run_disasm(args: &[String]) {
+    let (positional, options) = parse_options(args, &["--bank", "--org", "--range", "--cdl", "--symbols", "--variant"]);
+    if positional.len() != 1 {
+        usage("nes-emulator");
+    }
+    let cart = load_cartridge(positional[0]);
+    //unlike run and profile the log has to exist, and match the rom
+    let (prg_size, chr_size) = (cart.prg_rom.len(), cart.chr_rom.len());
+    let cdl = option(&options, "--cdl").map(|path| match CodeDataLogger::load(path, prg_size, chr_size) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    });
+    let cdl_flags = |offset: usize| cdl.as_ref().and_then(|log| log.prg().get(offset)).copied().unwrap_or(0);
+    let symbols = load_symbols(&options);
+    let variant = variant_option(&options, CpuVariant::Ricoh2A03);
+
+    let listing = match option(&options, "--range") {
+        Some(range) => {
+            let (start, end) = parse_range(range).unwrap_or_else(|| usage("nes-emulator"));
+            let mapper = match mapper::from_cartridge(cart) {
+                Ok(mapper) => mapper,
+                Err(e) => {
+                    eprintln!("{}: {}", positional[0], e);
+                    process::exit(1);
+                }
+            };
+            let bus = NesBus::new(mapper);
+            let peek = |addr| bus.peek(addr);
+            let is_data = |addr| {
+                let offset = bus.mapper().prg_offset(addr);
+                offset.is_some_and(|o| cdl::is_data(cdl_flags(o)))
+            };
+            let lines = disasm::disassemble(&peek, start, end, variant, &is_data);
+            disasm::listing(&lines, &symbols, &|addr| bus.prg_offset(addr))
+        }
+        None => {
+            let banks = cart.prg_rom.len().div_ceil(PRG_BANK_SIZE);
+            let bank = match option(&options, "--bank") {
+                Some(n) => n.parse().unwrap_or_else(|_| usage("nes-emulator")),
+                None => banks - 1,
+            };
+            if bank >= banks {
+                eprintln!("{}: bank {} out of range, the rom has {}", positional[0], bank, banks);
+                process::exit(1);
+            }
+            let org = match option(&options, "--org") {
+                Some(addr) => parse_hex(addr).unwrap_or_else(|| usage("nes-emulator")),
+                None if bank == banks - 1 => 0xc000,
+                None => 0x8000,
+            };
+            let offset = bank * PRG_BANK_SIZE;
+            let data = &cart.prg_rom[offset..cart.prg_rom.len().min(offset + PRG_BANK_SIZE)];
+            let end = org.saturating_add((data.len() - 1) as u16);
+            let peek = disasm::slice_peek(data, org);
+            let is_data = |addr: u16| cdl::is_data(cdl_flags(offset + addr.wrapping_sub(org) as usize));
+            let lines = disasm::disassemble(&peek, org, end, variant, &is_data);
+            //addresses outside the listed bank only get ram and register names
+            let prg_offset = |addr: u16| (org..=end).contains(&addr).then(|| offset + (addr - org) as usize);
+            disasm::listing(&lines, &symbols, &prg_offset)
+        }
+    };
+    for line in &listing {
+        println!("{}", line);
+    }
+}
+
+//instructions run by the trace command unless --instructions says otherwise
+const TRACE_INSTRUCTIONS: u64 = 100_000;
+
+fn parse_frames(text: &str) -> Option<(u64, u64)> {
+    let (first, last) = text.split_once('-')?;
+    Some((first.parse().ok()?, last.parse().ok()?))
+}
+
+//runs a rom from reset (or --entry) and logs what it executes. an illegal opcode
+//stops the run unless --illegal is given, with --ring only the last N lines are
+//written, when the run stops
+fn run_trace(args: &[String]) {
+    let (positional, options) = parse_options(
+        args,
+        &[
+            "--format",
+            "--out",
+            "--ring",
+            "--pc",
+            "--bank",
+            "--frames",
+            "--start",
+            "--stop",
+            "--entry",
+            "--instructions",
+            "--symbols",
+            "--cdl",
+        ],
+    );
+    if positional.len() != 1 {
+        usage("nes-emulator");
+    }
+    let hex = |name| option(&options, name).map(|v| parse_hex(v).unwrap_or_else(|| usage("nes-emulator")));
+    let number = |name| {
+        option(&options, name).map(|v| v.parse::<u64>().unwrap_or_else(|_| usage("nes-emulator")))
+    };
+
+    let format = match option(&options, "--format").unwrap_or("nestest") {
+        "nestest" => TraceFormat::Nestest,
+        "fceux" => TraceFormat::Fceux,
+        "mesen" => TraceFormat::Mesen,
+        _ => usage("nes-emulator"),
+    };
+    let mut tracer = match option(&options, "--out") {
+        Some(path) => Tracer::to_file(path, format).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }),
+        None => Tracer::new(Box::new(std::io::stdout()), format),
+    };
+    tracer.set_filter(TraceFilter {
+        pc: option(&options, "--pc").map(|r| parse_range(r).unwrap_or_else(|| usage("nes-emulator"))),
+        bank: number("--bank").map(|b| b as usize),
+        frames: option(&options, "--frames").map(|r| parse_frames(r).unwrap_or_else(|| usage("nes-emulator"))),
+        start: hex("--start"),
+        stop: hex("--stop"),
+    });
+    match number("--ring") {
+        Some(0) => usage("nes-emulator"),
+        Some(capacity) => tracer.set_ring_buffer(capacity as usize),
+        None => (),
+    }
+    tracer.set_symbols(load_symbols(&options));
+
+    let cart = load_cartridge(positional[0]);
+    let cdl = load_cdl(&options, &cart);
+    let mapper = match mapper::from_cartridge(cart) {
+        Ok(mapper) => mapper,
+        Err(e) => {
+            eprintln!("{}: {}", positional[0], e);
+            process::exit(1);
+        }
+    };
+    let mut cpu = Cpu::new(NesBus::new(mapper));
+    if option(&options, "--illegal").is_some() {
+        cpu.set_illegal_policy(IllegalOpcodePolicy::Execute);
+    }
+    cpu.reset();
+    if let Some(entry) = hex("--entry") {
+        cpu.state_mut().set_pc(entry);
+    }
+    cpu.set_tracer(Some(tracer));
+    cpu.set_code_data_logger(cdl);
+
+    let limit = number("--instructions").unwrap_or(TRACE_INSTRUCTIONS);
+    let mut failed = false;
+    for _ in 0..limit {
+        if let Err(e) = cpu.next_instruction() {
+            eprintln!("{}", e);
+            failed = true;
+            break;
+        }
+    }
+    if let Some(tracer) = cpu.tracer_mut() {
+        //an error has dumped the ring buffer already
+        if !failed && tracer.has_ring_buffer() {
+            tracer.dump("instruction limit reached");
+        }
+        tracer.flush();
+        if let Some(e) = tracer.error() {
+            eprintln!("trace: {}", e);
+            failed = true;
+        }
+    }
+    if !save_cdl(&options, &cpu) {
+        failed = true;
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+//--cdl FILE carries on with the log in FILE, or starts one if it doesn't exist yet
+fn load_cdl(options: &[(&str, &str)], cart: &Cartridge) -> Option<CodeDataLogger> {
+    let path = option(options, "--cdl")?;
+    let (prg_size, chr_size) = (cart.prg_rom.len(), cart.chr_rom.len());
+    if !Path::new(path).exists() {
+        return Some(CodeDataLogger::new(prg_size, chr_size));
+    }
+    match CodeDataLogger::load(path, prg_size, chr_size) {
+        Ok(cdl) => Some(cdl),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+//writes the log back to --cdl and prints how much of prg-rom it covers
+fn save_cdl<B: Bus>(options: &[(&str, &str)], cpu: &Cpu<B>) -> bool {
+    let (path, cdl) = match (option(options, "--cdl"), cpu.code_data_logger()) {
+        (Some(path), Some(cdl)) => (path, cdl),
+        _ => return true,
+    };
+    let (code, data, unlogged) = cdl.prg_coverage();
+    eprintln!("cdl: {} code, {} data, {} unlogged prg bytes", code, data, unlogged);
+    match cdl.save(path) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            false
+        }
+    }
+}
+
+//cpu on a nes bus, reset (or started at --entry) for the debuggers
+fn debug_cpu(rom: &str, options: &[(&str, &str)]) -> Cpu<NesBus> {
+    let cart = load_cartridge(rom);
+    let cdl = load_cdl(options, &cart);
+    let mapper = match mapper::from_cartridge(cart) {
+        Ok(mapper) => mapper,
+        Err(e) => {
+            eprintln!("{}: {}", rom, e);
+            process::exit(1);
+        }
+    };
+    let mut cpu = Cpu::new(NesBus::new(mapper));
+    if option(options, "--illegal").is_some() {
+        cpu.set_illegal_policy(IllegalOpcodePolicy::Execute);
+    }
+    cpu.reset();
+    if let Some(entry) = option(options, "--entry") {
+        cpu.state_mut().set_pc(parse_hex(entry).unwrap_or_else(|| usage("nes-emulator")));
+    }
+    cpu.set_code_data_logger(cdl);
+    cpu
+}
+
+//the debugger reads commands from stdin, piping a file in replays a session
+fn run_debug(args: &[String]) {
+    let (positional, options) = parse_options(args, &["--entry", "--symbols", "--cdl"]);
+    if positional.len() != 1 {
+        usage("nes-emulator");
+    }
+    let cpu = debug_cpu(positional[0], &options);
+
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut debugger = Debugger::new(cpu);
+    debugger.set_symbols(load_symbols(&options));
+    if let Err(e) = debugger.repl(stdin.lock(), &mut io::stdout(), interactive) {
+        eprintln!("debug: {}", e);
+        process::exit(1);
+    }
+    if !save_cdl(&options, debugger.cpu()) {
+        process::exit(1);
+    }
+}
+
+//default port of the gdb stub
+const GDB_ADDRESS: &str = "127.0.0.1:6502";
+
+//a socket left behind by an earlier session is removed so bind can reuse the
+//path, anything else there is an error rather than something to delete
+#[cfg(unix)]
+fn remove_stale_socket(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+//waits for one gdb client and serves it until it detaches
+fn run_gdb(args: &[String]) {
+    let (positional, options) = parse_options(args, &["--listen", "--unix", "--entry", "--cdl"]);
+    if positional.len() != 1 {
+        usage("nes-emulator");
+    }
+    let mut cpu = debug_cpu(positional[0], &options);
+
+    let result = match option(&options, "--unix") {
+        #[cfg(unix)]
+        Some(path) => {
+            remove_stale_socket(path).and_then(|_| UnixListener::bind(path)).and_then(|listener| {
+                eprintln!("waiting for gdb on {}", path);
+                let (conn, _) = listener.accept()?;
+                gdb::serve(&mut cpu, conn)
+            })
+        }
+        #[cfg(not(unix))]
+        Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets need a unix host")),
+        None => {
+            let address = option(&options, "--listen").unwrap_or(GDB_ADDRESS);
+            TcpListener::bind(address).and_then(|listener| {
+                eprintln!("waiting for gdb on {}", listener.local_addr()?);
+                let (conn, _) = listener.accept()?;
+                conn.set_nodelay(true)?;
+                gdb::serve(&mut cpu, conn)
+            })
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("gdb: {}", e);
+        process::exit(1);
+    }
+    if !save_cdl(&options, &cpu) {
+        process::exit(1);
+    }
+}
+
+//frames run by the profile command unless --frames says otherwise, ten seconds
+const PROFILE_FRAMES: u64 = 600;
+
+//prints the flat profile and the call graph, --folded writes stacks for flamegraph.pl
+fn run_profile(args: &[String]) {
+    let (positional, options) = parse_options(args, &["--frames", "--instructions", "--entry", "--symbols", "--folded", "--cdl"]);
+    if positional.len() != 1 {
+        usage("nes-emulator");
+    }
+    let number = |name| {
+        option(&options, name).map(|v| v.parse::<u64>().unwrap_or_else(|_| usage("nes-emulator")))
+    };
+    let mut cpu = debug_cpu(positional[0], &options);
+    let mut profiler = Profiler::new();
+    profiler.set_symbols(load_symbols(&options));
+    cpu.set_profiler(Some(profiler));
+
+    let last_frame = nes_bus::frame(cpu.cycles()) + number("--frames").unwrap_or(PROFILE_FRAMES);
+    let limit = number("--instructions").unwrap_or(u64::MAX);
+    let mut failed = false;
+    let mut instructions = 0;
+    while nes_bus::frame(cpu.cycles()) < last_frame && instructions < limit {
+        if let Err(e) = cpu.next_instruction() {
+            eprintln!("{}", e);
+            failed = true;
+            break;
+        }
+        instructions += 1;
+    }
+
+    let cycles = cpu.cycles();
+    let profiler = cpu.profiler_mut().unwrap();
+    profiler.finish(cycles);
+    for line in profiler.flat() {
+        println!("{}", line);
+    }
+    println!();
+    for line in profiler.call_graph() {
+        println!("{}", line);
+    }
+    if let Some(path) = option(&options, "--folded") {
+        let mut folded = profiler.folded().join("\n");
+        folded.push('\n');
+        if let Err(e) = fs::write(path, folded) {
+            eprintln!("{}: {}", path, e);
+            failed = true;
+        }
+    }
+    if !save_cdl(&options, &cpu) {
+        failed = true;
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
 fn main() {
-    let a: u8 = 0xff;
-    println!("{}", a);
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        usage(&args[0]);
+    }
+
+    match args[1].as_str() {
+        "info" if args.len() == 3 => info(&args[2]),
+        "nestest" if args.len() == 3 || args.len() == 4 => {
+            run_nestest(&args[2], args.get(3).map(|s| s.as_str()))
+        }
+        "harte" if args.len() > 2 => run_harte(&args[2..]),
+        "klaus" if args.len() > 2 => run_klaus(&args[2..]),
+        "disasm" if args.len() > 2 => run_disasm(&args[2..]),
+        "trace" if args.len() > 2 => run_trace(&args[2..]),
+        "debug" if args.len() > 2 => run_debug(&args[2..]),
+        "gdb" if args.len() > 2 => run_gdb(&args[2..]),
+        "profile" if args.len() > 2 => run_profile(&args[2..]),
+        "info" | "nestest" | "harte" | "klaus" | "disasm" | "trace" | "debug" | "gdb" | "profile" => {
+            usage(&args[0])
+        }
+        path => info(path),
+    }
 }
@@ -0,0 +1,105 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+
+//cartridge hardware seen by the cpu at $4020-$ffff.
+//None means nothing drives the data bus, so the bus returns open bus
+pub trait Mapper {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+
+    fn cpu_write(&mut self, addr: u16, value: u8);
+
+    //side effect free read for debuggers
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    //debugger write, lets rom be patched. a plain write by default
+    fn cpu_poke(&mut self, addr: u16, value: u8) {
+        self.cpu_write(addr, value);
+    }
+
+    //offset into prg-rom of the byte the cpu sees at addr with the current banking,
+    //None for ram, registers and open bus. used by code/data logs and symbol files
+    fn prg_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+}
+
+//no cartridge inserted, every read is open bus
+pub struct NoCartridge;
+
+impl Mapper for NoCartridge {
+    fn cpu_read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn cpu_write(&mut self, _addr: u16, _value: u8) {}
+
+    fn cpu_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+}
+
+//mapper 0: 16 or 32 KiB of prg-rom at 8000 (16 KiB is mirrored at c000),
+//plus prg-ram at 6000-7fff on family basic style boards
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+}
+
+impl Nrom {
+    pub fn new(cart: Cartridge) -> Nrom {
+        let ram_size = cart.header.prg_ram_size + cart.header.prg_nvram_size;
+        let mut prg_ram = vec![0; ram_size.max(0x2000)];
+        if let Some(trainer) = &cart.trainer {
+            //trainers are loaded at 7000
+            prg_ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+        }
+        Nrom {
+            prg_rom: cart.prg_rom,
+            prg_ram,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7fff = addr {
+            let len = self.prg_ram.len();
+            self.prg_ram[(addr - 0x6000) as usize % len] = value;
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => Some(self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]),
+            0x8000..=0xffff => Some(self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0xffff => {
+                let len = self.prg_rom.len();
+                self.prg_rom[(addr - 0x8000) as usize % len] = value;
+            }
+            _ => self.cpu_write(addr, value),
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+}
+
+pub fn from_cartridge(cart: Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
+    match cart.header.mapper {
+        0 => Ok(Box::new(Nrom::new(cart))),
+        m => Err(CartridgeError::UnsupportedMapper(m)),
+    }
+}
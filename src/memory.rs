@@ -1,17 +1,40 @@
-pub struct Memory {
-    data: [u8; 8192], //ram (0000-3fff), i/o (4000-7fff), rom(8000-ffff)
-}
-
-impl Memory {
-    pub fn new() -> Memory {
-        Memory { data: [0; 8192] }
-    }
-
-    pub fn read(&self, addr: u16) -> u8 {
-        self.data[addr as usize]
-    }
-
-    pub fn write(&mut self, addr: u16, value: u8) {
-        self.data[addr as usize] = value;
-    }
-}
\ No newline at end of file
+use crate::bus::Bus;
+
+//flat 64 KiB ram with no mapping at all, handy for tests and non nes rigs
+pub struct Memory {
+    data: Vec<u8>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory {
+            data: vec![0; 0x10000],
+        }
+    }
+
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, b) in bytes.iter().enumerate() {
+            self.data[(addr as usize + i) & 0xffff] = *b;
+        }
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.data[addr as usize] = value;
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        Some(self.data[addr as usize])
+    }
+}
@@ -0,0 +1,118 @@
+use crate::bus::Bus;
+use crate::mapper::Mapper;
+
+//nes cpu memory map:
+//  0000-07ff  2 KiB internal ram, mirrored up to 1fff
+//  2000-2007  ppu registers, mirrored every 8 bytes up to 3fff
+//  4000-4017  apu and i/o registers
+//  4018-401f  apu/io test mode, normally disabled
+//  4020-ffff  cartridge space (prg ram, prg rom, mapper registers)
+pub const RAM_SIZE: usize = 0x800;
+pub const IO_REGS: u16 = 0x4000;
+
+//readable i/o registers, the rest of 4000-4017 is write only
+pub const APU_STATUS: u16 = 0x4015;
+pub const JOY1: u16 = 0x4016;
+pub const JOY2: u16 = 0x4017;
+
+//ntsc ppu timing. there's no ppu yet, so positions and frame numbers are
+//worked out from the cpu cycle count
+pub const PPU_DOTS_PER_CYCLE: u64 = 3;
+pub const DOTS_PER_SCANLINE: u64 = 341;
+pub const SCANLINES_PER_FRAME: u64 = 262;
+
+//frame number estimated from the cpu cycle count, it ignores the dot the ppu
+//skips on odd frames and drifts from a real console by a cycle every 2 frames
+pub fn frame(cycles: u64) -> u64 {
+    cycles * PPU_DOTS_PER_CYCLE / (DOTS_PER_SCANLINE * SCANLINES_PER_FRAME)
+}
+
+pub struct NesBus {
+    ram: [u8; RAM_SIZE],
+    ppu_regs: [u8; 8],
+    io_regs: [u8; 0x18],
+    mapper: Box<dyn Mapper>,
+    open_bus: u8, //last value seen on the data bus
+}
+
+impl NesBus {
+    pub fn new(mapper: Box<dyn Mapper>) -> NesBus {
+        NesBus {
+            ram: [0; RAM_SIZE],
+            ppu_regs: [0; 8],
+            io_regs: [0; 0x18],
+            mapper,
+            open_bus: 0,
+        }
+    }
+
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+
+    //None means nothing drives the bus at that address
+    fn read_mapped(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1fff => Some(self.ram[addr as usize % RAM_SIZE]),
+            0x2000..=0x3fff => Some(self.ppu_regs[(addr & 0x7) as usize]),
+            APU_STATUS | JOY1 | JOY2 => Some(self.io_regs[(addr - IO_REGS) as usize]),
+            0x4000..=0x401f => None,
+            _ => self.mapper.cpu_read(addr),
+        }
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        if let Some(value) = self.read_mapped(addr) {
+            self.open_bus = value;
+        }
+        self.open_bus
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.open_bus = value;
+        match addr {
+            0x0000..=0x1fff => self.ram[addr as usize % RAM_SIZE] = value,
+            0x2000..=0x3fff => self.ppu_regs[(addr & 0x7) as usize] = value,
+            0x4000..=0x4017 => self.io_regs[(addr - IO_REGS) as usize] = value,
+            0x4018..=0x401f => (),
+            _ => self.mapper.cpu_write(addr, value),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        let value = match addr {
+            0x0000..=0x1fff => Some(self.ram[addr as usize % RAM_SIZE]),
+            0x2000..=0x3fff => Some(self.ppu_regs[(addr & 0x7) as usize]),
+            APU_STATUS | JOY1 | JOY2 => Some(self.io_regs[(addr - IO_REGS) as usize]),
+            0x4000..=0x401f => None,
+            _ => self.mapper.cpu_peek(addr),
+        };
+        Some(value.unwrap_or(self.open_bus))
+    }
+
+    //unlike write this leaves the open bus value alone
+    fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x401f => {
+                let open_bus = self.open_bus;
+                self.write(addr, value);
+                self.open_bus = open_bus;
+            }
+            _ => self.mapper.cpu_poke(addr, value),
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.prg_offset(addr)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cpu::{Cpu, CpuError, IllegalOpcodePolicy};
+use crate::mapper;
+use crate::nes_bus::NesBus;
+use crate::trace;
+
+//nestest's automated mode starts at c000 instead of the reset vector
+pub const START_PC: u16 = 0xc000;
+//number of instructions in the reference nestest.log
+pub const LOG_LINES: usize = 8991;
+//lines of matching trace printed before a divergence
+pub const CONTEXT_LINES: usize = 5;
+
+//the rom leaves the result of the official / unofficial tests here, 0 = passed
+pub const RESULT_OFFICIAL: u16 = 0x0002;
+pub const RESULT_UNOFFICIAL: u16 = 0x0003;
+
+pub struct Divergence {
+    pub line: usize, //1 based, like an editor
+    pub expected: String,
+    pub actual: String,
+    pub context: Vec<String>, //last matching lines
+}
+
+impl Divergence {
+    //column of the first differing character
+    pub fn column(&self) -> usize {
+        self.expected
+            .chars()
+            .zip(self.actual.chars())
+            .position(|(e, a)| e != a)
+            .unwrap_or_else(|| self.expected.len().min(self.actual.len()))
+    }
+}
+
+pub enum Outcome {
+    Matched { lines: usize },
+    Diverged(Divergence),
+    Failed { line: usize, error: CpuError },
+}
+
+pub fn setup(cart: Cartridge) -> Result<Cpu<NesBus>, CartridgeError> {
+    let bus = NesBus::new(mapper::from_cartridge(cart)?);
+    let mut cpu = Cpu::new(bus);
+    cpu.set_illegal_policy(IllegalOpcodePolicy::Execute);
+    cpu.reset();
+    cpu.state_mut().set_pc(START_PC);
+    Ok(cpu)
+}
+
+//runs nestest comparing every trace line with the reference log (if any).
+//each produced line is handed to on_line, e.g. to print the trace
+pub fn run<F: FnMut(&str)>(
+    cpu: &mut Cpu<NesBus>,
+    reference: Option<&str>,
+    mut on_line: F,
+) -> Outcome {
+    let expected: Vec<&str> = match reference {
+        Some(log) => log.lines().map(|l| l.trim_end()).filter(|l| !l.is_empty()).collect(),
+        None => Vec::new(),
+    };
+    let lines = if reference.is_some() {
+        expected.len()
+    } else {
+        LOG_LINES
+    };
+
+    let mut context: Vec<String> = Vec::new();
+    for i in 0..lines {
+        let actual = trace::nestest_line(cpu);
+        on_line(&actual);
+        if let Some(exp) = expected.get(i) {
+            if *exp != actual {
+                return Outcome::Diverged(Divergence {
+                    line: i + 1,
+                    expected: exp.to_string(),
+                    actual,
+                    context,
+                });
+            }
+        }
+        if context.len() == CONTEXT_LINES {
+            context.remove(0);
+        }
+        context.push(actual);
+
+        if let Err(error) = cpu.next_instruction() {
+            return Outcome::Failed { line: i + 1, error };
+        }
+    }
+    Outcome::Matched { lines }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use std::env;
+    use std::fs;
+
+    //cargo test -- --ignored with NESTEST_ROM pointing at nestest.nes, and
+    //NESTEST_LOG at nestest.log to compare the trace line by line
+    #[test]
+    #[ignore = "needs NESTEST_ROM"]
+    fn nestest_passes() {
+        let rom = env::var("NESTEST_ROM").expect("NESTEST_ROM isn't set");
+        let cart = Cartridge::from_bytes(&fs::read(&rom).unwrap()).unwrap();
+        let reference = env::var("NESTEST_LOG").ok().map(|path| fs::read_to_string(path).unwrap());
+        let mut cpu = setup(cart).unwrap();
+        match run(&mut cpu, reference.as_deref(), |_| ()) {
+            Outcome::Matched { .. } => (),
+            Outcome::Diverged(d) => panic!("line {}: expected {}, got {}", d.line, d.expected, d.actual),
+            Outcome::Failed { line, error } => panic!("line {}: {}", line, error),
+        }
+        assert_eq!(cpu.bus().peek(RESULT_OFFICIAL), Some(0));
+        assert_eq!(cpu.bus().peek(RESULT_UNOFFICIAL), Some(0));
+    }
+}
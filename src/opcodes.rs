@@ -0,0 +1,724 @@
+//static description of the 256 opcodes, used by the cpu decoder, the tracer and other tools.
+//the nmos chips share OPCODES, the 65c02 has CMOS_OPCODES, table() picks one
+use crate::cpu::CpuVariant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+    //65c02 only
+    ZeroPageIndirect,  //(zp)
+    AbsoluteIndirectX, //jmp ($nnnn,x)
+    ZeroPageRelative,  //bbr/bbs: zero page operand, then a branch offset
+}
+
+impl Mode {
+    //instruction length in bytes, opcode included
+    pub fn size(self) -> u8 {
+        match self {
+            Implied | Accumulator => 1,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | IndirectX | IndirectY | Relative
+            | ZeroPageIndirect => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect | AbsoluteIndirectX | ZeroPageRelative => 3,
+        }
+    }
+}
+
+use Mode::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+    //undocumented
+    Slo,
+    Rla,
+    Sre,
+    Rra,
+    Sax,
+    Lax,
+    Dcp,
+    Isc,
+    Anc,
+    Alr,
+    Arr,
+    Axs,
+    Xaa,
+    Lxa,
+    Las,
+    Sha,
+    Shx,
+    Shy,
+    Tas,
+    Kil,
+    //65c02
+    Bra,
+    Stz,
+    Tsb,
+    Trb,
+    Phx,
+    Phy,
+    Plx,
+    Ply,
+    Wai,
+    Stp,
+    Rmb0,
+    Rmb1,
+    Rmb2,
+    Rmb3,
+    Rmb4,
+    Rmb5,
+    Rmb6,
+    Rmb7,
+    Smb0,
+    Smb1,
+    Smb2,
+    Smb3,
+    Smb4,
+    Smb5,
+    Smb6,
+    Smb7,
+    Bbr0,
+    Bbr1,
+    Bbr2,
+    Bbr3,
+    Bbr4,
+    Bbr5,
+    Bbr6,
+    Bbr7,
+    Bbs0,
+    Bbs1,
+    Bbs2,
+    Bbs3,
+    Bbs4,
+    Bbs5,
+    Bbs6,
+    Bbs7,
+}
+
+//what an instruction does with the byte at its effective address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Modify, //read, then write back the result
+    None,   //implied, stack, jumps and branches
+}
+
+use Instruction::*;
+
+impl Instruction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Adc => "ADC",
+            And => "AND",
+            Asl => "ASL",
+            Bcc => "BCC",
+            Bcs => "BCS",
+            Beq => "BEQ",
+            Bit => "BIT",
+            Bmi => "BMI",
+            Bne => "BNE",
+            Bpl => "BPL",
+            Brk => "BRK",
+            Bvc => "BVC",
+            Bvs => "BVS",
+            Clc => "CLC",
+            Cld => "CLD",
+            Cli => "CLI",
+            Clv => "CLV",
+            Cmp => "CMP",
+            Cpx => "CPX",
+            Cpy => "CPY",
+            Dec => "DEC",
+            Dex => "DEX",
+            Dey => "DEY",
+            Eor => "EOR",
+            Inc => "INC",
+            Inx => "INX",
+            Iny => "INY",
+            Jmp => "JMP",
+            Jsr => "JSR",
+            Lda => "LDA",
+            Ldx => "LDX",
+            Ldy => "LDY",
+            Lsr => "LSR",
+            Nop => "NOP",
+            Ora => "ORA",
+            Pha => "PHA",
+            Php => "PHP",
+            Pla => "PLA",
+            Plp => "PLP",
+            Rol => "ROL",
+            Ror => "ROR",
+            Rti => "RTI",
+            Rts => "RTS",
+            Sbc => "SBC",
+            Sec => "SEC",
+            Sed => "SED",
+            Sei => "SEI",
+            Sta => "STA",
+            Stx => "STX",
+            Sty => "STY",
+            Tax => "TAX",
+            Tay => "TAY",
+            Tsx => "TSX",
+            Txa => "TXA",
+            Txs => "TXS",
+            Tya => "TYA",
+            Slo => "SLO",
+            Rla => "RLA",
+            Sre => "SRE",
+            Rra => "RRA",
+            Sax => "SAX",
+            Lax => "LAX",
+            Dcp => "DCP",
+            Isc => "ISC",
+            Anc => "ANC",
+            Alr => "ALR",
+            Arr => "ARR",
+            Axs => "AXS",
+            Xaa => "XAA",
+            Lxa => "LXA",
+            Las => "LAS",
+            Sha => "SHA",
+            Shx => "SHX",
+            Shy => "SHY",
+            Tas => "TAS",
+            Kil => "KIL",
+            Bra => "BRA",
+            Stz => "STZ",
+            Tsb => "TSB",
+            Trb => "TRB",
+            Phx => "PHX",
+            Phy => "PHY",
+            Plx => "PLX",
+            Ply => "PLY",
+            Wai => "WAI",
+            Stp => "STP",
+            Rmb0 => "RMB0",
+            Rmb1 => "RMB1",
+            Rmb2 => "RMB2",
+            Rmb3 => "RMB3",
+            Rmb4 => "RMB4",
+            Rmb5 => "RMB5",
+            Rmb6 => "RMB6",
+            Rmb7 => "RMB7",
+            Smb0 => "SMB0",
+            Smb1 => "SMB1",
+            Smb2 => "SMB2",
+            Smb3 => "SMB3",
+            Smb4 => "SMB4",
+            Smb5 => "SMB5",
+            Smb6 => "SMB6",
+            Smb7 => "SMB7",
+            Bbr0 => "BBR0",
+            Bbr1 => "BBR1",
+            Bbr2 => "BBR2",
+            Bbr3 => "BBR3",
+            Bbr4 => "BBR4",
+            Bbr5 => "BBR5",
+            Bbr6 => "BBR6",
+            Bbr7 => "BBR7",
+            Bbs0 => "BBS0",
+            Bbs1 => "BBS1",
+            Bbs2 => "BBS2",
+            Bbs3 => "BBS3",
+            Bbs4 => "BBS4",
+            Bbs5 => "BBS5",
+            Bbs6 => "BBS6",
+            Bbs7 => "BBS7",
+        }
+    }
+
+    //bit number and value of rmb/smb (reset or set the bit) and bbr/bbs
+    //(branch if it is reset or set), they carry both in their name
+    pub fn bit_operation(self) -> Option<(u8, bool)> {
+        match self.name().as_bytes() {
+            [b'R', b'M', b'B', n] | [b'B', b'B', b'R', n] => Some((n - b'0', false)),
+            [b'S', b'M', b'B', n] | [b'B', b'B', b'S', n] => Some((n - b'0', true)),
+            _ => None,
+        }
+    }
+
+    pub fn access(self) -> Access {
+        match self {
+            Adc | And | Bit | Cmp | Cpx | Cpy | Eor | Lda | Ldx | Ldy | Nop | Ora | Sbc | Lax
+            | Anc | Alr | Arr | Axs | Xaa | Lxa | Las => Access::Read,
+            Sta | Stx | Sty | Sax | Sha | Shx | Shy | Tas | Stz => Access::Write,
+            Asl | Lsr | Rol | Ror | Inc | Dec | Slo | Rla | Sre | Rra | Dcp | Isc | Tsb | Trb => Access::Modify,
+            Rmb0 | Rmb1 | Rmb2 | Rmb3 | Rmb4 | Rmb5 | Rmb6 | Rmb7 | Smb0 | Smb1 | Smb2 | Smb3 | Smb4
+            | Smb5 | Smb6 | Smb7 => Access::Modify,
+            _ => Access::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opcode {
+    pub instruction: Instruction,
+    pub mode: Mode,
+    pub cycles: u8,         //base cycles, branches and page crossings add to it
+    pub page_penalty: bool, //one more cycle when the indexed address crosses a page
+    pub official: bool,
+}
+
+impl Opcode {
+    pub fn mnemonic(&self) -> &'static str {
+        self.instruction.name()
+    }
+
+    pub fn size(&self) -> u8 {
+        self.mode.size()
+    }
+}
+
+const fn op(instruction: Instruction, mode: Mode, cycles: u8, page_penalty: bool, official: bool) -> Opcode {
+    Opcode {
+        instruction,
+        mode,
+        cycles,
+        page_penalty,
+        official,
+    }
+}
+
+//stores and read-modify-write instructions always take the indexed extra cycle,
+//it's already in their base count
+pub const OPCODES: [Opcode; 256] = [
+    op(Brk, Implied, 7, false, true), //0x00
+    op(Ora, IndirectX, 6, false, true), //0x01
+    op(Kil, Implied, 2, false, false), //0x02
+    op(Slo, IndirectX, 8, false, false), //0x03
+    op(Nop, ZeroPage, 3, false, false), //0x04
+    op(Ora, ZeroPage, 3, false, true), //0x05
+    op(Asl, ZeroPage, 5, false, true), //0x06
+    op(Slo, ZeroPage, 5, false, false), //0x07
+    op(Php, Implied, 3, false, true), //0x08
+    op(Ora, Immediate, 2, false, true), //0x09
+    op(Asl, Accumulator, 2, false, true), //0x0a
+    op(Anc, Immediate, 2, false, false), //0x0b
+    op(Nop, Absolute, 4, false, false), //0x0c
+    op(Ora, Absolute, 4, false, true), //0x0d
+    op(Asl, Absolute, 6, false, true), //0x0e
+    op(Slo, Absolute, 6, false, false), //0x0f
+    op(Bpl, Relative, 2, false, true), //0x10
+    op(Ora, IndirectY, 5, true, true), //0x11
+    op(Kil, Implied, 2, false, false), //0x12
+    op(Slo, IndirectY, 8, false, false), //0x13
+    op(Nop, ZeroPageX, 4, false, false), //0x14
+    op(Ora, ZeroPageX, 4, false, true), //0x15
+    op(Asl, ZeroPageX, 6, false, true), //0x16
+    op(Slo, ZeroPageX, 6, false, false), //0x17
+    op(Clc, Implied, 2, false, true), //0x18
+    op(Ora, AbsoluteY, 4, true, true), //0x19
+    op(Nop, Implied, 2, false, false), //0x1a
+    op(Slo, AbsoluteY, 7, false, false), //0x1b
+    op(Nop, AbsoluteX, 4, true, false), //0x1c
+    op(Ora, AbsoluteX, 4, true, true), //0x1d
+    op(Asl, AbsoluteX, 7, false, true), //0x1e
+    op(Slo, AbsoluteX, 7, false, false), //0x1f
+    op(Jsr, Absolute, 6, false, true), //0x20
+    op(And, IndirectX, 6, false, true), //0x21
+    op(Kil, Implied, 2, false, false), //0x22
+    op(Rla, IndirectX, 8, false, false), //0x23
+    op(Bit, ZeroPage, 3, false, true), //0x24
+    op(And, ZeroPage, 3, false, true), //0x25
+    op(Rol, ZeroPage, 5, false, true), //0x26
+    op(Rla, ZeroPage, 5, false, false), //0x27
+    op(Plp, Implied, 4, false, true), //0x28
+    op(And, Immediate, 2, false, true), //0x29
+    op(Rol, Accumulator, 2, false, true), //0x2a
+    op(Anc, Immediate, 2, false, false), //0x2b
+    op(Bit, Absolute, 4, false, true), //0x2c
+    op(And, Absolute, 4, false, true), //0x2d
+    op(Rol, Absolute, 6, false, true), //0x2e
+    op(Rla, Absolute, 6, false, false), //0x2f
+    op(Bmi, Relative, 2, false, true), //0x30
+    op(And, IndirectY, 5, true, true), //0x31
+    op(Kil, Implied, 2, false, false), //0x32
+    op(Rla, IndirectY, 8, false, false), //0x33
+    op(Nop, ZeroPageX, 4, false, false), //0x34
+    op(And, ZeroPageX, 4, false, true), //0x35
+    op(Rol, ZeroPageX, 6, false, true), //0x36
+    op(Rla, ZeroPageX, 6, false, false), //0x37
+    op(Sec, Implied, 2, false, true), //0x38
+    op(And, AbsoluteY, 4, true, true), //0x39
+    op(Nop, Implied, 2, false, false), //0x3a
+    op(Rla, AbsoluteY, 7, false, false), //0x3b
+    op(Nop, AbsoluteX, 4, true, false), //0x3c
+    op(And, AbsoluteX, 4, true, true), //0x3d
+    op(Rol, AbsoluteX, 7, false, true), //0x3e
+    op(Rla, AbsoluteX, 7, false, false), //0x3f
+    op(Rti, Implied, 6, false, true), //0x40
+    op(Eor, IndirectX, 6, false, true), //0x41
+    op(Kil, Implied, 2, false, false), //0x42
+    op(Sre, IndirectX, 8, false, false), //0x43
+    op(Nop, ZeroPage, 3, false, false), //0x44
+    op(Eor, ZeroPage, 3, false, true), //0x45
+    op(Lsr, ZeroPage, 5, false, true), //0x46
+    op(Sre, ZeroPage, 5, false, false), //0x47
+    op(Pha, Implied, 3, false, true), //0x48
+    op(Eor, Immediate, 2, false, true), //0x49
+    op(Lsr, Accumulator, 2, false, true), //0x4a
+    op(Alr, Immediate, 2, false, false), //0x4b
+    op(Jmp, Absolute, 3, false, true), //0x4c
+    op(Eor, Absolute, 4, false, true), //0x4d
+    op(Lsr, Absolute, 6, false, true), //0x4e
+    op(Sre, Absolute, 6, false, false), //0x4f
+    op(Bvc, Relative, 2, false, true), //0x50
+    op(Eor, IndirectY, 5, true, true), //0x51
+    op(Kil, Implied, 2, false, false), //0x52
+    op(Sre, IndirectY, 8, false, false), //0x53
+    op(Nop, ZeroPageX, 4, false, false), //0x54
+    op(Eor, ZeroPageX, 4, false, true), //0x55
+    op(Lsr, ZeroPageX, 6, false, true), //0x56
+    op(Sre, ZeroPageX, 6, false, false), //0x57
+    op(Cli, Implied, 2, false, true), //0x58
+    op(Eor, AbsoluteY, 4, true, true), //0x59
+    op(Nop, Implied, 2, false, false), //0x5a
+    op(Sre, AbsoluteY, 7, false, false), //0x5b
+    op(Nop, AbsoluteX, 4, true, false), //0x5c
+    op(Eor, AbsoluteX, 4, true, true), //0x5d
+    op(Lsr, AbsoluteX, 7, false, true), //0x5e
+    op(Sre, AbsoluteX, 7, false, false), //0x5f
+    op(Rts, Implied, 6, false, true), //0x60
+    op(Adc, IndirectX, 6, false, true), //0x61
+    op(Kil, Implied, 2, false, false), //0x62
+    op(Rra, IndirectX, 8, false, false), //0x63
+    op(Nop, ZeroPage, 3, false, false), //0x64
+    op(Adc, ZeroPage, 3, false, true), //0x65
+    op(Ror, ZeroPage, 5, false, true), //0x66
+    op(Rra, ZeroPage, 5, false, false), //0x67
+    op(Pla, Implied, 4, false, true), //0x68
+    op(Adc, Immediate, 2, false, true), //0x69
+    op(Ror, Accumulator, 2, false, true), //0x6a
+    op(Arr, Immediate, 2, false, false), //0x6b
+    op(Jmp, Indirect, 5, false, true), //0x6c
+    op(Adc, Absolute, 4, false, true), //0x6d
+    op(Ror, Absolute, 6, false, true), //0x6e
+    op(Rra, Absolute, 6, false, false), //0x6f
+    op(Bvs, Relative, 2, false, true), //0x70
+    op(Adc, IndirectY, 5, true, true), //0x71
+    op(Kil, Implied, 2, false, false), //0x72
+    op(Rra, IndirectY, 8, false, false), //0x73
+    op(Nop, ZeroPageX, 4, false, false), //0x74
+    op(Adc, ZeroPageX, 4, false, true), //0x75
+    op(Ror, ZeroPageX, 6, false, true), //0x76
+    op(Rra, ZeroPageX, 6, false, false), //0x77
+    op(Sei, Implied, 2, false, true), //0x78
+    op(Adc, AbsoluteY, 4, true, true), //0x79
+    op(Nop, Implied, 2, false, false), //0x7a
+    op(Rra, AbsoluteY, 7, false, false), //0x7b
+    op(Nop, AbsoluteX, 4, true, false), //0x7c
+    op(Adc, AbsoluteX, 4, true, true), //0x7d
+    op(Ror, AbsoluteX, 7, false, true), //0x7e
+    op(Rra, AbsoluteX, 7, false, false), //0x7f
+    op(Nop, Immediate, 2, false, false), //0x80
+    op(Sta, IndirectX, 6, false, true), //0x81
+    op(Nop, Immediate, 2, false, false), //0x82
+    op(Sax, IndirectX, 6, false, false), //0x83
+    op(Sty, ZeroPage, 3, false, true), //0x84
+    op(Sta, ZeroPage, 3, false, true), //0x85
+    op(Stx, ZeroPage, 3, false, true), //0x86
+    op(Sax, ZeroPage, 3, false, false), //0x87
+    op(Dey, Implied, 2, false, true), //0x88
+    op(Nop, Immediate, 2, false, false), //0x89
+    op(Txa, Implied, 2, false, true), //0x8a
+    op(Xaa, Immediate, 2, false, false), //0x8b
+    op(Sty, Absolute, 4, false, true), //0x8c
+    op(Sta, Absolute, 4, false, true), //0x8d
+    op(Stx, Absolute, 4, false, true), //0x8e
+    op(Sax, Absolute, 4, false, false), //0x8f
+    op(Bcc, Relative, 2, false, true), //0x90
+    op(Sta, IndirectY, 6, false, true), //0x91
+    op(Kil, Implied, 2, false, false), //0x92
+    op(Sha, IndirectY, 6, false, false), //0x93
+    op(Sty, ZeroPageX, 4, false, true), //0x94
+    op(Sta, ZeroPageX, 4, false, true), //0x95
+    op(Stx, ZeroPageY, 4, false, true), //0x96
+    op(Sax, ZeroPageY, 4, false, false), //0x97
+    op(Tya, Implied, 2, false, true), //0x98
+    op(Sta, AbsoluteY, 5, false, true), //0x99
+    op(Txs, Implied, 2, false, true), //0x9a
+    op(Tas, AbsoluteY, 5, false, false), //0x9b
+    op(Shy, AbsoluteX, 5, false, false), //0x9c
+    op(Sta, AbsoluteX, 5, false, true), //0x9d
+    op(Shx, AbsoluteY, 5, false, false), //0x9e
+    op(Sha, AbsoluteY, 5, false, false), //0x9f
+    op(Ldy, Immediate, 2, false, true), //0xa0
+    op(Lda, IndirectX, 6, false, true), //0xa1
+    op(Ldx, Immediate, 2, false, true), //0xa2
+    op(Lax, IndirectX, 6, false, false), //0xa3
+    op(Ldy, ZeroPage, 3, false, true), //0xa4
+    op(Lda, ZeroPage, 3, false, true), //0xa5
+    op(Ldx, ZeroPage, 3, false, true), //0xa6
+    op(Lax, ZeroPage, 3, false, false), //0xa7
+    op(Tay, Implied, 2, false, true), //0xa8
+    op(Lda, Immediate, 2, false, true), //0xa9
+    op(Tax, Implied, 2, false, true), //0xaa
+    op(Lxa, Immediate, 2, false, false), //0xab
+    op(Ldy, Absolute, 4, false, true), //0xac
+    op(Lda, Absolute, 4, false, true), //0xad
+    op(Ldx, Absolute, 4, false, true), //0xae
+    op(Lax, Absolute, 4, false, false), //0xaf
+    op(Bcs, Relative, 2, false, true), //0xb0
+    op(Lda, IndirectY, 5, true, true), //0xb1
+    op(Kil, Implied, 2, false, false), //0xb2
+    op(Lax, IndirectY, 5, true, false), //0xb3
+    op(Ldy, ZeroPageX, 4, false, true), //0xb4
+    op(Lda, ZeroPageX, 4, false, true), //0xb5
+    op(Ldx, ZeroPageY, 4, false, true), //0xb6
+    op(Lax, ZeroPageY, 4, false, false), //0xb7
+    op(Clv, Implied, 2, false, true), //0xb8
+    op(Lda, AbsoluteY, 4, true, true), //0xb9
+    op(Tsx, Implied, 2, false, true), //0xba
+    op(Las, AbsoluteY, 4, true, false), //0xbb
+    op(Ldy, AbsoluteX, 4, true, true), //0xbc
+    op(Lda, AbsoluteX, 4, true, true), //0xbd
+    op(Ldx, AbsoluteY, 4, true, true), //0xbe
+    op(Lax, AbsoluteY, 4, true, false), //0xbf
+    op(Cpy, Immediate, 2, false, true), //0xc0
+    op(Cmp, IndirectX, 6, false, true), //0xc1
+    op(Nop, Immediate, 2, false, false), //0xc2
+    op(Dcp, IndirectX, 8, false, false), //0xc3
+    op(Cpy, ZeroPage, 3, false, true), //0xc4
+    op(Cmp, ZeroPage, 3, false, true), //0xc5
+    op(Dec, ZeroPage, 5, false, true), //0xc6
+    op(Dcp, ZeroPage, 5, false, false), //0xc7
+    op(Iny, Implied, 2, false, true), //0xc8
+    op(Cmp, Immediate, 2, false, true), //0xc9
+    op(Dex, Implied, 2, false, true), //0xca
+    op(Axs, Immediate, 2, false, false), //0xcb
+    op(Cpy, Absolute, 4, false, true), //0xcc
+    op(Cmp, Absolute, 4, false, true), //0xcd
+    op(Dec, Absolute, 6, false, true), //0xce
+    op(Dcp, Absolute, 6, false, false), //0xcf
+    op(Bne, Relative, 2, false, true), //0xd0
+    op(Cmp, IndirectY, 5, true, true), //0xd1
+    op(Kil, Implied, 2, false, false), //0xd2
+    op(Dcp, IndirectY, 8, false, false), //0xd3
+    op(Nop, ZeroPageX, 4, false, false), //0xd4
+    op(Cmp, ZeroPageX, 4, false, true), //0xd5
+    op(Dec, ZeroPageX, 6, false, true), //0xd6
+    op(Dcp, ZeroPageX, 6, false, false), //0xd7
+    op(Cld, Implied, 2, false, true), //0xd8
+    op(Cmp, AbsoluteY, 4, true, true), //0xd9
+    op(Nop, Implied, 2, false, false), //0xda
+    op(Dcp, AbsoluteY, 7, false, false), //0xdb
+    op(Nop, AbsoluteX, 4, true, false), //0xdc
+    op(Cmp, AbsoluteX, 4, true, true), //0xdd
+    op(Dec, AbsoluteX, 7, false, true), //0xde
+    op(Dcp, AbsoluteX, 7, false, false), //0xdf
+    op(Cpx, Immediate, 2, false, true), //0xe0
+    op(Sbc, IndirectX, 6, false, true), //0xe1
+    op(Nop, Immediate, 2, false, false), //0xe2
+    op(Isc, IndirectX, 8, false, false), //0xe3
+    op(Cpx, ZeroPage, 3, false, true), //0xe4
+    op(Sbc, ZeroPage, 3, false, true), //0xe5
+    op(Inc, ZeroPage, 5, false, true), //0xe6
+    op(Isc, ZeroPage, 5, false, false), //0xe7
+    op(Inx, Implied, 2, false, true), //0xe8
+    op(Sbc, Immediate, 2, false, true), //0xe9
+    op(Nop, Implied, 2, false, true), //0xea
+    op(Sbc, Immediate, 2, false, false), //0xeb
+    op(Cpx, Absolute, 4, false, true), //0xec
+    op(Sbc, Absolute, 4, false, true), //0xed
+    op(Inc, Absolute, 6, false, true), //0xee
+    op(Isc, Absolute, 6, false, false), //0xef
+    op(Beq, Relative, 2, false, true), //0xf0
+    op(Sbc, IndirectY, 5, true, true), //0xf1
+    op(Kil, Implied, 2, false, false), //0xf2
+    op(Isc, IndirectY, 8, false, false), //0xf3
+    op(Nop, ZeroPageX, 4, false, false), //0xf4
+    op(Sbc, ZeroPageX, 4, false, true), //0xf5
+    op(Inc, ZeroPageX, 6, false, true), //0xf6
+    op(Isc, ZeroPageX, 6, false, false), //0xf7
+    op(Sed, Implied, 2, false, true), //0xf8
+    op(Sbc, AbsoluteY, 4, true, true), //0xf9
+    op(Nop, Implied, 2, false, false), //0xfa
+    op(Isc, AbsoluteY, 7, false, false), //0xfb
+    op(Nop, AbsoluteX, 4, true, false), //0xfc
+    op(Sbc, AbsoluteX, 4, true, true), //0xfd
+    op(Inc, AbsoluteX, 7, false, true), //0xfe
+    op(Isc, AbsoluteX, 7, false, false), //0xff
+];
+
+//the wdc 65c02 gives every nmos undocumented opcode a meaning: new instructions,
+//reserved nops of fixed size and timing (all of columns 3 and b are one byte,
+//one cycle nops), and a few changed timings
+const CMOS_CHANGES: [(u8, Opcode); 80] = [
+    (0x02, op(Nop, Immediate, 2, false, true)),
+    (0x04, op(Tsb, ZeroPage, 5, false, true)),
+    (0x07, op(Rmb0, ZeroPage, 5, false, true)),
+    (0x0c, op(Tsb, Absolute, 6, false, true)),
+    (0x0f, op(Bbr0, ZeroPageRelative, 5, false, true)),
+    (0x12, op(Ora, ZeroPageIndirect, 5, false, true)),
+    (0x14, op(Trb, ZeroPage, 5, false, true)),
+    (0x17, op(Rmb1, ZeroPage, 5, false, true)),
+    (0x1a, op(Inc, Accumulator, 2, false, true)),
+    (0x1c, op(Trb, Absolute, 6, false, true)),
+    (0x1e, op(Asl, AbsoluteX, 6, true, true)),
+    (0x1f, op(Bbr1, ZeroPageRelative, 5, false, true)),
+    (0x22, op(Nop, Immediate, 2, false, true)),
+    (0x27, op(Rmb2, ZeroPage, 5, false, true)),
+    (0x2f, op(Bbr2, ZeroPageRelative, 5, false, true)),
+    (0x32, op(And, ZeroPageIndirect, 5, false, true)),
+    (0x34, op(Bit, ZeroPageX, 4, false, true)),
+    (0x37, op(Rmb3, ZeroPage, 5, false, true)),
+    (0x3a, op(Dec, Accumulator, 2, false, true)),
+    (0x3c, op(Bit, AbsoluteX, 4, true, true)),
+    (0x3e, op(Rol, AbsoluteX, 6, true, true)),
+    (0x3f, op(Bbr3, ZeroPageRelative, 5, false, true)),
+    (0x42, op(Nop, Immediate, 2, false, true)),
+    (0x44, op(Nop, ZeroPage, 3, false, true)),
+    (0x47, op(Rmb4, ZeroPage, 5, false, true)),
+    (0x4f, op(Bbr4, ZeroPageRelative, 5, false, true)),
+    (0x52, op(Eor, ZeroPageIndirect, 5, false, true)),
+    (0x54, op(Nop, ZeroPageX, 4, false, true)),
+    (0x57, op(Rmb5, ZeroPage, 5, false, true)),
+    (0x5a, op(Phy, Implied, 3, false, true)),
+    (0x5c, op(Nop, Absolute, 8, false, true)),
+    (0x5e, op(Lsr, AbsoluteX, 6, true, true)),
+    (0x5f, op(Bbr5, ZeroPageRelative, 5, false, true)),
+    (0x62, op(Nop, Immediate, 2, false, true)),
+    (0x64, op(Stz, ZeroPage, 3, false, true)),
+    (0x67, op(Rmb6, ZeroPage, 5, false, true)),
+    (0x6c, op(Jmp, Indirect, 6, false, true)),
+    (0x6f, op(Bbr6, ZeroPageRelative, 5, false, true)),
+    (0x72, op(Adc, ZeroPageIndirect, 5, false, true)),
+    (0x74, op(Stz, ZeroPageX, 4, false, true)),
+    (0x77, op(Rmb7, ZeroPage, 5, false, true)),
+    (0x7a, op(Ply, Implied, 4, false, true)),
+    (0x7c, op(Jmp, AbsoluteIndirectX, 6, false, true)),
+    (0x7e, op(Ror, AbsoluteX, 6, true, true)),
+    (0x7f, op(Bbr7, ZeroPageRelative, 5, false, true)),
+    (0x80, op(Bra, Relative, 2, false, true)),
+    (0x82, op(Nop, Immediate, 2, false, true)),
+    (0x87, op(Smb0, ZeroPage, 5, false, true)),
+    (0x89, op(Bit, Immediate, 2, false, true)),
+    (0x8f, op(Bbs0, ZeroPageRelative, 5, false, true)),
+    (0x92, op(Sta, ZeroPageIndirect, 5, false, true)),
+    (0x97, op(Smb1, ZeroPage, 5, false, true)),
+    (0x9c, op(Stz, Absolute, 4, false, true)),
+    (0x9e, op(Stz, AbsoluteX, 5, false, true)),
+    (0x9f, op(Bbs1, ZeroPageRelative, 5, false, true)),
+    (0xa7, op(Smb2, ZeroPage, 5, false, true)),
+    (0xaf, op(Bbs2, ZeroPageRelative, 5, false, true)),
+    (0xb2, op(Lda, ZeroPageIndirect, 5, false, true)),
+    (0xb7, op(Smb3, ZeroPage, 5, false, true)),
+    (0xbf, op(Bbs3, ZeroPageRelative, 5, false, true)),
+    (0xc2, op(Nop, Immediate, 2, false, true)),
+    (0xc7, op(Smb4, ZeroPage, 5, false, true)),
+    (0xcb, op(Wai, Implied, 3, false, true)),
+    (0xcf, op(Bbs4, ZeroPageRelative, 5, false, true)),
+    (0xd2, op(Cmp, ZeroPageIndirect, 5, false, true)),
+    (0xd4, op(Nop, ZeroPageX, 4, false, true)),
+    (0xd7, op(Smb5, ZeroPage, 5, false, true)),
+    (0xda, op(Phx, Implied, 3, false, true)),
+    (0xdb, op(Stp, Implied, 3, false, true)),
+    (0xdc, op(Nop, Absolute, 4, false, true)),
+    (0xdf, op(Bbs5, ZeroPageRelative, 5, false, true)),
+    (0xe2, op(Nop, Immediate, 2, false, true)),
+    (0xe7, op(Smb6, ZeroPage, 5, false, true)),
+    (0xef, op(Bbs6, ZeroPageRelative, 5, false, true)),
+    (0xf2, op(Sbc, ZeroPageIndirect, 5, false, true)),
+    (0xf4, op(Nop, ZeroPageX, 4, false, true)),
+    (0xf7, op(Smb7, ZeroPage, 5, false, true)),
+    (0xfa, op(Plx, Implied, 4, false, true)),
+    (0xfc, op(Nop, Absolute, 4, false, true)),
+    (0xff, op(Bbs7, ZeroPageRelative, 5, false, true)),
+];
+
+const fn cmos_opcodes() -> [Opcode; 256] {
+    let mut table = OPCODES;
+    let mut i = 0;
+    while i < 256 {
+        if i & 0x07 == 0x03 {
+            table[i] = op(Nop, Implied, 1, false, true);
+        }
+        i += 1;
+    }
+    let mut i = 0;
+    while i < CMOS_CHANGES.len() {
+        let (code, opcode) = CMOS_CHANGES[i];
+        table[code as usize] = opcode;
+        i += 1;
+    }
+    table
+}
+
+pub const CMOS_OPCODES: [Opcode; 256] = cmos_opcodes();
+
+//the table a variant decodes with
+pub fn table(variant: CpuVariant) -> &'static [Opcode; 256] {
+    match variant {
+        CpuVariant::Ricoh2A03 | CpuVariant::Nmos6502 => &OPCODES,
+        CpuVariant::Cmos65C02 => &CMOS_OPCODES,
+    }
+}
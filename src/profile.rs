@@ -0,0 +1,387 @@
+use crate::bus::Bus;
+use crate::cartridge::PRG_BANK_SIZE;
+use crate::cpu::{Cpu, FrameKind, INTERRUPT_CYCLES};
+use crate::nes_bus;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+
+//attributes cpu cycles to routines. the cpu already follows jsr/rts, brk and
+//interrupts to show the call stack, the profiler mirrors that stack and charges
+//every instruction to the routines on it. attach with Cpu::set_profiler
+
+//a routine is known by its entry point, the prg-rom offset keeps the same
+//address in different banks apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Routine {
+    addr: u16,
+    prg_offset: Option<usize>,
+}
+
+//one place in the call tree, the same routine called from two places is two nodes
+struct Node {
+    routine: Routine,
+    parent: Option<usize>,
+    children: HashMap<Routine, usize>,
+    calls: u64,
+    self_cycles: u64,
+}
+
+struct Entry {
+    node: usize,
+    sp: u8,
+    kind: FrameKind,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Stats {
+    calls: u64,
+    self_cycles: u64,
+    total_cycles: u64, //including the routines it called
+    frame: Option<u64>, //last frame it ran in
+    frame_cycles: u64,  //total cycles in that frame
+    frames: u64,        //frames it ran in
+    min_frame: u64,     //over the finished frames
+    max_frame: u64,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            calls: 0,
+            self_cycles: 0,
+            total_cycles: 0,
+            frame: None,
+            frame_cycles: 0,
+            frames: 0,
+            min_frame: u64::MAX,
+            max_frame: 0,
+        }
+    }
+
+    fn add(&mut self, frame: u64, cycles: u64) {
+        if self.frame != Some(frame) {
+            if self.frame.is_some() {
+                self.min_frame = self.min_frame.min(self.frame_cycles);
+                self.max_frame = self.max_frame.max(self.frame_cycles);
+            }
+            self.frame = Some(frame);
+            self.frame_cycles = 0;
+            self.frames += 1;
+        }
+        self.frame_cycles += cycles;
+        self.total_cycles += cycles;
+    }
+
+    //min and max cycles per frame, counting the frame still running
+    fn frame_range(&self) -> (u64, u64) {
+        let min = self.min_frame.min(self.frame_cycles);
+        let max = self.max_frame.max(self.frame_cycles);
+        (min, max)
+    }
+}
+
+pub struct Profiler {
+    nodes: Vec<Node>, //nodes[0] is where profiling started
+    stack: Vec<Entry>,
+    stats: HashMap<Routine, Stats>,
+    cycles: Option<u64>, //cpu cycles when the running instruction started
+    first_frame: u64,
+    last_frame: u64,
+    symbols: Symbols,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            nodes: Vec::new(),
+            stack: Vec::new(),
+            stats: HashMap::new(),
+            cycles: None,
+            first_frame: 0,
+            last_frame: 0,
+            symbols: Symbols::new(),
+        }
+    }
+
+    //routine names in the reports, unlabelled ones show as bank:address
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    //called by the cpu before each instruction. the one before it is charged to
+    //the stack it started on, so jsr counts for the caller and rts for the callee
+    pub fn record<B: Bus>(&mut self, cpu: &Cpu<B>) {
+        let pc = cpu.state().pc();
+        let routine = Routine {
+            addr: pc,
+            prg_offset: cpu.bus().prg_offset(pc),
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                routine,
+                parent: None,
+                children: HashMap::new(),
+                calls: 1,
+                self_cycles: 0,
+            });
+            self.stats.entry(routine).or_insert_with(Stats::new).calls += 1;
+            self.stack.push(Entry {
+                node: 0,
+                sp: 0,
+                kind: FrameKind::Jsr,
+            });
+            self.first_frame = nes_bus::frame(cpu.cycles());
+        }
+
+        //frames the cpu still has are kept, the rest have returned
+        let frames = cpu.call_frames();
+        let mut kept = 0;
+        while kept < frames.len() && kept + 1 < self.stack.len() {
+            let entry = &self.stack[kept + 1];
+            if entry.sp != frames[kept].sp || entry.kind != frames[kept].kind {
+                break;
+            }
+            kept += 1;
+        }
+        let returned = kept + 1 < self.stack.len();
+        let entered = &frames[kept..];
+        //interrupt entry cycles belong to the handler, the instruction before
+        //them (the cycle-stepped core runs both in one go) to the caller
+        let interrupted = entered.last().is_some_and(|frame| frame.kind != FrameKind::Jsr);
+        let entry_cycles = if interrupted { INTERRUPT_CYCLES as u64 } else { 0 };
+        self.finish(cpu.cycles().saturating_sub(entry_cycles));
+        if returned {
+            self.stack.truncate(kept + 1);
+        }
+        for frame in entered {
+            self.enter(routine, frame.sp, frame.kind);
+        }
+        self.finish(cpu.cycles());
+    }
+
+    fn enter(&mut self, routine: Routine, sp: u8, kind: FrameKind) {
+        let parent = self.stack.last().map_or(0, |entry| entry.node);
+        let node = match self.nodes[parent].children.get(&routine) {
+            Some(&node) => node,
+            None => {
+                self.nodes.push(Node {
+                    routine,
+                    parent: Some(parent),
+                    children: HashMap::new(),
+                    calls: 0,
+                    self_cycles: 0,
+                });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(routine, node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.stats.entry(routine).or_insert_with(Stats::new).calls += 1;
+        self.stack.push(Entry { node, sp, kind });
+    }
+
+    //charges everything run since the last call to the current stack, call it
+    //with cpu.cycles() before asking for a report
+    pub fn finish(&mut self, cycles: u64) {
+        let start = match self.cycles.replace(cycles) {
+            Some(start) => start,
+            None => return,
+        };
+        let spent = cycles.saturating_sub(start);
+        let top = match self.stack.last() {
+            Some(entry) => entry.node,
+            None => return,
+        };
+        let frame = nes_bus::frame(start);
+        self.last_frame = frame;
+        self.nodes[top].self_cycles += spent;
+        if let Some(stats) = self.stats.get_mut(&self.nodes[top].routine) {
+            stats.self_cycles += spent;
+        }
+        //a recursive routine is on the stack more than once but only counted once
+        let mut seen: Vec<Routine> = Vec::with_capacity(self.stack.len());
+        for entry in &self.stack {
+            let routine = self.nodes[entry.node].routine;
+            if !seen.contains(&routine) {
+                seen.push(routine);
+                if let Some(stats) = self.stats.get_mut(&routine) {
+                    stats.add(frame, spent);
+                }
+            }
+        }
+    }
+
+    fn name(&self, routine: Routine) -> String {
+        match self.symbols.label(routine.addr, routine.prg_offset) {
+            Some(label) => label.to_string(),
+            None => match routine.prg_offset {
+                Some(offset) => format!("{:02X}:{:04X}", offset / PRG_BANK_SIZE, routine.addr),
+                None => format!("{:04X}", routine.addr),
+            },
+        }
+    }
+
+    fn total_cycles(&self) -> u64 {
+        self.nodes.iter().map(|node| node.self_cycles).sum()
+    }
+
+    //routines by self cycles, the ones burning the time are on top
+    pub fn flat(&self) -> Vec<String> {
+        let total = self.total_cycles();
+        let mut routines: Vec<(&Routine, &Stats)> = self.stats.iter().collect();
+        routines.sort_by(|a, b| b.1.self_cycles.cmp(&a.1.self_cycles).then(a.0.cmp(b.0)));
+
+        let mut lines = vec![
+            format!(
+                "{} cycles over {} frames",
+                total,
+                self.last_frame.saturating_sub(self.first_frame) + 1
+            ),
+            format!(
+                "{:>7} {:>10} {:>10} {:>8} {:>7} {:>9} {:>9}  routine",
+                "self%", "self", "total", "calls", "frames", "min/frame", "max/frame"
+            ),
+        ];
+        for (&routine, stats) in routines {
+            let (min, max) = stats.frame_range();
+            lines.push(format!(
+                "{:>6.2}% {:>10} {:>10} {:>8} {:>7} {:>9} {:>9}  {}",
+                percent(stats.self_cycles, total),
+                stats.self_cycles,
+                stats.total_cycles,
+                stats.calls,
+                stats.frames,
+                min,
+                max,
+                self.name(routine)
+            ));
+        }
+        lines
+    }
+
+    //every routine by total cycles with who called it and what it called,
+    //the cycles on an arc are those spent in the callee when called from there
+    pub fn call_graph(&self) -> Vec<String> {
+        let total = self.total_cycles();
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|node| node.self_cycles).collect();
+        //children always come after their parent
+        for i in (1..self.nodes.len()).rev() {
+            if let Some(parent) = self.nodes[i].parent {
+                inclusive[parent] += inclusive[i];
+            }
+        }
+        let mut arcs: HashMap<(Routine, Routine), (u64, u64)> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let Some(parent) = node.parent {
+                let arc = arcs.entry((self.nodes[parent].routine, node.routine)).or_insert((0, 0));
+                arc.0 += node.calls;
+                arc.1 += inclusive[i];
+            }
+        }
+
+        let mut routines: Vec<(&Routine, &Stats)> = self.stats.iter().collect();
+        routines.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(b.0)));
+        let mut lines = Vec::new();
+        for (&routine, stats) in routines {
+            lines.push(format!(
+                "{}  total {} ({:.2}%), self {}, calls {}",
+                self.name(routine),
+                stats.total_cycles,
+                percent(stats.total_cycles, total),
+                stats.self_cycles,
+                stats.calls
+            ));
+            let mut callers: Vec<_> = arcs.iter().filter(|(arc, _)| arc.1 == routine).collect();
+            callers.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+            for ((caller, _), (calls, cycles)) in callers {
+                lines.push(format!("    <- {:<24} {:>8} calls {:>10} cycles", self.name(*caller), calls, cycles));
+            }
+            let mut callees: Vec<_> = arcs.iter().filter(|(arc, _)| arc.0 == routine).collect();
+            callees.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+            for ((_, callee), (calls, cycles)) in callees {
+                lines.push(format!("    -> {:<24} {:>8} calls {:>10} cycles", self.name(*callee), calls, cycles));
+            }
+        }
+        lines
+    }
+
+    //one line per call stack with the cycles spent at its top, the input
+    //flamegraph.pl and speedscope take
+    pub fn folded(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|node| node.self_cycles > 0)
+            .map(|node| {
+                let mut names = vec![self.name(node.routine)];
+                let mut parent = node.parent;
+                while let Some(i) = parent {
+                    names.push(self.name(self.nodes[i].routine));
+                    parent = self.nodes[i].parent;
+                }
+                names.reverse();
+                format!("{} {}", names.join(";"), node.self_cycles)
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::CpuVariant;
+
+    #[test]
+    fn interrupt_entry_is_charged_to_the_handler_only() {
+        let program = asm!(
+            0x0600,
+            "ldx #$00",
+            "loop: inx",
+            "jmp loop",
+            ".org $0700",
+            "handler: rti",
+            ".org $fffa",
+            ".word handler"
+        );
+        //both cores, the cycle-stepped one runs the interrupt with the instruction before it
+        for variant in [CpuVariant::Ricoh2A03, CpuVariant::Cmos65C02] {
+            let mut cpu = Cpu::default();
+            cpu.set_variant(variant);
+            program.write_to(cpu.bus_mut());
+            cpu.state_mut().set_pc(0x0600);
+            cpu.state_mut().set_sp(0xfd);
+            cpu.set_profiler(Some(Profiler::new()));
+            while cpu.cycles() < 40 {
+                cpu.tick().unwrap();
+            }
+            cpu.trigger_nmi();
+            while cpu.cycles() < 100 {
+                cpu.tick().unwrap();
+            }
+
+            let cycles = cpu.cycles();
+            let profiler = cpu.profiler_mut().unwrap();
+            profiler.finish(cycles);
+            //7 to enter the handler, 6 for rti
+            assert_eq!(profiler.folded(), vec![format!("0600 {}", cycles - 13), "0600;0700 13".to_string()]);
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use crate::cartridge::{HEADER_SIZE, PRG_BANK_SIZE};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+//labels and source lines from ca65 .dbg files, fceux .nl namelists and mesen
+//.mlb label files. anything in prg-rom is keyed by its rom offset so the same
+//address gets a different name in every bank, ram and registers by cpu address
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    Cpu(u16),   //ram, registers, anything not banked
+    Prg(usize), //offset into prg-rom
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    UnknownFormat(String), //file name that isn't .dbg, .nl or .mlb
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(e) => write!(f, "i/o error: {}", e),
+            SymbolError::UnknownFormat(name) => {
+                write!(f, "{}: expected a .dbg, .nl or .mlb file", name)
+            }
+            SymbolError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(e: io::Error) -> Self {
+        SymbolError::Io(e)
+    }
+}
+
+fn parse_error<T>(line: usize, message: String) -> Result<T, SymbolError> {
+    Err(SymbolError::Parse { line, message })
+}
+
+//"$C000", "C000" or "0x00C000"
+fn parse_hex(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+//a .dbg line: "sym\tid=0,name=\"reset\",val=0x8000,..." into its key/value pairs
+fn dbg_fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (key, after) = match rest.split_once('=') {
+            Some(pair) => pair,
+            None => break,
+        };
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => after.split_once(',').map_or((after, ""), |(v, a)| (v, a)),
+        };
+        fields.insert(key.trim(), value);
+        rest = after.trim_start_matches(',');
+    }
+    fields
+}
+
+fn dbg_number(fields: &HashMap<&str, &str>, key: &str) -> Option<usize> {
+    let value = fields.get(key)?;
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+//segment of a .dbg file: where it runs and where it sits in the .nes file
+struct Segment {
+    start: usize,
+    rom: Option<usize>, //prg-rom offset of the first byte
+}
+
+impl Segment {
+    fn location(&self, offset: usize) -> Location {
+        match self.rom {
+            Some(rom) => Location::Prg(rom + offset),
+            None => Location::Cpu((self.start + offset) as u16),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    labels: HashMap<Location, String>,
+    locations: HashMap<String, Location>,
+    addresses: HashMap<String, u16>, //cpu address the file gave for a rom label
+    lines: HashMap<Location, SourceLine>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    //picks the format from the file name. fceux namelists are named
+    //rom.nes.ram.nl for ram and rom.nes.N.nl for 16 KiB prg bank N
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SymbolError> {
+        let path = path.as_ref();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("dbg") => self.load_dbg(&text),
+            Some("mlb") => self.load_mlb(&text),
+            Some("nl") => {
+                let stem = name.trim_end_matches(".nl");
+                let bank = stem.rsplit('.').next().and_then(|b| b.parse::<usize>().ok());
+                self.load_nl(&text, bank)
+            }
+            _ => Err(SymbolError::UnknownFormat(name)),
+        }
+    }
+
+    pub fn add(&mut self, location: Location, name: &str) {
+        self.labels.entry(location).or_insert_with(|| name.to_string());
+        self.locations.entry(name.to_string()).or_insert(location);
+    }
+
+    //fceux namelist: "$C000#Reset#comment", "$0300/10#buffer#" names 16 bytes.
+    //bank is None for the ram file, cartridge addresses then use the cpu address
+    pub fn load_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), SymbolError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(3, '#');
+            let addr = parts.next().unwrap_or("");
+            let name = parts.next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+            let (addr, len) = match addr.split_once('/') {
+                Some((addr, len)) => (addr, parse_hex(len)),
+                None => (addr, Some(1)),
+            };
+            let (addr, len) = match (parse_hex(addr), len) {
+                (Some(addr), Some(len)) if addr <= 0xffff => (addr as u16, len.max(1)),
+                _ => return parse_error(i + 1, format!("bad address '{}'", line)),
+            };
+            for n in 0..len {
+                let addr = addr.wrapping_add(n as u16);
+                let location = match bank {
+                    Some(bank) if addr >= 0x8000 => {
+                        Location::Prg(bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE))
+                    }
+                    _ => Location::Cpu(addr),
+                };
+                if n == 0 {
+                    self.addresses.entry(name.to_string()).or_insert(addr);
+                }
+                self.add_element(location, name, n);
+            }
+        }
+        Ok(())
+    }
+
+    //mesen label file: "P:1234:Label:comment", "R:0300-030F:buffer". mesen 2
+    //spells the types out (NesPrgRom, NesInternalRam, ...)
+    pub fn load_mlb(&mut self, text: &str) -> Result<(), SymbolError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(4, ':');
+            let (kind, range, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kind), Some(range), Some(name)) => (kind, range, name.trim()),
+                _ => return parse_error(i + 1, format!("expected TYPE:ADDR:LABEL, got '{}'", line)),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (parse_hex(start), parse_hex(end)),
+                None => (parse_hex(range), parse_hex(range)),
+            };
+            let (start, end) = match (start, end) {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                _ => return parse_error(i + 1, format!("bad address '{}'", range)),
+            };
+            for (n, value) in (start..=end).enumerate() {
+                let location = match kind {
+                    "P" | "NesPrgRom" => Location::Prg(value),
+                    "R" | "NesInternalRam" => Location::Cpu(value as u16 & 0x7ff),
+                    "S" | "W" | "NesSaveRam" | "NesWorkRam" => Location::Cpu(0x6000 + (value as u16 & 0x1fff)),
+                    "G" | "NesMemory" | "Register" => Location::Cpu(value as u16),
+                    _ => break, //chr, palette and the like have no cpu address
+                };
+                self.add_element(location, name, n);
+            }
+        }
+        Ok(())
+    }
+
+    //ca65/ld65 debug info (ld65 --dbgfile): lab symbols and line spans. segments
+    //written to the .nes file are placed by their file offset minus the header
+    pub fn load_dbg(&mut self, text: &str) -> Result<(), SymbolError> {
+        let mut segments = HashMap::new();
+        let mut files = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut syms = Vec::new();
+        let mut lines = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let (kind, rest) = match line.split_once(char::is_whitespace) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let fields = dbg_fields(rest.trim());
+            let id = dbg_number(&fields, "id");
+            match kind {
+                "seg" => {
+                    let start = dbg_number(&fields, "start");
+                    let (id, start) = match (id, start) {
+                        (Some(id), Some(start)) => (id, start),
+                        _ => return parse_error(i + 1, "seg without id or start".to_string()),
+                    };
+                    let rom = dbg_number(&fields, "ooffs")
+                        .filter(|offset| *offset >= HEADER_SIZE)
+                        .map(|offset| offset - HEADER_SIZE);
+                    segments.insert(id, Segment { start, rom });
+                }
+                "file" => {
+                    if let (Some(id), Some(name)) = (id, fields.get("name")) {
+                        files.insert(id, name.to_string());
+                    }
+                }
+                "span" => {
+                    let seg = dbg_number(&fields, "seg");
+                    let start = dbg_number(&fields, "start");
+                    if let (Some(id), Some(seg), Some(start)) = (id, seg, start) {
+                        spans.insert(id, (seg, start));
+                    }
+                }
+                //equates are left out, they would name every small number
+                "sym" if fields.get("type") == Some(&"lab") => {
+                    let name = fields.get("name").map(|n| n.to_string());
+                    let val = dbg_number(&fields, "val");
+                    let seg = dbg_number(&fields, "seg");
+                    if let (Some(name), Some(val)) = (name, val) {
+                        syms.push((name, val, seg));
+                    }
+                }
+                "line" => {
+                    //type 2 lines are macro expansions, the line using the macro is more useful
+                    if dbg_number(&fields, "type") == Some(2) {
+                        continue;
+                    }
+                    let file = dbg_number(&fields, "file");
+                    let number = dbg_number(&fields, "line");
+                    let span = fields.get("span").copied().unwrap_or("");
+                    if let (Some(file), Some(number)) = (file, number) {
+                        for span in span.split('+').filter_map(|s| s.parse::<usize>().ok()) {
+                            lines.push((file, number as u32, span));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for (name, val, seg) in syms {
+            let location = match seg.and_then(|seg| segments.get(&seg)) {
+                Some(segment) if val >= segment.start => segment.location(val - segment.start),
+                _ => Location::Cpu(val as u16),
+            };
+            self.addresses.entry(name.clone()).or_insert(val as u16);
+            self.add(location, &name);
+        }
+        for (file, number, span) in lines {
+            let (segment, offset) = match spans.get(&span).and_then(|(seg, start)| Some((segments.get(seg)?, *start))) {
+                Some(found) => found,
+                None => continue,
+            };
+            let file = match files.get(&file) {
+                Some(name) => name.clone(),
+                None => continue,
+            };
+            self.lines
+                .entry(segment.location(offset))
+                .or_insert(SourceLine { file, line: number });
+        }
+        Ok(())
+    }
+
+    //names a label that covers several bytes as name+1, name+2...
+    fn add_element(&mut self, location: Location, name: &str, n: usize) {
+        if n == 0 {
+            self.add(location, name);
+        } else {
+            self.labels.entry(location).or_insert_with(|| format!("{}+{}", name, n));
+        }
+    }
+
+    //label of the byte at addr, prg_offset is where the bus maps it in prg-rom
+    pub fn label(&self, addr: u16, prg_offset: Option<usize>) -> Option<&str> {
+        prg_offset
+            .and_then(|offset| self.labels.get(&Location::Prg(offset)))
+            .or_else(|| self.labels.get(&Location::Cpu(addr)))
+            .map(|name| name.as_str())
+    }
+
+    //label lookup for Line::text_with
+    pub fn namer<'a>(&'a self, prg_offset: &'a dyn Fn(u16) -> Option<usize>) -> impl Fn(u16) -> Option<String> + 'a {
+        move |addr| self.label(addr, prg_offset(addr)).map(|name| name.to_string())
+    }
+
+    pub fn source_line(&self, addr: u16, prg_offset: Option<usize>) -> Option<&SourceLine> {
+        prg_offset
+            .and_then(|offset| self.lines.get(&Location::Prg(offset)))
+            .or_else(|| self.lines.get(&Location::Cpu(addr)))
+    }
+
+    pub fn location(&self, name: &str) -> Option<Location> {
+        self.locations.get(name).copied()
+    }
+
+    //cpu address of a label under the current banking, prg_offset is the
+    //bus mapping. None if the bank holding it isn't mapped in. where rom is
+    //mirrored the address the file gave wins, otherwise the highest one
+    pub fn address(&self, name: &str, prg_offset: &dyn Fn(u16) -> Option<usize>) -> Option<u16> {
+        let offset = match self.location(name)? {
+            Location::Cpu(addr) => return Some(addr),
+            Location::Prg(offset) => Some(offset),
+        };
+        match self.addresses.get(name) {
+            Some(addr) if prg_offset(*addr) == offset => Some(*addr),
+            _ => (0x4020..=0xffff).rev().find(|addr| prg_offset(*addr) == offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_message(result: Result<(), SymbolError>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fceux_namelists() {
+        let mut symbols = Symbols::new();
+        symbols
+            .load_nl("$C000#Reset#entry point\n$8010/3#table#\n$0300#ram#\n\n$C008##\n", Some(1))
+            .unwrap();
+        symbols.load_nl("$0010#ptr#\n$2002#PPUSTATUS#", None).unwrap();
+
+        //bank 1 at $8000 or $c000, the offset is what counts
+        assert_eq!(symbols.label(0xc000, Some(PRG_BANK_SIZE)), Some("Reset"));
+        assert_eq!(symbols.label(0x8000, Some(PRG_BANK_SIZE)), Some("Reset"));
+        assert_eq!(symbols.label(0xc000, Some(0)), None);
+        assert_eq!(symbols.label(0x8012, Some(PRG_BANK_SIZE + 0x12)), Some("table+2"));
+        assert_eq!(symbols.location("ram"), Some(Location::Cpu(0x0300)));
+        assert_eq!(symbols.label(0x0010, None), Some("ptr"));
+        assert_eq!(symbols.label(0x2002, None), Some("PPUSTATUS"));
+
+        let prg_offset = |addr: u16| (addr >= 0xc000).then(|| PRG_BANK_SIZE + (addr as usize - 0xc000));
+        assert_eq!(symbols.address("Reset", &prg_offset), Some(0xc000));
+        assert_eq!(symbols.address("table", &prg_offset), Some(0xc010));
+
+        let result = Symbols::new().load_nl("$C000#ok#\nC0ZZ#bad#", Some(0));
+        assert_eq!(parse_message(result), "line 2: bad address 'C0ZZ#bad#'");
+    }
+
+    #[test]
+    fn mesen_labels() {
+        let mut symbols = Symbols::new();
+        let text = "P:4010:nmi:handler\nR:0300-0301:buffer\nR:0810:mirror\nS:0010:save\n\
+                    G:2002:PPUSTATUS\nNesPrgRom:0000:start\nC:0000:tiles\n";
+        symbols.load_mlb(text).unwrap();
+        assert_eq!(symbols.label(0xc010, Some(0x4010)), Some("nmi"));
+        assert_eq!(symbols.label(0x8000, Some(0)), Some("start"));
+        assert_eq!(symbols.label(0x0301, None), Some("buffer+1"));
+        assert_eq!(symbols.label(0x0010, None), Some("mirror")); //$0810 is $0010 in ram
+        assert_eq!(symbols.label(0x6010, None), Some("save"));
+        assert_eq!(symbols.label(0x2002, None), Some("PPUSTATUS"));
+        assert_eq!(symbols.location("tiles"), None);
+
+        let result = Symbols::new().load_mlb("P:0000:ok\njust text");
+        assert_eq!(parse_message(result), "line 2: expected TYPE:ADDR:LABEL, got 'just text'");
+        assert_eq!(parse_message(Symbols::new().load_mlb("R:0310-0300:backwards")), "line 1: bad address '0310-0300'");
+    }
+
+    const DBG: &str = r#"version	major=2,minor=0
+file	id=0,name="main.s",size=100,mtime=0x00000000,mod=0
+seg	id=0,name="CODE",start=0x008000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+seg	id=1,name="BSS",start=0x000300,size=0x0010,addrsize=absolute,type=rw
+span	id=0,seg=0,start=4,size=3
+line	id=0,file=0,line=12,span=0
+sym	id=0,name="reset",addrsize=absolute,scope=0,def=0,ref=1,val=0x8004,seg=0,type=lab
+sym	id=1,name="buffer",addrsize=absolute,scope=0,def=0,val=0x300,seg=1,type=lab
+sym	id=2,name="SIZE",addrsize=zeropage,scope=0,def=0,val=0x10,type=equ
+"#;
+
+    #[test]
+    fn ca65_debug_info() {
+        let mut symbols = Symbols::new();
+        symbols.load_dbg(DBG).unwrap();
+        //CODE sits right after the header, so it starts at prg-rom offset 0
+        assert_eq!(symbols.location("reset"), Some(Location::Prg(4)));
+        assert_eq!(symbols.label(0x8004, Some(4)), Some("reset"));
+        assert_eq!(symbols.label(0x8004, Some(PRG_BANK_SIZE + 4)), None);
+        assert_eq!(symbols.label(0x0300, None), Some("buffer"));
+        assert_eq!(symbols.location("SIZE"), None);
+        let line = symbols.source_line(0x8004, Some(4)).map(|l| l.to_string());
+        assert_eq!(line.as_deref(), Some("main.s:12"));
+        let prg_offset = |addr: u16| (addr >= 0x8000).then(|| addr as usize - 0x8000);
+        assert_eq!(symbols.address("reset", &prg_offset), Some(0x8004));
+
+        let broken = DBG.replace("start=0x000300,", "");
+        assert_eq!(parse_message(Symbols::new().load_dbg(&broken)), "line 4: seg without id or start");
+    }
+}
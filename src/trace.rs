@@ -0,0 +1,462 @@
+use crate::bus::Bus;
+use crate::cartridge::PRG_BANK_SIZE;
+use crate::cpu::{Cpu, CpuVariant};
+use crate::disasm;
+use crate::nes_bus::{frame, DOTS_PER_SCANLINE, PPU_DOTS_PER_CYCLE, SCANLINES_PER_FRAME};
+use crate::opcodes::{self, Instruction, Mode};
+use crate::symbols::Symbols;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+//nestest calls isc "isb"
+fn nestest_mnemonic(mnemonic: &str) -> &str {
+    match mnemonic {
+        "ISC" => "ISB",
+        m => m,
+    }
+}
+
+//operand as nestest.log prints it, with the effective address and the value
+//there before the instruction runs
+fn nestest_operand<B: Bus>(cpu: &Cpu<B>, pc: u16, instruction: Instruction, mode: Mode) -> String {
+    let peek = |addr: u16| cpu.bus().peek(addr).unwrap_or(0);
+    let peek16_zero = |zero: u8| peek(zero as u16) as u16 | (peek(zero.wrapping_add(1) as u16) as u16) << 8;
+    let regs = cpu.state();
+    let b1 = peek(pc.wrapping_add(1));
+    let word = b1 as u16 | (peek(pc.wrapping_add(2)) as u16) << 8;
+
+    match mode {
+        Mode::Implied => String::new(),
+        Mode::Accumulator => "A".to_string(),
+        Mode::Immediate => format!("#${:02X}", b1),
+        Mode::ZeroPage => format!("${:02X} = {:02X}", b1, peek(b1 as u16)),
+        Mode::ZeroPageX => {
+            let addr = b1.wrapping_add(regs.x());
+            format!("${:02X},X @ {:02X} = {:02X}", b1, addr, peek(addr as u16))
+        }
+        Mode::ZeroPageY => {
+            let addr = b1.wrapping_add(regs.y());
+            format!("${:02X},Y @ {:02X} = {:02X}", b1, addr, peek(addr as u16))
+        }
+        Mode::Absolute => match instruction {
+            Instruction::Jmp | Instruction::Jsr => format!("${:04X}", word),
+            _ => format!("${:04X} = {:02X}", word, peek(word)),
+        },
+        Mode::AbsoluteX => {
+            let addr = word.wrapping_add(regs.x() as u16);
+            format!("${:04X},X @ {:04X} = {:02X}", word, addr, peek(addr))
+        }
+        Mode::AbsoluteY => {
+            let addr = word.wrapping_add(regs.y() as u16);
+            format!("${:04X},Y @ {:04X} = {:02X}", word, addr, peek(addr))
+        }
+        Mode::Indirect => {
+            //the pointer high byte doesn't carry into the next page, except on the 65c02
+            let hi_addr = match cpu.variant() {
+                CpuVariant::Cmos65C02 => word.wrapping_add(1),
+                _ => (word & 0xff00) | (word.wrapping_add(1) & 0x00ff),
+            };
+            let target = peek(word) as u16 | (peek(hi_addr) as u16) << 8;
+            format!("(${:04X}) = {:04X}", word, target)
+        }
+        Mode::AbsoluteIndirectX => {
+            let ptr = word.wrapping_add(regs.x() as u16);
+            let target = peek(ptr) as u16 | (peek(ptr.wrapping_add(1)) as u16) << 8;
+            format!("(${:04X},X) @ {:04X} = {:04X}", word, ptr, target)
+        }
+        Mode::IndirectX => {
+            let zero = b1.wrapping_add(regs.x());
+            let addr = peek16_zero(zero);
+            format!("(${:02X},X) @ {:02X} = {:04X} = {:02X}", b1, zero, addr, peek(addr))
+        }
+        Mode::IndirectY => {
+            let base = peek16_zero(b1);
+            let addr = base.wrapping_add(regs.y() as u16);
+            format!("(${:02X}),Y = {:04X} @ {:04X} = {:02X}", b1, base, addr, peek(addr))
+        }
+        Mode::ZeroPageIndirect => {
+            let addr = peek16_zero(b1);
+            format!("(${:02X}) = {:04X} = {:02X}", b1, addr, peek(addr))
+        }
+        Mode::Relative => {
+            let target = pc.wrapping_add(2).wrapping_add(b1 as i8 as u16);
+            format!("${:04X}", target)
+        }
+        Mode::ZeroPageRelative => {
+            let offset = peek(pc.wrapping_add(2));
+            let target = pc.wrapping_add(3).wrapping_add(offset as i8 as u16);
+            format!("${:02X} = {:02X}, ${:04X}", b1, peek(b1 as u16), target)
+        }
+    }
+}
+
+//one line of nintendulator / nestest.log for the instruction about to run:
+//C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+pub fn nestest_line<B: Bus>(cpu: &Cpu<B>) -> String {
+    let regs = cpu.state();
+    let pc = regs.pc();
+    let opcode = cpu.bus().peek(pc).unwrap_or(0);
+    let info = opcodes::table(cpu.variant())[opcode as usize];
+
+    let bytes: Vec<String> = (0..info.mode.size() as u16)
+        .map(|i| format!("{:02X}", cpu.bus().peek(pc.wrapping_add(i)).unwrap_or(0)))
+        .collect();
+    let mnemonic = nestest_mnemonic(info.mnemonic());
+    let operand = nestest_operand(cpu, pc, info.instruction, info.mode);
+    let disasm = if operand.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operand)
+    };
+
+    let dots = cpu.cycles() * PPU_DOTS_PER_CYCLE;
+    let scanline = (dots / DOTS_PER_SCANLINE) % SCANLINES_PER_FRAME;
+    let dot = dots % DOTS_PER_SCANLINE;
+
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        pc,
+        bytes.join(" "),
+        if info.official { ' ' } else { '*' },
+        disasm,
+        regs.a(),
+        regs.x(),
+        regs.y(),
+        regs.p(),
+        regs.sp(),
+        scanline,
+        dot,
+        cpu.cycles()
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    Nestest,
+    Fceux,
+    Mesen,
+}
+
+//an instruction is logged only if it passes every filter that is set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub pc: Option<(u16, u16)>,     //inclusive range
+    pub bank: Option<usize>,        //16 KiB prg-rom bank the pc is in
+    pub frames: Option<(u64, u64)>, //inclusive range
+    pub start: Option<u16>,         //logging starts when pc gets here
+    pub stop: Option<u16>,          //and stops for good when it gets here
+}
+
+//writes executed instructions to a file (or anything else), or keeps the last ones
+//in a ring buffer to dump when something goes wrong. attach with Cpu::set_tracer
+pub struct Tracer {
+    format: TraceFormat,
+    filter: TraceFilter,
+    out: Box<dyn Write>,
+    ring: Option<(usize, VecDeque<String>)>,
+    started: bool,
+    stopped: bool,
+    error: Option<io::Error>,
+    symbols: Symbols,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer {
+            format,
+            filter: TraceFilter::default(),
+            out,
+            ring: None,
+            started: true,
+            stopped: false,
+            error: None,
+            symbols: Symbols::new(),
+        }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file)), format))
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.started = filter.start.is_none();
+        self.stopped = false;
+        self.filter = filter;
+    }
+
+    //labels for operands in the fceux and mesen formats, nestest stays as is
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    //keep only the last capacity lines in memory, nothing is written until dump.
+    //a capacity of 0 keeps nothing, dump then only writes the reason
+    pub fn set_ring_buffer(&mut self, capacity: usize) {
+        self.ring = Some((capacity, VecDeque::with_capacity(capacity)));
+    }
+
+    pub fn has_ring_buffer(&self) -> bool {
+        self.ring.is_some()
+    }
+
+    //first write error, the tracer goes quiet after it
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn passes<B: Bus>(&mut self, cpu: &Cpu<B>) -> bool {
+        let pc = cpu.state().pc();
+        let filter = self.filter;
+        if filter.stop == Some(pc) {
+            self.stopped = true;
+        }
+        if filter.start == Some(pc) {
+            self.started = true;
+        }
+        if !self.started || self.stopped {
+            return false;
+        }
+        if let Some((from, to)) = filter.pc {
+            if pc < from || pc > to {
+                return false;
+            }
+        }
+        if let Some(bank) = filter.bank {
+            let offset = cpu.bus().prg_offset(pc);
+            if offset.map(|o| o / PRG_BANK_SIZE) != Some(bank) {
+                return false;
+            }
+        }
+        if let Some((from, to)) = filter.frames {
+            let frame = frame(cpu.cycles());
+            if frame < from || frame > to {
+                return false;
+            }
+        }
+        true
+    }
+
+    //called by the cpu before each instruction
+    pub fn record<B: Bus>(&mut self, cpu: &Cpu<B>) {
+        if self.error.is_some() || !self.passes(cpu) {
+            return;
+        }
+        let line = match self.format {
+            TraceFormat::Nestest => nestest_line(cpu),
+            TraceFormat::Fceux => fceux_line(cpu, &self.symbols),
+            TraceFormat::Mesen => mesen_line(cpu, &self.symbols),
+        };
+        match &mut self.ring {
+            Some((capacity, lines)) => {
+                if *capacity == 0 {
+                    return;
+                }
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            None => self.write(&line),
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", line) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    //writes out the ring buffer after a crash, reason ends up on the last line
+    pub fn dump(&mut self, reason: &str) {
+        let lines = match &mut self.ring {
+            Some((_, lines)) => std::mem::take(lines),
+            None => VecDeque::new(),
+        };
+        for line in &lines {
+            self.write(line);
+        }
+        self.write(&format!("; {}", reason));
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.out.flush() {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+//NV-BDIZC with set flags in capitals, bit 5 shown as U
+pub fn flags(p: u8) -> String {
+    "nvubdizc"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if p & (0x80 >> i) != 0 { c.to_ascii_uppercase() } else { c })
+        .collect()
+}
+
+//disassembly with the mnemonic in capitals, labels for known addresses and
+//the effective address appended
+fn annotated<B: Bus>(
+    cpu: &Cpu<B>,
+    symbols: &Symbols,
+    indexed: impl Fn(u16, u8) -> String,
+    plain: impl Fn(u8) -> String,
+) -> (String, String) {
+    let line = match disasm::at_pc(cpu) {
+        Some(line) => line,
+        None => return (String::new(), String::new()),
+    };
+    let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let prg_offset = |addr| cpu.bus().prg_offset(addr);
+    let text = line.text_with(&symbols.namer(&prg_offset));
+    let mut text = match text.split_once(' ') {
+        Some((mnemonic, operand)) => format!("{} {}", mnemonic.to_ascii_uppercase(), operand),
+        None => text.to_ascii_uppercase(),
+    };
+    if let (Some((addr, value)), Some(opcode)) = (line.effective, line.opcode) {
+        let direct = matches!(opcode.mode, Mode::ZeroPage | Mode::Absolute);
+        text += &if direct { plain(value) } else { indexed(addr, value) };
+    }
+    (bytes.join(" "), text)
+}
+
+//A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000: 4C F5 C5  JMP $C5F5
+pub fn fceux_line<B: Bus>(cpu: &Cpu<B>, symbols: &Symbols) -> String {
+    let regs = cpu.state();
+    let (bytes, text) = annotated(
+        cpu,
+        symbols,
+        |addr, value| format!(" @ ${:04X} = #${:02X}", addr, value),
+        |value| format!(" = #${:02X}", value),
+    );
+    format!(
+        "A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}: {:<8}  {}",
+        regs.a(),
+        regs.x(),
+        regs.y(),
+        regs.sp(),
+        flags(regs.p()),
+        regs.pc(),
+        bytes,
+        text
+    )
+}
+
+//C000  4C F5 C5  JMP $C5F5                     A:00 X:00 Y:00 S:FD P:nvUbdIzc Cycle:21 SL:0 FC:0 CPU Cycle:7
+pub fn mesen_line<B: Bus>(cpu: &Cpu<B>, symbols: &Symbols) -> String {
+    let regs = cpu.state();
+    let (bytes, text) = annotated(
+        cpu,
+        symbols,
+        |addr, value| format!(" [${:04X}] = ${:02X}", addr, value),
+        |value| format!(" = ${:02X}", value),
+    );
+    let dots = cpu.cycles() * PPU_DOTS_PER_CYCLE;
+    format!(
+        "{:04X}  {:<8}  {:<30}A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} Cycle:{} SL:{} FC:{} CPU Cycle:{}",
+        regs.pc(),
+        bytes,
+        text,
+        regs.a(),
+        regs.x(),
+        regs.y(),
+        regs.sp(),
+        flags(regs.p()),
+        dots % DOTS_PER_SCANLINE,
+        (dots / DOTS_PER_SCANLINE) % SCANLINES_PER_FRAME,
+        frame(cpu.cycles()),
+        cpu.cycles()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::memory::Memory;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    //a Write the test can still read after handing it to the tracer
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8_lossy(&self.0.borrow()).lines().map(str::to_string).collect()
+        }
+    }
+
+    fn traced_cpu(capacity: usize) -> (Cpu<Memory>, Shared) {
+        let program = asm!(0x0600, "lda #$01", "ldx #$02", "ldy #$03", ".byte $02");
+        let mut cpu = Cpu::default();
+        program.write_to(cpu.bus_mut());
+        cpu.state_mut().set_pc(0x0600);
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()), TraceFormat::Nestest);
+        tracer.set_ring_buffer(capacity);
+        cpu.set_tracer(Some(tracer));
+        (cpu, out)
+    }
+
+    #[test]
+    fn ring_buffer_is_written_when_the_run_stops() {
+        //a clean run only writes when told to
+        let (mut cpu, out) = traced_cpu(2);
+        for _ in 0..3 {
+            cpu.next_instruction().unwrap();
+        }
+        assert!(out.lines().is_empty());
+        cpu.tracer_mut().unwrap().dump("instruction limit reached");
+        let lines = out.lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0602  A2 02"));
+        assert!(lines[1].starts_with("0604  A0 03"));
+        assert_eq!(lines[2], "; instruction limit reached");
+
+        //an illegal opcode dumps the ring itself, its own line included
+        let (mut cpu, out) = traced_cpu(2);
+        for _ in 0..3 {
+            cpu.next_instruction().unwrap();
+        }
+        let error = cpu.next_instruction().unwrap_err();
+        let lines = out.lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0604  A0 03"));
+        assert!(lines[1].starts_with("0606  02"));
+        assert_eq!(lines[2], format!("; {}", error));
+    }
+
+    #[test]
+    fn empty_ring_buffer_keeps_nothing() {
+        let (mut cpu, out) = traced_cpu(0);
+        for _ in 0..3 {
+            cpu.next_instruction().unwrap();
+        }
+        cpu.tracer_mut().unwrap().dump("instruction limit reached");
+        assert_eq!(out.lines(), vec!["; instruction limit reached"]);
+    }
+}